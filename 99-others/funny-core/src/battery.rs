@@ -0,0 +1,201 @@
+// battery / supply voltage
+//
+// pack voltage through a resistor divider on an adc pin:
+//   pack+ --- R1 --- adc --- R2 --- gnd,   divider = (R1 + R2) / R2
+// state of charge comes from a resting voltage curve per cell, so it reads low under load
+// and is only a rough estimate, good enough to know when to recharge.
+//
+// the board calls `init` once with its `battery_*` config keys, owns the adc pin & driver
+// and hands them to `measure`. `last` works from anywhere after that.
+
+use std::borrow::Borrow;
+use std::sync::atomic::{AtomicU32, Ordering};
+use std::sync::OnceLock;
+
+use anyhow::{bail, Result};
+use esp_idf_svc::hal::adc::oneshot::{AdcChannelDriver, AdcDriver};
+use esp_idf_svc::hal::gpio::ADCPin;
+use esp_idf_svc::sys::EspError;
+use log::{info, warn};
+
+// reads averaged per measurement
+const SAMPLES: u32 = 8;
+
+// (cell mV, %), resting voltage, high to low
+const LI_ION_CURVE: &[(u32, u32)] = &[
+    (4200, 100),
+    (4150, 95),
+    (4110, 90),
+    (4080, 85),
+    (4020, 80),
+    (3980, 75),
+    (3950, 70),
+    (3910, 65),
+    (3870, 60),
+    (3850, 55),
+    (3840, 50),
+    (3820, 45),
+    (3800, 40),
+    (3790, 35),
+    (3770, 30),
+    (3750, 25),
+    (3730, 20),
+    (3710, 15),
+    (3690, 10),
+    (3610, 5),
+    (3270, 0),
+];
+// very flat in the middle, the percentage jumps around there
+const LIFEPO4_CURVE: &[(u32, u32)] = &[
+    (3400, 100),
+    (3350, 99),
+    (3320, 90),
+    (3300, 70),
+    (3270, 40),
+    (3260, 30),
+    (3250, 20),
+    (3220, 17),
+    (3200, 14),
+    (3000, 9),
+    (2500, 0),
+];
+
+// pack mV of the last measurement, 0 = not measured yet
+static LAST_MV: AtomicU32 = AtomicU32::new(0);
+// the board's pack, unset = monitoring off
+static BATTERY: OnceLock<Battery> = OnceLock::new();
+
+#[derive(Clone, Copy, Debug, PartialEq)]
+pub enum Chemistry {
+    LiIon,
+    LiFePo4,
+}
+
+impl Chemistry {
+    // `battery_chemistry`, empty disables battery monitoring
+    pub fn from_config(name: &str) -> Option<Self> {
+        match name.to_ascii_lowercase().as_str() {
+            "" => None,
+            "li-ion" | "liion" | "lipo" => Some(Self::LiIon),
+            "lifepo4" => Some(Self::LiFePo4),
+            other => {
+                warn!("unknown battery_chemistry {}, battery monitoring disabled", other);
+                None
+            }
+        }
+    }
+
+    fn curve(&self) -> &'static [(u32, u32)] {
+        match self {
+            Self::LiIon => LI_ION_CURVE,
+            Self::LiFePo4 => LIFEPO4_CURVE,
+        }
+    }
+}
+
+#[derive(Clone, Copy, Debug)]
+pub struct Reading {
+    pub voltage_mv: u32,
+    // state of charge, %
+    pub soc: u32,
+}
+
+impl Reading {
+    // V with 2 decimals, for telemetry
+    pub fn volts(&self) -> f32 {
+        (self.voltage_mv / 10) as f32 / 100.0
+    }
+}
+
+// the pack a board runs from, filled from its `battery_*` config keys
+#[derive(Clone, Copy, Debug)]
+pub struct Battery {
+    pub chemistry: Chemistry,
+    pub cells: u32,
+    pub divider: f32,
+}
+
+impl Battery {
+    // None when `battery_chemistry` is empty or unknown
+    pub fn from_config(chemistry: &str, cells: u32, divider: f32) -> Option<Self> {
+        Some(Self {
+            chemistry: Chemistry::from_config(chemistry)?,
+            cells: cells.max(1),
+            divider,
+        })
+    }
+
+    // average a few calibrated reads (mV at the pin), scale up by the divider
+    pub fn measure(&self, mut read_mv: impl FnMut() -> Result<u16, EspError>) -> Result<Reading> {
+        let mut sum = 0;
+        for _ in 0..SAMPLES {
+            sum += read_mv()? as u32;
+        }
+        let adc_mv = sum / SAMPLES;
+        let voltage_mv = (adc_mv as f32 * self.divider) as u32;
+        LAST_MV.store(voltage_mv, Ordering::Relaxed);
+
+        let reading = self.reading(voltage_mv);
+        info!("battery {}mV {}%", reading.voltage_mv, reading.soc);
+        Ok(reading)
+    }
+
+    // last measurement, None if not measured yet
+    pub fn last(&self) -> Option<Reading> {
+        match LAST_MV.load(Ordering::Relaxed) {
+            0 => None,
+            voltage_mv => Some(self.reading(voltage_mv)),
+        }
+    }
+
+    fn reading(&self, voltage_mv: u32) -> Reading {
+        Reading {
+            voltage_mv,
+            soc: state_of_charge(self.chemistry, voltage_mv / self.cells),
+        }
+    }
+}
+
+// linear between the curve points
+fn state_of_charge(chemistry: Chemistry, cell_mv: u32) -> u32 {
+    let curve = chemistry.curve();
+    if cell_mv >= curve[0].0 {
+        return 100;
+    }
+    for pair in curve.windows(2) {
+        let (high_mv, high_soc) = pair[0];
+        let (low_mv, low_soc) = pair[1];
+        if cell_mv >= low_mv {
+            return low_soc + (cell_mv - low_mv) * (high_soc - low_soc) / (high_mv - low_mv);
+        }
+    }
+    0
+}
+
+// at boot, from the board's config. None when `chemistry` is empty or unknown, monitoring
+// stays off then
+pub fn init(chemistry: &str, cells: u32, divider: f32) -> Option<Battery> {
+    let battery = Battery::from_config(chemistry, cells, divider)?;
+    Some(*BATTERY.get_or_init(|| battery))
+}
+
+// None when battery monitoring is off
+pub fn battery() -> Option<Battery> {
+    BATTERY.get().copied()
+}
+
+pub fn measure<'d, T, M>(adc: &AdcDriver<'d, T::Adc>, channel: &mut AdcChannelDriver<'d, T, M>) -> Result<Reading>
+where
+    T: ADCPin,
+    M: Borrow<AdcDriver<'d, T::Adc>>,
+{
+    let Some(battery) = battery() else {
+        bail!("battery monitoring disabled");
+    };
+    battery.measure(|| adc.read(channel))
+}
+
+// last measurement, None if disabled or not measured yet
+pub fn last() -> Option<Reading> {
+    battery()?.last()
+}
@@ -0,0 +1,94 @@
+// typed settings for the shared parts
+//
+// `toml_cfg` only reads the section of the crate it is used in, so every board keeps its
+// own Config and hands the shared keys over here. the pumper may take them from nvs instead.
+
+#[cfg(feature = "mqtt")]
+use core::time::Duration;
+
+#[cfg(feature = "mqtt")]
+use esp_idf_svc::mqtt::client::MqttProtocolVersion;
+#[cfg(feature = "mqtt")]
+use log::warn;
+
+use crate::wifi::{self, KnownNetwork};
+
+// `mqtt_protocol`
+#[cfg(feature = "mqtt")]
+#[derive(Clone, Copy, Debug, Default, PartialEq)]
+pub enum Protocol {
+    // ThingsCloud does not speak mqtt 5
+    #[default]
+    V3_1_1,
+    V5,
+}
+
+#[cfg(feature = "mqtt")]
+impl Protocol {
+    // "3.1.1" or "5"
+    pub fn parse(protocol: &str) -> Self {
+        match protocol {
+            "5" | "5.0" => Protocol::V5,
+            "3.1.1" | "" => Protocol::V3_1_1,
+            other => {
+                warn!("unknown mqtt_protocol:{}, fallback to 3.1.1", other);
+                Protocol::V3_1_1
+            }
+        }
+    }
+
+    pub fn is_v5(self) -> bool {
+        self == Protocol::V5
+    }
+
+    pub fn version(self) -> MqttProtocolVersion {
+        match self {
+            Protocol::V3_1_1 => MqttProtocolVersion::V3_1_1,
+            Protocol::V5 => MqttProtocolVersion::V5,
+        }
+    }
+}
+
+// `wifi_ssid`, `wifi_psk` & `wifi_networks`
+#[derive(Clone, Copy, Debug)]
+pub struct WifiConfig<'a> {
+    pub ssid: &'a str,
+    pub psk: &'a str,
+    // "ssid|psk;ssid|psk", the strongest visible one is used
+    pub networks: &'a str,
+}
+
+impl WifiConfig<'_> {
+    pub fn known_networks(&self) -> Vec<KnownNetwork> {
+        wifi::known_networks(
+            KnownNetwork {
+                ssid: self.ssid.to_string(),
+                psk: self.psk.to_string(),
+            },
+            self.networks,
+        )
+    }
+}
+
+// one broker, `mqtt_host`, `mqtt_user`, ..
+#[cfg(feature = "mqtt")]
+#[derive(Clone, Copy, Debug)]
+pub struct MqttConfig<'a> {
+    pub url: &'a str,
+    pub user: &'a str,
+    pub pass: &'a str,
+    pub client_id: &'a str,
+    pub protocol: Protocol,
+    // retained "online" birth & "offline" last will, empty to disable
+    pub availability_topic: &'a str,
+    // None for the esp-mqtt default
+    pub network_timeout: Option<Duration>,
+}
+
+#[cfg(feature = "mqtt")]
+impl MqttConfig<'_> {
+    // url without scheme, safe to report in telemetry
+    pub fn host(&self) -> &str {
+        self.url.split("://").last().unwrap_or(self.url)
+    }
+}
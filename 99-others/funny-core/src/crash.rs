@@ -0,0 +1,201 @@
+// crash reports
+//
+// the panic hook writes message & location to rtc memory that is never initialized, it
+// survives the reset after the panic (no flash writes while panicking). the boot count is
+// in nvs. every boot that is not a deep sleep wake-up makes a report, published once mqtt
+// is up:
+//
+//   {"reset_reason":"panic","boot_count":17,"version":"0.1.0",
+//    "panic":"src/main.rs:221:5: called `Result::unwrap()` on an `Err` value: .."}
+//
+// reset reasons: poweron, external, software, panic, interrupt_watchdog, task_watchdog,
+// watchdog, brownout, deepsleep, sdio, unknown
+
+use core::fmt::{self, Write};
+use core::ptr::addr_of_mut;
+
+use anyhow::Result;
+#[cfg(feature = "mqtt")]
+use esp_idf_svc::mqtt::client::{EspMqttClient, QoS};
+use esp_idf_svc::nvs::{EspDefaultNvsPartition, EspNvs};
+use esp_idf_svc::sys;
+#[cfg(feature = "mqtt")]
+use log::error;
+use log::{info, warn};
+use serde::Serialize;
+#[cfg(feature = "mqtt")]
+use serde_json::json;
+
+const NAMESPACE: &str = "crash";
+const KEY_BOOTS: &str = "boots";
+const MAX_MESSAGE_LEN: usize = 200;
+// anything else in there is garbage from a power on
+const MAGIC: u32 = 0x5061_6e63;
+
+struct PanicRecord {
+    magic: u32,
+    len: u16,
+    message: [u8; MAX_MESSAGE_LEN],
+}
+
+#[link_section = ".rtc_noinit"]
+static mut PANIC_RECORD: PanicRecord = PanicRecord {
+    magic: 0,
+    len: 0,
+    message: [0; MAX_MESSAGE_LEN],
+};
+
+#[derive(Debug, Serialize)]
+pub struct CrashReport {
+    pub reset_reason: &'static str,
+    pub boot_count: u32,
+    pub version: &'static str,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub panic: Option<String>,
+}
+
+// right after the logger, before anything can panic, Device::take does it
+pub fn install_panic_hook() {
+    let default_hook = std::panic::take_hook();
+    std::panic::set_hook(Box::new(move |info| {
+        let mut message = FixedBuf::default();
+        match info.location() {
+            Some(location) => {
+                let _ = write!(message, "{}:{}:{}: {}", location.file(), location.line(), location.column(), payload(info));
+            }
+            None => {
+                let _ = write!(message, "{}", payload(info));
+            }
+        }
+        message.save();
+        default_hook(info);
+    }));
+}
+
+// a task returned an error, kept like a panic message, the reset follows
+pub fn record_error(e: &anyhow::Error) {
+    let mut message = FixedBuf::default();
+    let _ = write!(message, "error: {:#}", e);
+    message.save();
+}
+
+// a supervisor is about to reset the board, kept like a panic message
+pub fn record_message(message: &str) {
+    let mut buf = FixedBuf::default();
+    let _ = buf.write_str(message);
+    buf.save();
+}
+
+// counts the boot, the report for it, None after a deep sleep wake-up
+// `version` is the board's firmware version
+pub fn boot(nvs: &EspDefaultNvsPartition, version: &'static str) -> Result<Option<CrashReport>> {
+    let reason = unsafe { sys::esp_reset_reason() };
+    if reason == sys::esp_reset_reason_t_ESP_RST_DEEPSLEEP {
+        return Ok(None);
+    }
+
+    let mut store = EspNvs::new(nvs.clone(), NAMESPACE, true)?;
+    let boot_count = store.get_u32(KEY_BOOTS)?.unwrap_or(0) + 1;
+    store.set_u32(KEY_BOOTS, boot_count)?;
+
+    let panic = take_panic_message();
+    let report = CrashReport {
+        reset_reason: reset_reason_name(reason),
+        boot_count,
+        version,
+        panic,
+    };
+    match report.panic {
+        Some(ref panic) => warn!("boot {}, reset by {}, last panic: {}", boot_count, report.reset_reason, panic),
+        None => info!("boot {}, reset by {}", boot_count, report.reset_reason),
+    }
+    Ok(Some(report))
+}
+
+// to `topic` as {"crash_report":{..}}, once mqtt is up
+#[cfg(feature = "mqtt")]
+pub fn publish(client: &mut EspMqttClient<'static>, topic: &str, report: &CrashReport) {
+    let payload = json!({ "crash_report": report }).to_string();
+    match client.enqueue(topic, QoS::AtLeastOnce, false, payload.as_bytes()) {
+        Ok(_) => info!("crash report:{}", payload),
+        Err(e) => error!("send crash report error:{}", e),
+    }
+}
+
+fn take_panic_message() -> Option<String> {
+    let record = unsafe { &mut *addr_of_mut!(PANIC_RECORD) };
+    if record.magic != MAGIC {
+        return None;
+    }
+    record.magic = 0;
+    let len = (record.len as usize).min(MAX_MESSAGE_LEN);
+    Some(String::from_utf8_lossy(&record.message[..len]).into_owned())
+}
+
+fn reset_reason_name(reason: sys::esp_reset_reason_t) -> &'static str {
+    match reason {
+        sys::esp_reset_reason_t_ESP_RST_POWERON => "poweron",
+        sys::esp_reset_reason_t_ESP_RST_EXT => "external",
+        sys::esp_reset_reason_t_ESP_RST_SW => "software",
+        sys::esp_reset_reason_t_ESP_RST_PANIC => "panic",
+        sys::esp_reset_reason_t_ESP_RST_INT_WDT => "interrupt_watchdog",
+        sys::esp_reset_reason_t_ESP_RST_TASK_WDT => "task_watchdog",
+        sys::esp_reset_reason_t_ESP_RST_WDT => "watchdog",
+        sys::esp_reset_reason_t_ESP_RST_BROWNOUT => "brownout",
+        sys::esp_reset_reason_t_ESP_RST_DEEPSLEEP => "deepsleep",
+        sys::esp_reset_reason_t_ESP_RST_SDIO => "sdio",
+        _ => "unknown",
+    }
+}
+
+fn payload<'a>(info: &'a std::panic::PanicHookInfo) -> &'a str {
+    if let Some(message) = info.payload().downcast_ref::<&str>() {
+        message
+    } else if let Some(message) = info.payload().downcast_ref::<String>() {
+        message
+    } else {
+        "panic"
+    }
+}
+
+// no allocation, the heap may be what failed
+struct FixedBuf {
+    buf: [u8; MAX_MESSAGE_LEN],
+    len: usize,
+}
+
+impl Default for FixedBuf {
+    fn default() -> Self {
+        Self {
+            buf: [0; MAX_MESSAGE_LEN],
+            len: 0,
+        }
+    }
+}
+
+impl Write for FixedBuf {
+    fn write_str(&mut self, s: &str) -> fmt::Result {
+        // cut on a char boundary, the rest is lost
+        for c in s.chars() {
+            let mut utf8 = [0u8; 4];
+            let bytes = c.encode_utf8(&mut utf8).as_bytes();
+            if self.len + bytes.len() > MAX_MESSAGE_LEN {
+                return Err(fmt::Error);
+            }
+            self.buf[self.len..self.len + bytes.len()].copy_from_slice(bytes);
+            self.len += bytes.len();
+        }
+        Ok(())
+    }
+}
+
+impl FixedBuf {
+    fn save(&self) {
+        unsafe {
+            let record = &mut *addr_of_mut!(PANIC_RECORD);
+            record.message = self.buf;
+            record.len = self.len as u16;
+            record.magic = MAGIC;
+        }
+    }
+}
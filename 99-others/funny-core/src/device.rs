@@ -0,0 +1,51 @@
+// board bring-up, the first thing in every main
+//
+//   let Device { sysloop, nvs, peripherals } = Device::take()?;
+//   let wifi_driver = wifi::driver(peripherals.modem, &sysloop, &nvs)?;
+//
+// then WifiManager::start & mqtt::connect once the board knows its settings
+
+use anyhow::Result;
+use esp_idf_svc::eventloop::EspSystemEventLoop;
+use esp_idf_svc::hal::prelude::Peripherals;
+use esp_idf_svc::log::EspLogger;
+use esp_idf_svc::nvs::EspDefaultNvsPartition;
+use esp_idf_svc::sys::{self, esp};
+
+use crate::crash;
+
+pub struct Device {
+    pub sysloop: EspSystemEventLoop,
+    pub nvs: EspDefaultNvsPartition,
+    pub peripherals: Peripherals,
+}
+
+impl Device {
+    // with the plain esp-idf logger
+    pub fn take() -> Result<Self> {
+        Self::take_with_logger(EspLogger::initialize_default)
+    }
+
+    // `init_logger` binds the log crate, for boards with their own logger (remote log, ..)
+    pub fn take_with_logger(init_logger: impl FnOnce()) -> Result<Self> {
+        // It is necessary to call this function once. Otherwise some patches to the runtime
+        // implemented by esp-idf-sys might not link properly. See https://github.com/esp-rs/esp-idf-template/issues/71
+        esp_idf_svc::sys::link_patches();
+
+        init_logger();
+        crash::install_panic_hook();
+
+        Ok(Self {
+            sysloop: EspSystemEventLoop::take()?,
+            nvs: EspDefaultNvsPartition::take()?,
+            peripherals: Peripherals::take()?,
+        })
+    }
+}
+
+// last 2 bytes of the mac, to tell boards apart in hostnames, ap & ble names
+pub fn device_suffix() -> Result<String> {
+    let mut mac = [0u8; 6];
+    esp!(unsafe { sys::esp_read_mac(mac.as_mut_ptr(), sys::esp_mac_type_t_ESP_MAC_WIFI_STA) })?;
+    Ok(format!("{:02x}{:02x}", mac[4], mac[5]))
+}
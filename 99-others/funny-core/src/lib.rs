@@ -0,0 +1,47 @@
+// what every funny board does the same way
+//
+//   device     bring-up: link patches, logger, panic hook, sysloop, nvs, peripherals
+//   config     typed wifi & mqtt settings, filled from the board's cfg.toml or nvs
+//   wifi       connection manager, known networks, backoff, deep sleep ap hint
+//   mqtt       client with lwt, tls & one event callback, availability
+//   mqtt5      properties & reason codes esp-idf-svc does not wrap
+//   tls        mqtts:// certificates, embedded by the board with `embedded_certificates!`
+//   telemetry  json to the telemetry topic, mqtt 5 expiry & user properties
+//   crash      reset reason, boot count & last panic
+//   prometheus /metrics text format, uptime, heap, wifi & battery
+//   battery    pack voltage & state of charge through an adc divider, configured once at boot
+//   sleep      deep sleep, rtc state (wake-ups, samples, ap hint), gpio hold
+//   mdns       funny-<type>-xxxx.local & the _funnygames service, `mdns` feature
+//
+// mqtt, mqtt5, tls, telemetry & the mqtt config are behind the `mqtt` feature, mqtt5.rs needs
+// CONFIG_MQTT_PROTOCOL_5=y in the board's sdkconfig.defaults. without it (the wifi example)
+// that line is not needed.
+//
+// the boards keep their sensors, tasks & their own `toml_cfg` Config (it works per crate)
+
+pub mod battery;
+pub mod config;
+pub mod crash;
+pub mod device;
+#[cfg(feature = "mdns")]
+pub mod mdns;
+#[cfg(feature = "mqtt")]
+pub mod mqtt;
+#[cfg(feature = "mqtt")]
+pub mod mqtt5;
+pub mod prometheus;
+pub mod sleep;
+#[cfg(feature = "mqtt")]
+pub mod telemetry;
+#[cfg(feature = "mqtt")]
+pub mod tls;
+pub mod wifi;
+
+pub use config::WifiConfig;
+#[cfg(feature = "mqtt")]
+pub use config::{MqttConfig, Protocol};
+pub use device::{device_suffix, Device};
+#[cfg(feature = "mqtt")]
+pub use mqtt::MqttEvent;
+#[cfg(feature = "mqtt")]
+pub use telemetry::Telemetry;
@@ -0,0 +1,47 @@
+// mdns, the board answers as funny-<type>-xxxx.local (see device_suffix) and, while it
+// runs a http server, advertises
+//
+//   _http._tcp        whatever the board serves there, dashboard, /metrics, ..
+//   _funnygames._tcp  every funny board, for host tools to find them
+//
+// txt records: type, id, version, caps (comma separated)
+//
+//   avahi-browse -rt _funnygames._tcp
+//   dns-sd -B _funnygames._tcp
+//
+// needs the `mdns` feature and the espressif/mdns managed component in the board's Cargo.toml
+
+use anyhow::Result;
+use esp_idf_svc::mdns::EspMdns;
+use log::info;
+
+use crate::device::device_suffix;
+
+// keep it alive as long as the board should be found
+//
+// `http_port` only when a server is really listening there, without one there is nothing
+// to connect to and only the hostname is answered
+pub fn start(device_type: &str, version: &str, caps: &[&str], http_port: Option<u16>) -> Result<EspMdns> {
+    let id = device_suffix()?;
+    let hostname = format!("funny-{}-{}", device_type, id);
+    let caps = caps.join(",");
+    let txt = [
+        ("type", device_type),
+        ("id", id.as_str()),
+        ("version", version),
+        ("caps", caps.as_str()),
+    ];
+
+    let mut mdns = EspMdns::take()?;
+    mdns.set_hostname(&hostname)?;
+    mdns.set_instance_name(&hostname)?;
+    match http_port {
+        Some(port) => {
+            mdns.add_service(None, "_http", "_tcp", port, &txt)?;
+            mdns.add_service(None, "_funnygames", "_tcp", port, &txt)?;
+            info!("mdns {}.local, port:{} caps:{}", hostname, port, caps);
+        }
+        None => info!("mdns {}.local, no http server, nothing advertised", hostname),
+    }
+    Ok(mdns)
+}
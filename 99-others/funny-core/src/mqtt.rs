@@ -0,0 +1,148 @@
+// mqtt client
+//
+// one callback gets what a board cares about: connected, disconnected & incoming messages.
+// in mqtt 5 mode messages come from the raw esp-mqtt handler with their reply-to (see
+// mqtt5.rs), in 3.1.1 mode from the esp-idf-svc callback.
+//
+// the availability topic gets a retained "offline" last will, the board sends the "online"
+// birth message itself after Connected, see `publish_availability`
+
+use std::ops::{Deref, DerefMut};
+use std::sync::Arc;
+
+use anyhow::Result;
+use esp_idf_svc::mqtt::client::{EspMqttClient, EventPayload, LwtConfiguration, MqttClientConfiguration, QoS};
+use log::{error, info, warn};
+
+use crate::config::MqttConfig;
+use crate::mqtt5::{self, RawHandler, ReplyTo};
+use crate::tls::{self, Certificates};
+
+// availability payloads
+// retained, so subscribers always see the latest state
+pub const AVAILABILITY_ONLINE: &str = "online";
+pub const AVAILABILITY_OFFLINE: &str = "offline";
+
+pub enum MqttEvent<'a> {
+    // every (re)connect, subscriptions & birth message are up to the board
+    Connected,
+    Disconnected,
+    Received {
+        topic: &'a str,
+        data: &'a [u8],
+        // mqtt 5 request/response only
+        reply: Option<ReplyTo>,
+    },
+}
+
+// runs on the mqtt task, hand work over to the board's tasks & never wait there
+pub type EventHandler = Arc<dyn Fn(MqttEvent) + Send + Sync>;
+
+// the esp-idf-svc client, plus the raw event handler hooked into it in mqtt 5 mode
+pub struct MqttClient {
+    client: EspMqttClient<'static>,
+    // dropped after the client, its task may be inside the handler until then
+    handler: Option<RawHandler>,
+}
+
+impl Deref for MqttClient {
+    type Target = EspMqttClient<'static>;
+
+    fn deref(&self) -> &Self::Target {
+        &self.client
+    }
+}
+
+impl DerefMut for MqttClient {
+    fn deref_mut(&mut self) -> &mut Self::Target {
+        &mut self.client
+    }
+}
+
+impl Drop for MqttClient {
+    fn drop(&mut self) {
+        if let Some(handler) = &self.handler {
+            handler.unregister(&self.client);
+        }
+    }
+}
+
+pub fn connect(
+    config: &MqttConfig,
+    certificates: &Certificates,
+    on_event: impl Fn(MqttEvent) + Send + Sync + 'static,
+) -> Result<MqttClient> {
+    let handler: EventHandler = Arc::new(on_event);
+    let is_v5 = config.protocol.is_v5();
+    certificates.check_config(config.url);
+    info!("mqtt connecting to:{}", config.host());
+
+    let mut client_config = MqttClientConfiguration {
+        client_id: Some(config.client_id),
+        username: Some(config.user),
+        password: Some(config.pass),
+        protocol_version: Some(config.protocol.version()),
+        // broker publish "offline" for us when we are gone
+        lwt: match config.availability_topic {
+            "" => None,
+            topic => Some(LwtConfiguration {
+                topic,
+                payload: AVAILABILITY_OFFLINE.as_bytes(),
+                qos: QoS::AtLeastOnce,
+                retain: true,
+            }),
+        },
+        // mqtts://
+        server_certificate: certificates.server_certificate(),
+        client_certificate: certificates.client_certificate(),
+        private_key: certificates.private_key(),
+        crt_bundle_attach: certificates.crt_bundle_attach(config.url),
+        ..Default::default()
+    };
+    if let Some(timeout) = config.network_timeout {
+        client_config.network_timeout = timeout;
+    }
+
+    let callback = handler.clone();
+    let client = EspMqttClient::new_cb(config.url, &client_config, move |message_event| match message_event.payload() {
+        EventPayload::Received { topic, data, .. } => {
+            // mqtt 5 messages come from mqtt5::event_handler, with their properties
+            if !is_v5 {
+                callback(MqttEvent::Received {
+                    topic: topic.unwrap_or_default(),
+                    data,
+                    reply: None,
+                });
+            }
+        }
+        EventPayload::Connected(_) => {
+            info!("MQTT connected");
+            callback(MqttEvent::Connected);
+        }
+        EventPayload::Disconnected => {
+            warn!("MQTT disconnected");
+            callback(MqttEvent::Disconnected);
+        }
+        // the reason is logged by tls::register_error_logger
+        EventPayload::Error(e) => error!("MQTT error {:?}", e),
+        e => warn!("MQTT event {:?}", e),
+    })?;
+    tls::register_error_logger(&client)?;
+    let handler = match is_v5 {
+        true => Some(mqtt5::register_event_handler(&client, handler)?),
+        false => None,
+    };
+
+    Ok(MqttClient { client, handler })
+}
+
+// publish retained availability state, if enabled
+pub fn publish_availability(client: &mut EspMqttClient<'static>, topic: &str, payload: &str) {
+    if topic.is_empty() {
+        return;
+    }
+    match client.publish(topic, QoS::AtLeastOnce, true, payload.as_bytes()) {
+        Ok(_) => info!("availability:{}", payload),
+        Err(e) => error!("publish availability error:{}", e),
+    }
+}
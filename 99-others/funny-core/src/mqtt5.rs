@@ -0,0 +1,229 @@
+// mqtt 5 extras
+//
+// esp-idf-svc only wraps the 3.1.1 feature set, so properties and reason codes
+// go through esp-mqtt directly. needs CONFIG_MQTT_PROTOCOL_5=y in the board's sdkconfig.defaults
+//
+// 3.1.1 stays the default, ThingsCloud does not speak mqtt 5
+
+use core::ffi::{c_char, c_void};
+use core::ptr;
+use std::ffi::CString;
+
+use esp_idf_svc::handle::RawHandle;
+use esp_idf_svc::mqtt::client::{EspMqttClient, QoS};
+use esp_idf_svc::sys::{self, esp, EspError};
+use log::{error, info, warn};
+
+use crate::mqtt::{EventHandler, MqttEvent};
+
+// where a mqtt 5 request wants its reply
+pub struct ReplyTo {
+    pub topic: String,
+    pub correlation_data: Vec<u8>,
+}
+
+// properties for one publish
+#[derive(Default)]
+pub struct PublishProperties<'a> {
+    // seconds, 0 for never expire
+    pub message_expiry: u32,
+    pub correlation_data: Option<&'a [u8]>,
+    pub user_properties: &'a [(&'a str, &'a str)],
+}
+
+// esp-mqtt keeps the publish properties until they are replaced, and only keeps the pointers,
+// so set them right before the publish and reset them right after
+pub fn enqueue(
+    client: &mut EspMqttClient<'static>,
+    topic: &str,
+    qos: QoS,
+    retain: bool,
+    payload: &[u8],
+    properties: &PublishProperties,
+) -> Result<u32, EspError> {
+    let user_items = properties
+        .user_properties
+        .iter()
+        .filter_map(|(key, value)| Some((CString::new(*key).ok()?, CString::new(*value).ok()?)))
+        .collect::<Vec<_>>();
+    let mut items = user_items
+        .iter()
+        .map(|(key, value)| sys::esp_mqtt5_user_property_item_t {
+            key: key.as_ptr(),
+            value: value.as_ptr(),
+        })
+        .collect::<Vec<_>>();
+
+    let mut user_property: sys::mqtt5_user_property_handle_t = ptr::null_mut();
+    if !items.is_empty() {
+        esp!(unsafe {
+            sys::esp_mqtt5_client_set_user_property(&mut user_property, items.as_mut_ptr(), items.len() as u8)
+        })?;
+    }
+
+    let mut config = sys::esp_mqtt5_publish_property_config_t {
+        message_expiry_interval: properties.message_expiry,
+        user_property,
+        ..Default::default()
+    };
+    if let Some(data) = properties.correlation_data {
+        config.correlation_data = data.as_ptr() as *const c_char;
+        config.correlation_data_len = data.len() as u16;
+    }
+
+    let handle = client.handle();
+    let result = esp!(unsafe { sys::esp_mqtt5_client_set_publish_property(handle, &config) })
+        .and_then(|_| client.enqueue(topic, qos, retain, payload));
+
+    // drop dangling pointers before anyone else publishes
+    let reset = sys::esp_mqtt5_publish_property_config_t::default();
+    unsafe {
+        sys::esp_mqtt5_client_set_publish_property(handle, &reset);
+        if !user_property.is_null() {
+            sys::esp_mqtt5_client_delete_user_property(user_property);
+        }
+    }
+
+    result
+}
+
+// hook raw esp-mqtt events, the esp-idf-svc callback does not expose properties or reason codes
+//
+// in mqtt 5 mode incoming messages are passed on from here instead of the esp-idf-svc
+// callback, so the response topic & correlation data can travel with the command
+pub(crate) fn register_event_handler(client: &EspMqttClient<'static>, handler: EventHandler) -> Result<RawHandler, EspError> {
+    let handler = RawHandler(Box::into_raw(Box::new(handler)));
+    esp!(unsafe {
+        sys::esp_mqtt_client_register_event(
+            client.handle(),
+            sys::esp_mqtt_event_id_t_MQTT_EVENT_ANY,
+            Some(event_handler),
+            handler.0 as *mut c_void,
+        )
+    })?;
+    Ok(handler)
+}
+
+// the handler esp-mqtt got a pointer to, owned by mqtt::MqttClient
+//
+// failover & network recovery create new clients at runtime, the old one unregisters
+// (see `unregister`) & this frees the handler once the client is gone
+pub(crate) struct RawHandler(*mut EventHandler);
+
+// EventHandler is Send + Sync, the pointer is only shared with esp-mqtt
+unsafe impl Send for RawHandler {}
+
+impl RawHandler {
+    pub(crate) fn unregister(&self, client: &EspMqttClient<'static>) {
+        let result = esp!(unsafe {
+            sys::esp_mqtt_client_unregister_event(
+                client.handle(),
+                sys::esp_mqtt_event_id_t_MQTT_EVENT_ANY,
+                Some(event_handler),
+            )
+        });
+        if let Err(e) = result {
+            warn!("mqtt event handler unregister error:{}", e);
+        }
+    }
+}
+
+impl Drop for RawHandler {
+    fn drop(&mut self) {
+        drop(unsafe { Box::from_raw(self.0) });
+    }
+}
+
+unsafe extern "C" fn event_handler(
+    handler_args: *mut c_void,
+    _base: sys::esp_event_base_t,
+    event_id: i32,
+    event_data: *mut c_void,
+) {
+    let handler = &*(handler_args as *const EventHandler);
+    let event = &*(event_data as sys::esp_mqtt_event_handle_t);
+
+    #[allow(non_upper_case_globals)]
+    match event_id as sys::esp_mqtt_event_id_t {
+        sys::esp_mqtt_event_id_t_MQTT_EVENT_DATA => {
+            // only the first chunk of fragmented messages carries the topic, commands are small
+            if event.current_data_offset != 0 {
+                return;
+            }
+            let topic = bytes(event.topic as *const u8, event.topic_len as usize);
+            let data = bytes(event.data as *const u8, event.data_len as usize);
+            let reply = reply_to(event.property);
+            handler(MqttEvent::Received {
+                topic: core::str::from_utf8(topic).unwrap_or_default(),
+                data,
+                reply,
+            });
+        }
+        sys::esp_mqtt_event_id_t_MQTT_EVENT_DISCONNECTED => {
+            if let Some(codes) = event.error_handle.as_ref() {
+                let code = codes.disconnect_return_code as u32;
+                warn!("MQTT disconnected, reason code 0x{:02x}: {}", code, reason_code(code));
+            }
+        }
+        sys::esp_mqtt_event_id_t_MQTT_EVENT_ERROR => {
+            if let Some(codes) = event.error_handle.as_ref() {
+                if codes.error_type == sys::esp_mqtt_error_type_t_MQTT_ERROR_TYPE_CONNECTION_REFUSED {
+                    let code = codes.connect_return_code as u32;
+                    error!("MQTT connection refused, reason code 0x{:02x}: {}", code, reason_code(code));
+                }
+            }
+        }
+        _ => {}
+    }
+}
+
+unsafe fn bytes<'a>(data: *const u8, len: usize) -> &'a [u8] {
+    if data.is_null() || len == 0 {
+        return &[];
+    }
+    core::slice::from_raw_parts(data, len)
+}
+
+unsafe fn reply_to(property: sys::esp_mqtt5_event_property_t_handle) -> Option<ReplyTo> {
+    let property = property.as_ref()?;
+    let topic = bytes(property.response_topic as *const u8, property.response_topic_len as usize);
+    if topic.is_empty() {
+        return None;
+    }
+    let reply = ReplyTo {
+        topic: String::from_utf8_lossy(topic).into_owned(),
+        correlation_data: bytes(property.correlation_data, property.correlation_data_len as usize).to_vec(),
+    };
+    info!("MQTT request wants reply at:{}", reply.topic);
+    Some(reply)
+}
+
+// mqtt 5 reason codes, the ones a small client actually sees
+fn reason_code(code: u32) -> &'static str {
+    match code {
+        0x00 => "normal disconnection",
+        0x04 => "disconnect with will message",
+        0x80 => "unspecified error",
+        0x81 => "malformed packet",
+        0x82 => "protocol error",
+        0x83 => "implementation specific error",
+        0x84 => "unsupported protocol version",
+        0x85 => "client identifier not valid",
+        0x86 => "bad user name or password",
+        0x87 => "not authorized",
+        0x88 => "server unavailable",
+        0x89 => "server busy",
+        0x8A => "banned",
+        0x8B => "server shutting down",
+        0x8D => "keep alive timeout",
+        0x8E => "session taken over",
+        0x90 => "topic name invalid",
+        0x93 => "receive maximum exceeded",
+        0x95 => "packet too large",
+        0x97 => "quota exceeded",
+        0x9C => "use another server",
+        0x9D => "server moved",
+        0x9F => "connection rate exceeded",
+        _ => "unknown",
+    }
+}
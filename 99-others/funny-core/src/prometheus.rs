@@ -0,0 +1,80 @@
+// prometheus text exposition format, for the boards' GET /metrics
+//
+//   let mut out = Exposition::new("pumper", FIRMWARE_VERSION);
+//   out.system();
+//   out.metric("pump_runs_total", "counter", "Pump runs since boot.", Some(runs));
+//   out.wifi(&wifi);
+//   out.battery(battery::last());
+//   let body = out.finish();
+//
+// the boards record their own values & serve the text, this only formats. every name gets
+// the board's prefix, pumper_uptime_seconds, thermometer_uptime_seconds, ..
+
+use std::fmt::{Display, Write};
+
+use esp_idf_svc::sys;
+
+use crate::battery::Reading;
+use crate::wifi::WifiState;
+
+// for the response header
+pub const CONTENT_TYPE: &str = "text/plain; version=0.0.4";
+
+pub struct Exposition {
+    prefix: &'static str,
+    out: String,
+}
+
+impl Exposition {
+    // starts with `<prefix>_info{version=".."} 1`
+    pub fn new(prefix: &'static str, version: &str) -> Self {
+        let mut out = String::with_capacity(2048);
+        let _ = write!(
+            out,
+            "# HELP {prefix}_info Firmware version.\n# TYPE {prefix}_info gauge\n{prefix}_info{{version=\"{version}\"}} 1\n"
+        );
+        Self { prefix, out }
+    }
+
+    // # HELP / # TYPE / sample, skipped while `value` is unknown
+    pub fn metric(&mut self, name: &str, kind: &str, help: &str, value: Option<impl Display>) {
+        if let Some(value) = value {
+            let prefix = self.prefix;
+            let _ = write!(
+                self.out,
+                "# HELP {prefix}_{name} {help}\n# TYPE {prefix}_{name} {kind}\n{prefix}_{name} {value}\n"
+            );
+        }
+    }
+
+    // uptime & heap
+    pub fn system(&mut self) {
+        let (free_heap, min_free_heap, uptime_us) = unsafe {
+            (
+                sys::esp_get_free_heap_size(),
+                sys::esp_get_minimum_free_heap_size(),
+                sys::esp_timer_get_time(),
+            )
+        };
+        self.metric("uptime_seconds", "gauge", "Seconds since boot.", Some(uptime_us / 1_000_000));
+        self.metric("heap_free_bytes", "gauge", "Free heap.", Some(free_heap));
+        self.metric("heap_min_free_bytes", "gauge", "Lowest free heap since boot.", Some(min_free_heap));
+    }
+
+    pub fn wifi(&mut self, wifi: &WifiState) {
+        self.metric("wifi_up", "gauge", "1 while the station has an ip.", Some(wifi.is_up() as u8));
+        self.metric("wifi_rssi_dbm", "gauge", "Signal of the connected access point.", wifi.rssi());
+        self.metric("wifi_reconnects_total", "counter", "WiFi reconnects since boot.", Some(wifi.reconnects()));
+        self.metric("wifi_failures", "gauge", "Failed connects in a row.", Some(wifi.failures()));
+    }
+
+    // nothing while battery monitoring is off or nothing measured yet
+    pub fn battery(&mut self, reading: Option<Reading>) {
+        self.metric("battery_volts", "gauge", "Battery pack voltage.", reading.map(|reading| reading.volts()));
+        self.metric("battery_soc_percent", "gauge", "Battery state of charge, estimated.", reading.map(|reading| reading.soc));
+    }
+
+    pub fn finish(self) -> String {
+        self.out
+    }
+}
@@ -0,0 +1,180 @@
+// deep sleep helpers for the battery boards
+//
+// the rtc state survives deep sleep but not a power loss or reset. every board keeps the
+// wake-up count, the samples collected offline and the last good ap, `B` is whatever else
+// it needs (counters, filters). the board owns the static:
+//
+//   #[link_section = ".rtc.data"]
+//   static RTC_STATE: RtcSlot<RtcState<Counters, Sample, 32>> = RtcSlot::new(RtcState::new(..));
+//
+// everything in there is plain `Copy` data, no pointers into the heap.
+
+use core::cell::UnsafeCell;
+use std::time::Duration;
+
+use esp_idf_svc::sys;
+use log::info;
+
+use crate::wifi::ApHint;
+
+// samples collected between publishes, the oldest is dropped when full
+#[derive(Clone, Copy)]
+pub struct SampleRing<T: Copy, const N: usize> {
+    samples: [T; N],
+    count: u8,
+}
+
+impl<T: Copy, const N: usize> SampleRing<T, N> {
+    // `empty` only fills the unused slots, const so it can live in a static
+    pub const fn new(empty: T) -> Self {
+        Self { samples: [empty; N], count: 0 }
+    }
+
+    pub fn push(&mut self, sample: T) {
+        if self.count as usize == N {
+            self.samples.copy_within(1.., 0);
+            self.count -= 1;
+        }
+        self.samples[self.count as usize] = sample;
+        self.count += 1;
+    }
+
+    pub fn as_slice(&self) -> &[T] {
+        &self.samples[..self.count as usize]
+    }
+
+    pub fn clear(&mut self) {
+        self.count = 0;
+    }
+}
+
+// wifi::ApHint without the String, so it fits in rtc memory
+#[derive(Clone, Copy)]
+pub struct CachedAp {
+    ssid: [u8; 32],
+    ssid_len: u8,
+    bssid: [u8; 6],
+    channel: u8,
+}
+
+impl CachedAp {
+    pub fn from_hint(hint: &ApHint) -> Self {
+        let mut ssid = [0u8; 32];
+        let len = hint.ssid.len().min(32);
+        ssid[..len].copy_from_slice(&hint.ssid.as_bytes()[..len]);
+        Self {
+            ssid,
+            ssid_len: len as u8,
+            bssid: hint.bssid,
+            channel: hint.channel,
+        }
+    }
+
+    pub fn hint(&self) -> ApHint {
+        ApHint {
+            ssid: String::from_utf8_lossy(&self.ssid[..self.ssid_len as usize]).into_owned(),
+            bssid: self.bssid,
+            channel: self.channel,
+        }
+    }
+}
+
+#[derive(Clone, Copy)]
+pub struct RtcState<B: Copy, S: Copy, const N: usize> {
+    magic: u32,
+    // wake-ups since power on
+    pub wakeups: u32,
+    // the board's own part
+    pub board: B,
+    samples: SampleRing<S, N>,
+    ap: Option<CachedAp>,
+}
+
+impl<B: Copy + Default, S: Copy, const N: usize> RtcState<B, S, N> {
+    // `board` & `empty` only fill the static, a cold boot starts from `B::default()`
+    pub const fn new(board: B, empty: S) -> Self {
+        Self {
+            magic: 0,
+            wakeups: 0,
+            board,
+            samples: SampleRing::new(empty),
+            ap: None,
+        }
+    }
+
+    pub fn push_sample(&mut self, sample: S) {
+        self.samples.push(sample);
+    }
+
+    pub fn samples(&self) -> &[S] {
+        self.samples.as_slice()
+    }
+
+    pub fn clear_samples(&mut self) {
+        self.samples.clear();
+    }
+
+    pub fn ap_hint(&self) -> Option<ApHint> {
+        self.ap.map(|ap| ap.hint())
+    }
+
+    pub fn set_ap_hint(&mut self, hint: Option<ApHint>) {
+        self.ap = hint.as_ref().map(CachedAp::from_hint);
+    }
+}
+
+// the board's rtc memory static
+pub struct RtcSlot<T>(UnsafeCell<T>);
+
+// only the main task touches it, on wake-up and right before deep sleep
+unsafe impl<T> Sync for RtcSlot<T> {}
+
+impl<B: Copy + Default, S: Copy, const N: usize> RtcSlot<RtcState<B, S, N>> {
+    pub const fn new(empty: RtcState<B, S, N>) -> Self {
+        Self(UnsafeCell::new(empty))
+    }
+
+    // state of the previous wake-up, empty after a cold boot
+    // wrong `magic` = cold boot or the layout changed with a firmware update, bump it then
+    pub fn load(&self, magic: u32) -> RtcState<B, S, N> {
+        let mut state = unsafe { *self.0.get() };
+        if state.magic != magic {
+            info!("cold boot, rtc state reset");
+            state.magic = magic;
+            state.wakeups = 0;
+            state.board = B::default();
+            state.samples.clear();
+            state.ap = None;
+        }
+        state
+    }
+
+    pub fn store(&self, state: &RtcState<B, S, N>) {
+        unsafe { *self.0.get() = *state };
+    }
+}
+
+// keep an output level through deep sleep, eg the relay must stay off
+pub fn hold_during_sleep(gpio: i32) {
+    unsafe {
+        sys::gpio_hold_en(gpio);
+        sys::gpio_deep_sleep_hold_en();
+    }
+}
+
+// after a wake-up the pin is still held, release before driving it
+pub fn release_hold(gpio: i32) {
+    unsafe { sys::gpio_hold_dis(gpio) };
+}
+
+pub fn woke_from_timer() -> bool {
+    unsafe { sys::esp_sleep_get_wakeup_cause() == sys::esp_sleep_source_t_ESP_SLEEP_WAKEUP_TIMER }
+}
+
+pub fn deep_sleep(duration: Duration) -> ! {
+    info!("deep sleep for {:?}", duration);
+    unsafe {
+        sys::esp_sleep_enable_timer_wakeup(duration.as_micros() as u64);
+        sys::esp_deep_sleep_start();
+    }
+}
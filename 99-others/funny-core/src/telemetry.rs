@@ -0,0 +1,53 @@
+// telemetry publisher
+//
+// serializes to json and enqueues on the telemetry topic, never waits for the broker.
+// mqtt 5 adds message expiry & the device / fw_version user properties. a mirror topic,
+// eg the home assistant state topic, gets the same payload without properties.
+
+use anyhow::Result;
+use esp_idf_svc::mqtt::client::{EspMqttClient, QoS};
+use log::{error, info};
+use serde::Serialize;
+
+use crate::config::Protocol;
+use crate::mqtt5::{self, PublishProperties};
+
+pub struct Telemetry<'a> {
+    // `mqtt_topic`
+    pub topic: &'a str,
+    pub protocol: Protocol,
+    // mqtt 5 only, seconds, 0 for never expire
+    pub message_expiry: u32,
+    // "pumper", "thermometer", ..
+    pub device: &'a str,
+    pub version: &'a str,
+}
+
+impl Telemetry<'_> {
+    // `value` as json, to the telemetry topic and `mirror` if any, the payload for logging
+    pub fn publish<T: Serialize + ?Sized>(
+        &self,
+        client: &mut EspMqttClient<'static>,
+        mirror: Option<&str>,
+        value: &T,
+    ) -> Result<String> {
+        let payload = serde_json::to_string(value)?;
+        if let Some(topic) = mirror {
+            if let Err(e) = client.enqueue(topic, QoS::AtMostOnce, false, payload.as_bytes()) {
+                error!("mirror publish error:{} topic:{}", e, topic);
+            }
+        }
+        if self.protocol.is_v5() {
+            let properties = PublishProperties {
+                message_expiry: self.message_expiry,
+                user_properties: &[("device", self.device), ("fw_version", self.version)],
+                ..Default::default()
+            };
+            mqtt5::enqueue(client, self.topic, QoS::AtMostOnce, false, payload.as_bytes(), &properties)?;
+        } else {
+            client.enqueue(self.topic, QoS::AtMostOnce, false, payload.as_bytes())?;
+        }
+        info!("send telemetry:{}", payload);
+        Ok(payload)
+    }
+}
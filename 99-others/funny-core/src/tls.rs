@@ -0,0 +1,170 @@
+// mqtts:// support
+//
+// certificates are embedded at build time from the board's `certs/`, see certs/README.md
+// - feature `mqtt-tls`:  certs/ca.crt, CA used to verify the broker
+// - feature `mqtt-mtls`: certs/client.crt & certs/client.key, for mutual tls
+//
+// the features belong to the board, `embedded_certificates!()` expands there
+//
+// without `mqtt-tls`, mqtts:// brokers are verified with the esp-idf certificate bundle,
+// so public brokers (eg ThingsCloud) still work
+
+use core::ffi::c_void;
+use core::ptr;
+
+use esp_idf_svc::handle::RawHandle;
+use esp_idf_svc::mqtt::client::EspMqttClient;
+use esp_idf_svc::sys::{self, esp, esp_err_t, EspError};
+use esp_idf_svc::tls::X509;
+use log::{error, warn};
+
+// pem strings, nul terminated for mbedtls
+#[derive(Clone, Copy, Debug, Default)]
+pub struct Certificates {
+    pub ca: Option<&'static str>,
+    pub client_cert: Option<&'static str>,
+    pub client_key: Option<&'static str>,
+}
+
+// the board's certs/, by its `mqtt-tls` & `mqtt-mtls` features
+#[macro_export]
+macro_rules! embedded_certificates {
+    () => {
+        $crate::tls::Certificates {
+            #[cfg(feature = "mqtt-tls")]
+            ca: Some(concat!(include_str!(concat!(env!("CARGO_MANIFEST_DIR"), "/certs/ca.crt")), "\0")),
+            #[cfg(not(feature = "mqtt-tls"))]
+            ca: None,
+            #[cfg(feature = "mqtt-mtls")]
+            client_cert: Some(concat!(include_str!(concat!(env!("CARGO_MANIFEST_DIR"), "/certs/client.crt")), "\0")),
+            #[cfg(not(feature = "mqtt-mtls"))]
+            client_cert: None,
+            #[cfg(feature = "mqtt-mtls")]
+            client_key: Some(concat!(include_str!(concat!(env!("CARGO_MANIFEST_DIR"), "/certs/client.key")), "\0")),
+            #[cfg(not(feature = "mqtt-mtls"))]
+            client_key: None,
+        }
+    };
+}
+
+pub fn is_tls(url: &str) -> bool {
+    url.starts_with("mqtts://") || url.starts_with("wss://")
+}
+
+impl Certificates {
+    pub fn server_certificate(&self) -> Option<X509<'static>> {
+        self.ca.map(|pem| X509::pem_until_nul(pem.as_bytes()))
+    }
+
+    pub fn client_certificate(&self) -> Option<X509<'static>> {
+        self.client_cert.map(|pem| X509::pem_until_nul(pem.as_bytes()))
+    }
+
+    pub fn private_key(&self) -> Option<X509<'static>> {
+        self.client_key.map(|pem| X509::pem_until_nul(pem.as_bytes()))
+    }
+
+    // fall back to the built-in CA bundle when no CA is embedded
+    pub fn crt_bundle_attach(&self, url: &str) -> Option<unsafe extern "C" fn(*mut core::ffi::c_void) -> esp_err_t> {
+        if is_tls(url) && self.ca.is_none() {
+            Some(sys::esp_crt_bundle_attach)
+        } else {
+            None
+        }
+    }
+
+    // warn early about setups that can never work
+    pub fn check_config(&self, url: &str) {
+        if is_tls(url) {
+            return;
+        }
+        if self.ca.is_some() {
+            warn!("mqtt-tls enabled but mqtt_host is not mqtts://, CA certificate is ignored");
+        }
+        warn!("mqtt_host is not mqtts://, username and password are sent in cleartext");
+    }
+}
+
+// human readable reason for esp_tls_last_esp_err
+fn describe_error(code: esp_err_t) -> Option<&'static str> {
+    let reason = match code as u32 {
+        sys::ESP_ERR_ESP_TLS_CANNOT_RESOLVE_HOSTNAME => "cannot resolve broker hostname",
+        sys::ESP_ERR_ESP_TLS_FAILED_CONNECT_TO_HOST => "cannot connect to broker",
+        sys::ESP_ERR_ESP_TLS_CONNECTION_TIMEOUT => "tls connection timeout",
+        sys::ESP_ERR_MBEDTLS_X509_CRT_PARSE_FAILED => "embedded certificate is not valid pem, check certs/",
+        sys::ESP_ERR_MBEDTLS_PK_PARSE_KEY_FAILED => "embedded client key is not valid pem, check certs/client.key",
+        sys::ESP_ERR_MBEDTLS_SSL_SETUP_FAILED => "tls setup failed, out of memory?",
+        sys::ESP_ERR_MBEDTLS_SSL_HANDSHAKE_FAILED => {
+            "tls handshake failed: broker certificate not signed by our CA, hostname mismatch, or client certificate rejected"
+        }
+        sys::ESP_ERR_MBEDTLS_SSL_CONF_OWN_CERT_FAILED => "client certificate and key do not match",
+        _ => return None,
+    };
+    Some(reason)
+}
+
+// mbedtls MBEDTLS_X509_BADCERT_* flags, from esp_tls_cert_verify_flags
+const VERIFY_FLAGS: &[(i32, &str)] = &[
+    (0x01, "expired"),
+    (0x02, "revoked"),
+    (0x04, "hostname mismatch"),
+    (0x08, "not signed by our CA"),
+    (0x40, "missing"),
+    (0x0200, "not valid yet, clock not synced?"),
+    (0x0800, "key usage"),
+    (0x1000, "extended key usage"),
+    (0x4000, "hash algorithm not allowed"),
+    (0x8000, "key type not allowed"),
+    (0x10000, "key too short"),
+];
+
+// esp-idf-svc hands MQTT_EVENT_ERROR over as a plain ESP_FAIL, the transport & tls details are
+// only in the raw event's error_handle
+pub(crate) fn register_error_logger(client: &EspMqttClient<'static>) -> Result<(), EspError> {
+    esp!(unsafe {
+        sys::esp_mqtt_client_register_event(
+            client.handle(),
+            sys::esp_mqtt_event_id_t_MQTT_EVENT_ERROR,
+            Some(log_error),
+            ptr::null_mut(),
+        )
+    })
+}
+
+unsafe extern "C" fn log_error(
+    _handler_args: *mut c_void,
+    _base: sys::esp_event_base_t,
+    _event_id: i32,
+    event_data: *mut c_void,
+) {
+    let event = &*(event_data as sys::esp_mqtt_event_handle_t);
+    let Some(codes) = event.error_handle.as_ref() else {
+        return;
+    };
+    if codes.error_type != sys::esp_mqtt_error_type_t_MQTT_ERROR_TYPE_TCP_TRANSPORT {
+        return;
+    }
+
+    let code = codes.esp_tls_last_esp_err;
+    match describe_error(code) {
+        Some(reason) => error!("MQTT tls error 0x{:x}: {}", code, reason),
+        None if code != 0 => error!("MQTT transport error 0x{:x}", code),
+        None => {}
+    }
+    if codes.esp_tls_stack_err != 0 {
+        error!("MQTT mbedtls error -0x{:04x}", codes.esp_tls_stack_err.unsigned_abs());
+    }
+    let flags = codes.esp_tls_cert_verify_flags;
+    if flags != 0 {
+        let reasons = VERIFY_FLAGS
+            .iter()
+            .filter(|(flag, _)| flags & flag != 0)
+            .map(|(_, reason)| *reason)
+            .collect::<Vec<_>>();
+        error!("MQTT broker certificate rejected (0x{:x}): {}", flags, reasons.join(", "));
+    }
+    if codes.esp_transport_sock_errno != 0 {
+        let errno = codes.esp_transport_sock_errno;
+        error!("MQTT socket errno {}: {}", errno, std::io::Error::from_raw_os_error(errno));
+    }
+}
@@ -0,0 +1,435 @@
+// wifi connection manager
+//
+// wifi runs in its own thread and is driven by wifi/ip events:
+// - disconnected -> wait (exponential backoff + jitter) -> scan -> connect again
+// - got ip       -> reset backoff
+// the board never blocks on wifi, its sensors keep running while offline
+//
+// several known networks are supported (house ap, garden shed repeater, ...). every round
+// scans first and tries the visible known networks strongest first, a failed one falls
+// through to the next, only after the whole list failed we back off.
+// a deep sleep wake-up passes the last good ap (bssid + channel) and skips the first scan.
+
+use std::sync::atomic::{AtomicBool, AtomicU32, Ordering};
+use std::sync::mpsc::{self, Receiver, RecvTimeoutError};
+use std::sync::{Arc, Mutex};
+use std::thread;
+use std::time::{Duration, Instant};
+
+use anyhow::{anyhow, Result};
+use esp_idf_svc::eventloop::{EspSubscription, EspSystemEventLoop, System};
+use esp_idf_svc::hal::modem::Modem;
+use esp_idf_svc::netif::IpEvent;
+use esp_idf_svc::nvs::EspDefaultNvsPartition;
+use esp_idf_svc::sys::{self, esp};
+use esp_idf_svc::wifi::{AuthMethod, ClientConfiguration, Configuration, EspWifi, WifiEvent};
+use log::{error, info, warn};
+
+// backoff between reconnect attempts
+const BACKOFF_MIN: Duration = Duration::from_secs(1);
+const BACKOFF_MAX: Duration = Duration::from_secs(5 * 60);
+// esp_wifi_connect may never report back, eg the ap is gone
+const CONNECT_TIMEOUT: Duration = Duration::from_secs(30);
+// what the driver takes, as bytes
+const MAX_SSID_LEN: usize = 32;
+const MIN_PSK_LEN: usize = 8;
+const MAX_PSK_LEN: usize = 64;
+
+enum WifiSignal {
+    Started,
+    Disconnected,
+    GotIp,
+}
+
+// the esp-idf driver, nvs keeps the rf calibration
+pub fn driver(modem: Modem, sysloop: &EspSystemEventLoop, nvs: &EspDefaultNvsPartition) -> Result<EspWifi<'static>> {
+    Ok(EspWifi::new(modem, sysloop.clone(), Some(nvs.clone()))?)
+}
+
+#[derive(Clone, Debug, PartialEq)]
+pub struct KnownNetwork {
+    pub ssid: String,
+    pub psk: String,
+}
+
+// check before saving credentials, the driver can not take longer ones
+// an empty psk is an open network
+pub fn check_credentials(ssid: &str, psk: &str) -> Result<(), &'static str> {
+    if ssid.len() > MAX_SSID_LEN {
+        return Err("wifi ssid is longer than 32 bytes");
+    }
+    if !psk.is_empty() && !(MIN_PSK_LEN..=MAX_PSK_LEN).contains(&psk.len()) {
+        return Err("wifi psk must be 8 to 64 characters");
+    }
+    Ok(())
+}
+
+// the provisioned network first, then "ssid|psk;ssid|psk" from `wifi_networks`
+pub fn known_networks(primary: KnownNetwork, extra: &str) -> Vec<KnownNetwork> {
+    let mut networks = vec![primary];
+    let extra = extra.split(';').map(str::trim).filter(|item| !item.is_empty()).map(|item| {
+        let (ssid, psk) = item.split_once('|').unwrap_or((item, ""));
+        KnownNetwork {
+            ssid: ssid.trim().to_string(),
+            psk: psk.to_string(),
+        }
+    });
+    for network in extra {
+        if !networks.iter().any(|known| known.ssid == network.ssid) {
+            networks.push(network);
+        }
+    }
+    networks
+}
+
+// last good ap, lets a deep sleep wake-up connect without scanning
+#[derive(Clone, Debug)]
+pub struct ApHint {
+    pub ssid: String,
+    pub bssid: [u8; 6],
+    pub channel: u8,
+}
+
+// shared with the rest of the firmware (metrics, http api, supervisor, ..)
+#[derive(Default)]
+pub struct WifiState {
+    up: AtomicBool,
+    reconnects: AtomicU32,
+    failures: AtomicU32,
+    // ssid we are (or were last) connected to
+    network: Mutex<Option<String>>,
+}
+
+impl WifiState {
+    pub fn is_up(&self) -> bool {
+        self.up.load(Ordering::Relaxed)
+    }
+
+    // successful reconnects since boot
+    pub fn reconnects(&self) -> u32 {
+        self.reconnects.load(Ordering::Relaxed)
+    }
+
+    // failed connect attempts in a row
+    pub fn failures(&self) -> u32 {
+        self.failures.load(Ordering::Relaxed)
+    }
+
+    pub fn network(&self) -> Option<String> {
+        self.network.lock().unwrap().clone()
+    }
+
+    // signal of the current ap, as dBm
+    pub fn rssi(&self) -> Option<i8> {
+        if !self.is_up() {
+            return None;
+        }
+        let mut record = sys::wifi_ap_record_t::default();
+        esp!(unsafe { sys::esp_wifi_sta_get_ap_info(&mut record) }).ok()?;
+        Some(record.rssi)
+    }
+
+    // the ap we are connected to, for the next wake-up
+    pub fn ap_hint(&self) -> Option<ApHint> {
+        if !self.is_up() {
+            return None;
+        }
+        let mut record = sys::wifi_ap_record_t::default();
+        esp!(unsafe { sys::esp_wifi_sta_get_ap_info(&mut record) }).ok()?;
+        let len = record.ssid.iter().position(|&b| b == 0).unwrap_or(record.ssid.len());
+        Some(ApHint {
+            ssid: String::from_utf8_lossy(&record.ssid[..len]).into_owned(),
+            bssid: record.bssid,
+            channel: record.primary,
+        })
+    }
+}
+
+pub struct WifiManager {
+    pub wifi: Arc<Mutex<EspWifi<'static>>>,
+    pub state: Arc<WifiState>,
+    _subscriptions: (EspSubscription<'static, System>, EspSubscription<'static, System>),
+}
+
+impl WifiManager {
+    pub fn start(
+        mut wifi: EspWifi<'static>,
+        sysloop: &EspSystemEventLoop,
+        networks: Vec<KnownNetwork>,
+        hint: Option<ApHint>,
+    ) -> Result<Self> {
+        let (signal_tx, signal_rx) = mpsc::channel::<WifiSignal>();
+
+        let tx = signal_tx.clone();
+        let wifi_subscription = sysloop.subscribe::<WifiEvent, _>(move |event| {
+            let signal = match event {
+                WifiEvent::StaStarted => WifiSignal::Started,
+                WifiEvent::StaDisconnected(_) => WifiSignal::Disconnected,
+                _ => return,
+            };
+            let _ = tx.send(signal);
+        })?;
+        let tx = signal_tx;
+        let ip_subscription = sysloop.subscribe::<IpEvent, _>(move |event| {
+            if let IpEvent::DhcpIpAssigned(assignment) = event {
+                info!("wifi got ip:{}", assignment.ip());
+                let _ = tx.send(WifiSignal::GotIp);
+            }
+        })?;
+
+        info!("wifi known networks:{:?}", networks.iter().map(|n| n.ssid.as_str()).collect::<Vec<_>>());
+        wifi.set_configuration(&Configuration::Client(ClientConfiguration::default()))?;
+        wifi.start()?;
+
+        let wifi = Arc::new(Mutex::new(wifi));
+        let state = Arc::new(WifiState::default());
+
+        let thread_wifi = wifi.clone();
+        let thread_state = state.clone();
+        thread::Builder::new()
+            .name("wifi".into())
+            .stack_size(6 * 1024)
+            .spawn(move || connection_loop(thread_wifi, thread_state, networks, hint, signal_rx))?;
+
+        Ok(Self {
+            wifi,
+            state,
+            _subscriptions: (wifi_subscription, ip_subscription),
+        })
+    }
+
+    // drop the connection, the connection loop connects again
+    pub fn reconnect(&self) -> Result<()> {
+        info!("wifi reconnect requested");
+        self.wifi.lock().unwrap().disconnect()?;
+        Ok(())
+    }
+
+    // driver stop & start, the connection loop starts over with a scan
+    pub fn restart(&self) -> Result<()> {
+        info!("wifi restart requested");
+        let mut wifi = self.wifi.lock().unwrap();
+        wifi.stop()?;
+        wifi.start()?;
+        Ok(())
+    }
+}
+
+struct Backoff {
+    delay: Duration,
+}
+
+impl Backoff {
+    // next attempt time, doubles the delay for the one after
+    fn next(&mut self) -> Instant {
+        let at = Instant::now() + jitter(self.delay);
+        info!("wifi reconnect in {:?}", self.delay);
+        self.delay = (self.delay * 2).min(BACKOFF_MAX);
+        at
+    }
+
+    fn reset(&mut self) {
+        self.delay = BACKOFF_MIN;
+    }
+}
+
+// a known network seen in the scan
+struct Candidate {
+    network: KnownNetwork,
+    bssid: Option<[u8; 6]>,
+    channel: Option<u8>,
+    auth_method: Option<AuthMethod>,
+    rssi: Option<i8>,
+}
+
+// visible known networks, strongest first
+// nothing visible (hidden ssid, scan failed), try them all in the configured order
+fn scan_candidates(wifi: &mut EspWifi<'static>, networks: &[KnownNetwork]) -> Vec<Candidate> {
+    let blind = || {
+        networks
+            .iter()
+            .map(|network| Candidate {
+                network: network.clone(),
+                bssid: None,
+                channel: None,
+                auth_method: None,
+                rssi: None,
+            })
+            .collect()
+    };
+
+    let aps = match wifi.scan() {
+        Ok(aps) => aps,
+        Err(e) => {
+            error!("wifi scan error:{}", e);
+            return blind();
+        }
+    };
+    let mut candidates = Vec::new();
+    for network in networks {
+        // the same ssid from several aps, take the strongest
+        let best = aps
+            .iter()
+            .filter(|ap| ap.ssid.as_str() == network.ssid)
+            .max_by_key(|ap| ap.signal_strength);
+        if let Some(ap) = best {
+            candidates.push(Candidate {
+                network: network.clone(),
+                bssid: Some(ap.bssid),
+                channel: Some(ap.channel),
+                auth_method: ap.auth_method,
+                rssi: Some(ap.signal_strength),
+            });
+        }
+    }
+    if candidates.is_empty() {
+        warn!("wifi scan found {} aps, none of them known", aps.len());
+        return blind();
+    }
+    candidates.sort_by_key(|candidate| std::cmp::Reverse(candidate.rssi));
+    candidates
+}
+
+// straight to the cached ap, if it is still a known network
+fn hinted_candidate(networks: &[KnownNetwork], hint: ApHint) -> Option<Candidate> {
+    let network = networks.iter().find(|network| network.ssid == hint.ssid)?;
+    Some(Candidate {
+        network: network.clone(),
+        bssid: Some(hint.bssid),
+        channel: Some(hint.channel),
+        auth_method: None,
+        rssi: None,
+    })
+}
+
+fn connect(wifi: &mut EspWifi<'static>, candidate: &Candidate) -> Result<()> {
+    info!("wifi connecting to {}, rssi:{:?}", candidate.network.ssid, candidate.rssi);
+    let auth_method = match candidate.auth_method {
+        Some(auth_method) => auth_method,
+        None if candidate.network.psk.is_empty() => AuthMethod::None,
+        None => AuthMethod::WPA2Personal,
+    };
+    wifi.set_configuration(&Configuration::Client(ClientConfiguration {
+        // a bad value counts as a failed connect, the other networks are still tried
+        ssid: candidate.network.ssid.as_str().try_into().map_err(|_| anyhow!("ssid longer than 32 bytes"))?,
+        password: candidate.network.psk.as_str().try_into().map_err(|_| anyhow!("psk longer than 64 bytes"))?,
+        bssid: candidate.bssid,
+        channel: candidate.channel,
+        auth_method,
+        ..Default::default()
+    }))?;
+    // only kicks off the connect, the result comes back as an event
+    wifi.connect()?;
+    Ok(())
+}
+
+// next candidate right away, back off once the whole round failed
+fn attempt_failed(state: &WifiState, remaining: &[Candidate], backoff: &mut Backoff) -> Instant {
+    state.failures.fetch_add(1, Ordering::Relaxed);
+    if remaining.is_empty() {
+        backoff.next()
+    } else {
+        Instant::now()
+    }
+}
+
+fn connection_loop(
+    wifi: Arc<Mutex<EspWifi<'static>>>,
+    state: Arc<WifiState>,
+    networks: Vec<KnownNetwork>,
+    mut hint: Option<ApHint>,
+    signals: Receiver<WifiSignal>,
+) {
+    let mut backoff = Backoff { delay: BACKOFF_MIN };
+    // when to (re)try connecting, None while connected or an attempt is running
+    let mut next_attempt: Option<Instant> = None;
+    let mut attempt_started: Option<Instant> = None;
+    let mut ever_connected = false;
+    // networks left to try this round, rescanned once empty
+    let mut candidates: Vec<Candidate> = Vec::new();
+    let mut attempting: Option<String> = None;
+
+    loop {
+        let timeout = next_attempt
+            .map(|at| at.saturating_duration_since(Instant::now()))
+            .unwrap_or(CONNECT_TIMEOUT);
+
+        match signals.recv_timeout(timeout) {
+            Ok(WifiSignal::Started) => next_attempt = Some(Instant::now()),
+            Ok(WifiSignal::Disconnected) => {
+                if state.up.swap(false, Ordering::Relaxed) {
+                    warn!("wifi lost");
+                    if next_attempt.is_none() {
+                        next_attempt = Some(backoff.next());
+                    }
+                } else if attempt_started.take().is_some() {
+                    warn!("wifi connect to {} failed", attempting.as_deref().unwrap_or_default());
+                    next_attempt = Some(attempt_failed(&state, &candidates, &mut backoff));
+                } else if next_attempt.is_none() {
+                    next_attempt = Some(backoff.next());
+                }
+            }
+            Ok(WifiSignal::GotIp) => {
+                state.up.store(true, Ordering::Relaxed);
+                if ever_connected {
+                    state.reconnects.fetch_add(1, Ordering::Relaxed);
+                    info!("wifi reconnected!!");
+                } else {
+                    info!("wifi connected");
+                }
+                ever_connected = true;
+                *state.network.lock().unwrap() = attempting.clone();
+                state.failures.store(0, Ordering::Relaxed);
+                backoff.reset();
+                candidates.clear();
+                next_attempt = None;
+                attempt_started = None;
+            }
+            Err(RecvTimeoutError::Timeout) => {}
+            Err(RecvTimeoutError::Disconnected) => {
+                error!("wifi event channel closed");
+                return;
+            }
+        }
+
+        // attempt hanging without any event, treat as failed
+        if let Some(started) = attempt_started {
+            if started.elapsed() >= CONNECT_TIMEOUT {
+                warn!("wifi connect timeout");
+                attempt_started = None;
+                next_attempt = Some(attempt_failed(&state, &candidates, &mut backoff));
+            }
+        }
+
+        if next_attempt.is_some_and(|at| Instant::now() >= at) {
+            next_attempt = None;
+            let mut wifi = wifi.lock().unwrap();
+            if candidates.is_empty() {
+                // the cached ap only gets the first try, a failure falls back to scanning
+                candidates = match hint.take().and_then(|hint| hinted_candidate(&networks, hint)) {
+                    Some(candidate) => vec![candidate],
+                    None => scan_candidates(&mut wifi, &networks),
+                };
+                // pop from the back
+                candidates.reverse();
+            }
+            let Some(candidate) = candidates.pop() else {
+                next_attempt = Some(backoff.next());
+                continue;
+            };
+            attempting = Some(candidate.network.ssid.clone());
+            attempt_started = Some(Instant::now());
+            if let Err(e) = connect(&mut wifi, &candidate) {
+                error!("wifi connect error:{}", e);
+                attempt_started = None;
+                next_attempt = Some(attempt_failed(&state, &candidates, &mut backoff));
+            }
+        }
+    }
+}
+
+// +-25%, so a room full of devices does not hammer the ap at the same moment
+fn jitter(backoff: Duration) -> Duration {
+    let random = unsafe { esp_idf_svc::sys::esp_random() } % 500;
+    backoff * (750 + random) / 1000
+}
@@ -1,8 +1,10 @@
 use anyhow::Result;
-use esp_idf_svc::{
-    eventloop::EspSystemEventLoop, hal::{delay::{Delay, FreeRtos}, prelude::Peripherals}, mqtt::{self, client::{EspMqttClient, EventPayload, MqttProtocolVersion, QoS}}, nvs::EspDefaultNvsPartition, wifi::{BlockingWifi, ClientConfiguration, Configuration, EspWifi}
-};
-use log::{error, info, warn};
+use esp_idf_svc::hal::delay::FreeRtos;
+use funny_core::tls::Certificates;
+use funny_core::wifi::{self, WifiManager};
+use funny_core::{mqtt, Device, MqttConfig, Protocol, Telemetry, WifiConfig};
+use log::{error, info};
+use serde_json::json;
 
 #[toml_cfg::toml_config]
 pub struct Config {
@@ -22,80 +24,50 @@ pub struct Config {
     mqtt_topic: &'static str,
 }
 
-fn wifi() -> Result<BlockingWifi<EspWifi<'static>>> {
-    let sysloop = EspSystemEventLoop::take()?;
-    let nvs = EspDefaultNvsPartition::take()?;
-    let peripherals = Peripherals::take()?;
+fn main() -> Result<()> {
+    // logger, sysloop, nvs & peripherals, see funny-core
+    let Device { sysloop, nvs, peripherals } = Device::take()?;
 
-    // get wifi connect info
+    // connect wifi, reconnects in background
     let app_config = CONFIG;
-    info!(
-        "wifi config info: ssid:{},pass:{}",
-        app_config.wifi_ssid, app_config.wifi_psk
-    );
-
-    // init wifi driver
-    let mut wifi_driver = EspWifi::new(peripherals.modem, sysloop.clone(), Some(nvs))?;
-    let mut wifi = BlockingWifi::wrap(wifi_driver, sysloop.clone())?;
-    wifi.set_configuration(&Configuration::Client(ClientConfiguration {
-        ssid: app_config.wifi_ssid.try_into().unwrap(),
-        password: app_config.wifi_psk.try_into().unwrap(),
-        ..Default::default()
-    }))?;
-
-    wifi.start()?;
-    info!("wifi start");
-
-    wifi.connect()?;
-    info!("wifi connected");
-
-    wifi.wait_netif_up()?;
-
-    Ok(wifi)
-}
-
+    info!("wifi config info: ssid:{}", app_config.wifi_ssid);
+    let wifi_driver = wifi::driver(peripherals.modem, &sysloop, &nvs)?;
+    let networks = WifiConfig {
+        ssid: app_config.wifi_ssid,
+        psk: app_config.wifi_psk,
+        networks: "",
+    }
+    .known_networks();
+    let wifi = WifiManager::start(wifi_driver, &sysloop, networks, None)?;
 
-fn mqtt_client_init()->Result<EspMqttClient<'static>>{
     // mqtt client
-    let app_config = CONFIG;
-    // let mqtt_config = mqtt::client::MqttClientConfiguration::default();
-    let mut client: EspMqttClient<'static>= EspMqttClient::new_cb(
-        app_config.mqtt_host,
-        &mqtt::client::MqttClientConfiguration {
-            client_id: Some(app_config.mqtt_clientid),
-            username: Some(app_config.mqtt_user),
-            password: Some(&app_config.mqtt_pass),
-            protocol_version: Some(MqttProtocolVersion::V3_1_1),
-            ..Default::default()
-        },
-        move |message_event| match message_event.payload() {
-            EventPayload::Error(e) => error!("MQTT error {:?}", e),
-            e => warn!("MQTT event {:?}", e),
-        },
-    )?;
-    return Ok(client)
-}
-fn main() -> Result<()> {
-    // It is necessary to call this function once. Otherwise some patches to the runtime
-    // implemented by esp-idf-sys might not link properly. See https://github.com/esp-rs/esp-idf-template/issues/71
-    esp_idf_svc::sys::link_patches();
+    let mqtt_config = MqttConfig {
+        url: app_config.mqtt_host,
+        user: app_config.mqtt_user,
+        pass: app_config.mqtt_pass,
+        client_id: app_config.mqtt_clientid,
+        protocol: Protocol::V3_1_1,
+        availability_topic: "",
+        network_timeout: None,
+    };
+    // mqtts:// is verified with the esp-idf certificate bundle
+    let mut client = mqtt::connect(&mqtt_config, &Certificates::default(), |_| {})?;
+    let telemetry = Telemetry {
+        topic: app_config.mqtt_topic,
+        protocol: mqtt_config.protocol,
+        message_expiry: 0,
+        device: "mqtt-client",
+        version: env!("CARGO_PKG_VERSION"),
+    };
 
-    // Bind the log crate to the ESP Logging facilities
-    esp_idf_svc::log::EspLogger::initialize_default();
-
-    // connect wifi
-    let _wifi = wifi()?;
-
-    // mqtt client
-    let app_config = CONFIG;
-    let mut client = mqtt_client_init()?;
-    
     loop {
         // sample data！！
-        let payload =  r#"
-        {"temperature": 34.2,"percent": 70}"#;
-        client.publish(app_config.mqtt_topic, QoS::AtMostOnce, false, payload.as_bytes())?;
+        if wifi.state.is_up() {
+            let payload = json!({ "temperature": 34.2, "percent": 70 });
+            if let Err(e) = telemetry.publish(&mut client, None, &payload) {
+                error!("publish error:{}", e);
+            }
+        }
         FreeRtos::delay_ms(1000*60);
     }
-    Ok(())
 }
@@ -0,0 +1,178 @@
+// ota-sign
+// keys & signatures for pumper ota images
+//
+//   ota-sign keygen --out ota.key
+//   ota-sign sign --key ota.key --version 0.2.0 --url http://192.168.1.20:8000/pumper.bin pumper.bin
+//   ota-sign verify --public-key <hex> --version 0.2.0 --signature <hex> pumper.bin
+
+use std::fs;
+use std::path::{Path, PathBuf};
+
+use anyhow::{anyhow, bail, Context, Result};
+use clap::{Parser, Subcommand};
+use ed25519_dalek::{Signature, Signer, SigningKey, VerifyingKey};
+use pumper_logic::ota::signed_message;
+use rand_core::OsRng;
+use sha2::{Digest, Sha256};
+
+#[derive(Parser)]
+#[command(version, about = "sign funny_games ota firmware images")]
+struct Args {
+    #[command(subcommand)]
+    command: Command,
+}
+
+#[derive(Subcommand)]
+enum Command {
+    /// new signing key, prints the public key for cfg.toml
+    Keygen {
+        #[arg(long, default_value = "ota.key")]
+        out: PathBuf,
+    },
+    /// sign an image, made with `espflash save-image`
+    Sign {
+        #[arg(long, default_value = "ota.key")]
+        key: PathBuf,
+        /// version the image was built with, must be newer than the running one
+        #[arg(long)]
+        version: String,
+        /// where the device downloads the image, prints the full cloud command
+        #[arg(long)]
+        url: Option<String>,
+        image: PathBuf,
+    },
+    /// check a signature like the device does
+    Verify {
+        #[arg(long)]
+        public_key: String,
+        #[arg(long)]
+        version: String,
+        #[arg(long)]
+        signature: String,
+        image: PathBuf,
+    },
+}
+
+fn image_digest(image: &Path) -> Result<Vec<u8>> {
+    let data = fs::read(image).with_context(|| format!("read {}", image.display()))?;
+    Ok(Sha256::digest(data).to_vec())
+}
+
+fn check_version(version: &str) -> Result<()> {
+    if version.is_empty() || version.split('.').any(|part| part.parse::<u32>().is_err()) {
+        bail!("version must look like 1.2.3, got {}", version);
+    }
+    Ok(())
+}
+
+fn load_key(path: &Path) -> Result<SigningKey> {
+    let text = fs::read_to_string(path).with_context(|| format!("read {}", path.display()))?;
+    let bytes: [u8; 32] = hex::decode(text.trim())?
+        .try_into()
+        .map_err(|_| anyhow!("{} is not a 32 byte hex key", path.display()))?;
+    Ok(SigningKey::from_bytes(&bytes))
+}
+
+fn keygen(out: &Path) -> Result<()> {
+    if out.exists() {
+        bail!("{} exists, not overwriting a signing key", out.display());
+    }
+    let key = SigningKey::generate(&mut OsRng);
+    fs::write(out, hex::encode(key.to_bytes()) + "\n")?;
+    println!("secret key written to {}, keep it out of git", out.display());
+    println!("add to cfg.toml:");
+    println!("ota_public_key = \"{}\"", hex::encode(key.verifying_key().to_bytes()));
+    Ok(())
+}
+
+fn sign(key: &Path, version: &str, url: Option<&str>, image: &Path) -> Result<()> {
+    check_version(version)?;
+    let key = load_key(key)?;
+    let digest = image_digest(image)?;
+    let signature = sign_digest(&key, version, &digest);
+
+    println!("sha256:    {}", hex::encode(&digest));
+    println!("signature: {}", signature);
+    if let Some(url) = url {
+        let command = serde_json::json!({
+            "method": "ota",
+            "params": { "Ota": { "url": url, "version": version, "signature": signature } },
+            "id": 1,
+        });
+        println!("command:   {}", command);
+    }
+    Ok(())
+}
+
+fn verify(public_key: &str, version: &str, signature: &str, image: &Path) -> Result<()> {
+    verify_digest(public_key, version, signature, &image_digest(image)?)?;
+    println!("signature ok");
+    Ok(())
+}
+
+// hex
+fn sign_digest(key: &SigningKey, version: &str, digest: &[u8]) -> String {
+    hex::encode(key.sign(&signed_message(version, digest)).to_bytes())
+}
+
+// what pumper/src/ota.rs does
+fn verify_digest(public_key: &str, version: &str, signature: &str, digest: &[u8]) -> Result<()> {
+    let public_key: [u8; 32] = hex::decode(public_key)?
+        .try_into()
+        .map_err(|_| anyhow!("public key must be 32 bytes"))?;
+    let signature: [u8; 64] = hex::decode(signature)?
+        .try_into()
+        .map_err(|_| anyhow!("signature must be 64 bytes"))?;
+    VerifyingKey::from_bytes(&public_key)?
+        .verify_strict(&signed_message(version, digest), &Signature::from_bytes(&signature))
+        .map_err(|_| anyhow!("bad signature"))
+}
+
+fn main() -> Result<()> {
+    match Args::parse().command {
+        Command::Keygen { out } => keygen(&out),
+        Command::Sign {
+            key,
+            version,
+            url,
+            image,
+        } => sign(&key, &version, url.as_deref(), &image),
+        Command::Verify {
+            public_key,
+            version,
+            signature,
+            image,
+        } => verify(&public_key, &version, &signature, &image),
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn key() -> SigningKey {
+        SigningKey::from_bytes(&[7; 32])
+    }
+
+    #[test]
+    fn signature_verifies() {
+        let key = key();
+        let public_key = hex::encode(key.verifying_key().to_bytes());
+        let digest = Sha256::digest(b"image").to_vec();
+        let signature = sign_digest(&key, "0.2.0", &digest);
+
+        verify_digest(&public_key, "0.2.0", &signature, &digest).unwrap();
+    }
+
+    #[test]
+    fn relabeled_or_changed_image_is_refused() {
+        let key = key();
+        let public_key = hex::encode(key.verifying_key().to_bytes());
+        let digest = Sha256::digest(b"image").to_vec();
+        let signature = sign_digest(&key, "0.2.0", &digest);
+
+        assert!(verify_digest(&public_key, "0.3.0", &signature, &digest).is_err());
+        let other = Sha256::digest(b"other image").to_vec();
+        assert!(verify_digest(&public_key, "0.2.0", &signature, &other).is_err());
+    }
+}
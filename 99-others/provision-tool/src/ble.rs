@@ -0,0 +1,97 @@
+// real ble peripheral via btleplug
+
+use std::time::Duration;
+
+use anyhow::{anyhow, bail, Context, Result};
+use btleplug::api::{Central, Characteristic, Manager as _, Peripheral as _, ScanFilter, WriteType};
+use btleplug::platform::{Manager, Peripheral};
+use uuid::Uuid;
+
+use crate::protocol::{self, NAME_PREFIX, SERVICE_UUID};
+
+pub struct BlePeripheral {
+    peripheral: Peripheral,
+    characteristics: Vec<Characteristic>,
+}
+
+impl BlePeripheral {
+    // scan for provisioning devices, connect to `name` or the first one found
+    pub async fn connect(name: Option<&str>, scan: Duration) -> Result<Self> {
+        let manager = Manager::new().await?;
+        let adapter = manager
+            .adapters()
+            .await?
+            .into_iter()
+            .next()
+            .ok_or_else(|| anyhow!("no bluetooth adapter"))?;
+
+        println!("scanning {:?} ...", scan);
+        adapter
+            .start_scan(ScanFilter {
+                services: vec![SERVICE_UUID],
+            })
+            .await?;
+        tokio::time::sleep(scan).await;
+        adapter.stop_scan().await?;
+
+        let mut found = Vec::new();
+        for peripheral in adapter.peripherals().await? {
+            let Some(properties) = peripheral.properties().await? else {
+                continue;
+            };
+            let local_name = properties.local_name.unwrap_or_default();
+            let matches = match name {
+                Some(name) => local_name == name,
+                None => local_name.starts_with(NAME_PREFIX) || properties.services.contains(&SERVICE_UUID),
+            };
+            if matches {
+                println!("found {} ({})", local_name, peripheral.address());
+                found.push(peripheral);
+            }
+        }
+        let Some(peripheral) = found.into_iter().next() else {
+            bail!("no provisioning device found, is it in provisioning mode?");
+        };
+
+        peripheral.connect().await.context("connect")?;
+        peripheral.discover_services().await.context("discover services")?;
+        let characteristics = peripheral
+            .characteristics()
+            .into_iter()
+            .filter(|c| c.service_uuid == protocol::SERVICE_UUID)
+            .collect::<Vec<_>>();
+        if characteristics.is_empty() {
+            bail!("device does not expose the provisioning service");
+        }
+        Ok(Self {
+            peripheral,
+            characteristics,
+        })
+    }
+
+    fn characteristic(&self, uuid: Uuid) -> Result<&Characteristic> {
+        self.characteristics
+            .iter()
+            .find(|c| c.uuid == uuid)
+            .ok_or_else(|| anyhow!("characteristic {} not found", uuid))
+    }
+
+    pub async fn disconnect(&self) -> Result<()> {
+        Ok(self.peripheral.disconnect().await?)
+    }
+}
+
+impl protocol::Peripheral for BlePeripheral {
+    async fn write(&mut self, characteristic: Uuid, value: &[u8]) -> Result<()> {
+        let characteristic = self.characteristic(characteristic)?;
+        self.peripheral
+            .write(characteristic, value, WriteType::WithResponse)
+            .await
+            .with_context(|| format!("write {}, paired with the right passkey?", characteristic.uuid))
+    }
+
+    async fn read(&mut self, characteristic: Uuid) -> Result<Vec<u8>> {
+        let characteristic = self.characteristic(characteristic)?;
+        Ok(self.peripheral.read(characteristic).await?)
+    }
+}
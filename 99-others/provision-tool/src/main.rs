@@ -0,0 +1,76 @@
+// funny-provision
+// write wifi & mqtt settings to a board in ble provisioning mode
+//
+//   funny-provision --ssid home --psk secret --mqtt-host mqtt://192.168.1.10:1883
+//   funny-provision --simulate --ssid home --psk secret
+
+#[cfg(feature = "ble")]
+mod ble;
+mod protocol;
+mod sim;
+
+use std::time::Duration;
+
+use anyhow::Result;
+use clap::Parser;
+use protocol::Credentials;
+
+#[derive(Parser, Debug)]
+#[command(version, about = "provision funny_games devices over ble")]
+struct Args {
+    /// wifi name
+    #[arg(long)]
+    ssid: Option<String>,
+    /// wifi password
+    #[arg(long)]
+    psk: Option<String>,
+    /// eg mqtt://192.168.1.10:1883
+    #[arg(long)]
+    mqtt_host: Option<String>,
+    #[arg(long)]
+    mqtt_user: Option<String>,
+    #[arg(long)]
+    mqtt_pass: Option<String>,
+    /// advertised name, eg funny-pumper-1a2b, default the first device found
+    #[arg(long)]
+    device: Option<String>,
+    /// seconds to scan for devices
+    #[arg(long, default_value_t = 5)]
+    scan_secs: u64,
+    /// run against a simulated device instead of real ble
+    #[arg(long)]
+    simulate: bool,
+}
+
+#[tokio::main]
+async fn main() -> Result<()> {
+    let args = Args::parse();
+    let credentials = Credentials {
+        wifi_ssid: args.ssid,
+        wifi_psk: args.psk,
+        mqtt_host: args.mqtt_host,
+        mqtt_user: args.mqtt_user,
+        mqtt_pass: args.mqtt_pass,
+    };
+
+    if args.simulate {
+        let mut device = sim::SimulatedPeripheral::new(sim::DeviceSettings::default());
+        protocol::provision(&mut device, &credentials).await?;
+        println!("simulated device saved: {:?}", device.saved);
+        return Ok(());
+    }
+
+    #[cfg(feature = "ble")]
+    {
+        let mut device = ble::BlePeripheral::connect(args.device.as_deref(), Duration::from_secs(args.scan_secs)).await?;
+        let result = protocol::provision(&mut device, &credentials).await;
+        // the device reboots after saving, a failed disconnect is expected then
+        let _ = device.disconnect().await;
+        result
+    }
+    #[cfg(not(feature = "ble"))]
+    {
+        let _ = (args.device, Duration::from_secs(args.scan_secs));
+        anyhow::bail!("built without the `ble` feature, only --simulate is available")
+    }
+}
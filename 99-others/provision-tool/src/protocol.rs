@@ -0,0 +1,80 @@
+// ble provisioning protocol
+// keep the uuids in sync with pumper/src/ble_provision.rs
+
+use std::time::Duration;
+
+use anyhow::{bail, Result};
+use uuid::Uuid;
+
+// only the ble transport scans for these
+#[cfg_attr(not(feature = "ble"), allow(dead_code))]
+pub const SERVICE_UUID: Uuid = Uuid::from_u128(0xf0a40001_6a8f_4f5b_9d2e_8c3b1f2a0c01);
+pub const WIFI_SSID_UUID: Uuid = Uuid::from_u128(0xf0a40002_6a8f_4f5b_9d2e_8c3b1f2a0c01);
+pub const WIFI_PSK_UUID: Uuid = Uuid::from_u128(0xf0a40003_6a8f_4f5b_9d2e_8c3b1f2a0c01);
+pub const MQTT_HOST_UUID: Uuid = Uuid::from_u128(0xf0a40004_6a8f_4f5b_9d2e_8c3b1f2a0c01);
+pub const MQTT_USER_UUID: Uuid = Uuid::from_u128(0xf0a40005_6a8f_4f5b_9d2e_8c3b1f2a0c01);
+pub const MQTT_PASS_UUID: Uuid = Uuid::from_u128(0xf0a40006_6a8f_4f5b_9d2e_8c3b1f2a0c01);
+pub const COMMAND_UUID: Uuid = Uuid::from_u128(0xf0a40007_6a8f_4f5b_9d2e_8c3b1f2a0c01);
+pub const STATUS_UUID: Uuid = Uuid::from_u128(0xf0a40008_6a8f_4f5b_9d2e_8c3b1f2a0c01);
+
+// devices advertise as "funny-pumper-xxxx"
+#[cfg_attr(not(feature = "ble"), allow(dead_code))]
+pub const NAME_PREFIX: &str = "funny-";
+
+const STATUS_POLL: Duration = Duration::from_millis(200);
+const STATUS_TIMEOUT: Duration = Duration::from_secs(10);
+
+// what we write, None keeps the value on the device
+#[derive(Clone, Debug, Default)]
+pub struct Credentials {
+    pub wifi_ssid: Option<String>,
+    pub wifi_psk: Option<String>,
+    pub mqtt_host: Option<String>,
+    pub mqtt_user: Option<String>,
+    pub mqtt_pass: Option<String>,
+}
+
+impl Credentials {
+    fn fields(&self) -> [(Uuid, &'static str, Option<&String>); 5] {
+        [
+            (WIFI_SSID_UUID, "wifi_ssid", self.wifi_ssid.as_ref()),
+            (WIFI_PSK_UUID, "wifi_psk", self.wifi_psk.as_ref()),
+            (MQTT_HOST_UUID, "mqtt_host", self.mqtt_host.as_ref()),
+            (MQTT_USER_UUID, "mqtt_user", self.mqtt_user.as_ref()),
+            (MQTT_PASS_UUID, "mqtt_pass", self.mqtt_pass.as_ref()),
+        ]
+    }
+}
+
+// the gatt operations provisioning needs, real ble or simulated
+pub trait Peripheral {
+    async fn write(&mut self, characteristic: Uuid, value: &[u8]) -> Result<()>;
+    async fn read(&mut self, characteristic: Uuid) -> Result<Vec<u8>>;
+}
+
+// write settings, commit, wait for the device to confirm
+pub async fn provision<P: Peripheral>(peripheral: &mut P, credentials: &Credentials) -> Result<()> {
+    for (uuid, name, value) in credentials.fields() {
+        if let Some(value) = value {
+            println!("write {}", name);
+            peripheral.write(uuid, value.as_bytes()).await?;
+        }
+    }
+
+    println!("commit");
+    peripheral.write(COMMAND_UUID, b"commit").await?;
+
+    let started = tokio::time::Instant::now();
+    while started.elapsed() < STATUS_TIMEOUT {
+        let status = String::from_utf8_lossy(&peripheral.read(STATUS_UUID).await?).into_owned();
+        match status.as_str() {
+            "saved" => {
+                println!("saved, device is restarting into station mode");
+                return Ok(());
+            }
+            error if error.starts_with("error") => bail!("device refused: {}", error),
+            _ => tokio::time::sleep(STATUS_POLL).await,
+        }
+    }
+    bail!("device did not confirm within {:?}", STATUS_TIMEOUT)
+}
@@ -0,0 +1,161 @@
+// simulated provisioning peripheral
+//
+// mirrors the state machine in pumper/src/ble_provision.rs, so the tool can be
+// exercised without a board: `funny-provision --simulate --ssid ...`
+
+use std::time::Duration;
+
+use anyhow::{bail, Result};
+use uuid::Uuid;
+
+use crate::protocol::*;
+
+#[derive(Clone, Debug, Default)]
+pub struct DeviceSettings {
+    pub wifi_ssid: String,
+    pub wifi_psk: String,
+    pub mqtt_host: String,
+    pub mqtt_user: String,
+    pub mqtt_pass: String,
+}
+
+pub struct SimulatedPeripheral {
+    current: DeviceSettings,
+    pending: DeviceSettings,
+    status: String,
+    // what ended up in "nvs"
+    pub saved: Option<DeviceSettings>,
+    // radio latency
+    delay: Duration,
+}
+
+impl SimulatedPeripheral {
+    pub fn new(current: DeviceSettings) -> Self {
+        Self {
+            pending: current.clone(),
+            current,
+            status: "idle".into(),
+            saved: None,
+            delay: Duration::from_millis(20),
+        }
+    }
+
+    fn command(&mut self, command: &[u8]) {
+        self.status = match command {
+            b"commit" => match check(&self.pending) {
+                Err(e) => format!("error: {}", e),
+                Ok(_) => {
+                    self.saved = Some(self.pending.clone());
+                    "saved".into()
+                }
+            },
+            b"cancel" => {
+                self.pending = self.current.clone();
+                "idle".into()
+            }
+            other => format!("error: unknown command {}", String::from_utf8_lossy(other)),
+        };
+    }
+}
+
+// Settings::check & funny_core::wifi::check_credentials
+fn check(settings: &DeviceSettings) -> Result<(), &'static str> {
+    let (ssid, psk) = (&settings.wifi_ssid, &settings.wifi_psk);
+    if ssid.is_empty() {
+        return Err("wifi ssid is required");
+    }
+    if ssid.len() > 32 {
+        return Err("wifi ssid is longer than 32 bytes");
+    }
+    if !psk.is_empty() && !(8..=64).contains(&psk.len()) {
+        return Err("wifi psk must be 8 to 64 characters");
+    }
+    Ok(())
+}
+
+impl Peripheral for SimulatedPeripheral {
+    async fn write(&mut self, characteristic: Uuid, value: &[u8]) -> Result<()> {
+        tokio::time::sleep(self.delay).await;
+        let text = String::from_utf8_lossy(value).into_owned();
+        match characteristic {
+            WIFI_SSID_UUID => self.pending.wifi_ssid = text,
+            WIFI_PSK_UUID => self.pending.wifi_psk = text,
+            MQTT_HOST_UUID => self.pending.mqtt_host = text,
+            MQTT_USER_UUID => self.pending.mqtt_user = text,
+            MQTT_PASS_UUID => self.pending.mqtt_pass = text,
+            COMMAND_UUID => {
+                self.command(value);
+                return Ok(());
+            }
+            other => bail!("characteristic {} is not writable", other),
+        }
+        self.status = "pending".into();
+        Ok(())
+    }
+
+    async fn read(&mut self, characteristic: Uuid) -> Result<Vec<u8>> {
+        tokio::time::sleep(self.delay).await;
+        match characteristic {
+            STATUS_UUID => Ok(self.status.clone().into_bytes()),
+            other => bail!("characteristic {} is not readable", other),
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn device() -> SimulatedPeripheral {
+        SimulatedPeripheral::new(DeviceSettings {
+            mqtt_host: "mqtt://192.168.1.10:1883".into(),
+            ..Default::default()
+        })
+    }
+
+    #[tokio::test]
+    async fn commit_saves_written_values() {
+        let mut device = device();
+        let credentials = Credentials {
+            wifi_ssid: Some("home".into()),
+            wifi_psk: Some("12345678".into()),
+            mqtt_user: Some("user".into()),
+            ..Default::default()
+        };
+        provision(&mut device, &credentials).await.unwrap();
+
+        let saved = device.saved.expect("settings saved");
+        assert_eq!(saved.wifi_ssid, "home");
+        assert_eq!(saved.wifi_psk, "12345678");
+        assert_eq!(saved.mqtt_user, "user");
+        // not written, kept
+        assert_eq!(saved.mqtt_host, "mqtt://192.168.1.10:1883");
+    }
+
+    #[tokio::test]
+    async fn commit_without_ssid_is_refused() {
+        let mut device = device();
+        let credentials = Credentials {
+            wifi_psk: Some("12345678".into()),
+            ..Default::default()
+        };
+        let error = provision(&mut device, &credentials).await.unwrap_err();
+
+        assert!(error.to_string().contains("wifi ssid is required"), "{}", error);
+        assert!(device.saved.is_none());
+    }
+
+    #[tokio::test]
+    async fn commit_with_short_psk_is_refused() {
+        let mut device = device();
+        let credentials = Credentials {
+            wifi_ssid: Some("home".into()),
+            wifi_psk: Some("1234".into()),
+            ..Default::default()
+        };
+        let error = provision(&mut device, &credentials).await.unwrap_err();
+
+        assert!(error.to_string().contains("8 to 64"), "{}", error);
+        assert!(device.saved.is_none());
+    }
+}
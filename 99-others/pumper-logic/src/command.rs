@@ -0,0 +1,24 @@
+// cloud commands, json on `mqtt_subscribe_topic`
+//
+//   {"method":"cmd","params":{"Reboot":null},"id":1}
+//   {"method":"cmd","params":{"Ota":{"url":"..","version":"0.2.0","signature":".."}},"id":2}
+
+use serde::{Deserialize, Serialize};
+
+#[derive(Serialize, Deserialize, Debug, Clone, PartialEq)]
+pub enum Instruct {
+    Volumn(u32),
+    Reboot,
+    // firmware update, `url` to the .bin, http:// or https://
+    // `signature` from ota-sign, hex
+    Ota { url: String, version: String, signature: String },
+    // remote log level, "off", "error", "warn", "info", "debug" or "trace", until reboot
+    LogLevel(String),
+}
+
+#[derive(Serialize, Deserialize, Debug, Clone, PartialEq)]
+pub struct CloudCommand {
+    pub method: String,
+    pub params: Instruct,
+    pub id: u32,
+}
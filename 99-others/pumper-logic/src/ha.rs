@@ -0,0 +1,177 @@
+// home assistant mqtt discovery & commands
+// https://www.home-assistant.io/integrations/mqtt/#mqtt-discovery
+//
+// every entity gets a retained config message under
+// <prefix>/<component>/<node_id>/<object_id>/config
+// and reads its value from one shared json state topic. the board publishes `discovery`,
+// the mqtt client is its own.
+
+use serde_json::{json, Value};
+
+// watering volume entity range, as ml. what HA offers is also all the board takes
+pub const VOLUME_MIN: u32 = 10;
+pub const VOLUME_MAX: u32 = 500;
+
+#[derive(Clone, Debug)]
+pub struct HaTopics {
+    pub prefix: String,
+    pub node_id: String,
+    // empty if availability is disabled
+    pub availability: String,
+    // battery voltage & charge entities
+    pub battery: bool,
+}
+
+impl HaTopics {
+    pub fn new(prefix: &str, node_id: &str, availability: &str) -> Self {
+        Self {
+            prefix: prefix.to_string(),
+            node_id: node_id.to_string(),
+            availability: availability.to_string(),
+            battery: false,
+        }
+    }
+
+    // pumper telemetry json goes here
+    pub fn state(&self) -> String {
+        format!("funny_games/{}/state", self.node_id)
+    }
+
+    // HA switch sends "ON" / "OFF"
+    pub fn relay_command(&self) -> String {
+        format!("funny_games/{}/relay/set", self.node_id)
+    }
+
+    // HA number sends the volume as plain text, eg "50"
+    pub fn volume_command(&self) -> String {
+        format!("funny_games/{}/volume/set", self.node_id)
+    }
+
+    fn config(&self, component: &str, object_id: &str) -> String {
+        format!(
+            "{}/{}/{}/{}/config",
+            self.prefix, component, self.node_id, object_id
+        )
+    }
+
+    fn device(&self, sw_version: &str) -> Value {
+        json!({
+            "identifiers": [self.node_id],
+            "name": "pumper",
+            "model": "esp32c3 pumper",
+            "manufacturer": "funny_games",
+            "sw_version": sw_version,
+        })
+    }
+
+    fn entity(&self, sw_version: &str, object_id: &str, name: &str) -> Value {
+        let mut entity = json!({
+            "name": name,
+            "unique_id": format!("{}_{}", self.node_id, object_id),
+            "object_id": format!("{}_{}", self.node_id, object_id),
+            "state_topic": self.state(),
+            "device": self.device(sw_version),
+        });
+        if !self.availability.is_empty() {
+            entity["availability_topic"] = json!(self.availability);
+        }
+        entity
+    }
+
+    // all entities of the pumper, as (component, object_id, config)
+    fn entities(&self, sw_version: &str) -> Vec<(&'static str, &'static str, Value)> {
+        let mut moisture = self.entity(sw_version, "solid_humidity", "Soil Moisture");
+        moisture["device_class"] = json!("moisture");
+        moisture["unit_of_measurement"] = json!("%");
+        moisture["state_class"] = json!("measurement");
+        moisture["value_template"] = json!("{{ value_json.solid_humidity }}");
+
+        let mut temperature = self.entity(sw_version, "temperature", "Temperature");
+        temperature["device_class"] = json!("temperature");
+        temperature["unit_of_measurement"] = json!("°C");
+        temperature["state_class"] = json!("measurement");
+        temperature["value_template"] = json!("{{ value_json.environment_temperature }}");
+
+        let mut humidity = self.entity(sw_version, "humidity", "Humidity");
+        humidity["device_class"] = json!("humidity");
+        humidity["unit_of_measurement"] = json!("%");
+        humidity["state_class"] = json!("measurement");
+        humidity["value_template"] = json!("{{ value_json.environment_humidity }}");
+
+        let mut relay = self.entity(sw_version, "relay", "Pump");
+        relay["command_topic"] = json!(self.relay_command());
+        relay["value_template"] = json!("{{ 'ON' if value_json.relay else 'OFF' }}");
+        relay["icon"] = json!("mdi:water-pump");
+
+        let mut volume = self.entity(sw_version, "pumper_volume", "Watering Volume");
+        volume["command_topic"] = json!(self.volume_command());
+        volume["value_template"] = json!("{{ value_json.pumper_volume }}");
+        volume["unit_of_measurement"] = json!("mL");
+        volume["min"] = json!(VOLUME_MIN);
+        volume["max"] = json!(VOLUME_MAX);
+        volume["step"] = json!(10);
+        volume["mode"] = json!("box");
+        volume["icon"] = json!("mdi:cup-water");
+
+        let mut rssi = self.entity(sw_version, "wifi_rssi", "WiFi Signal");
+        rssi["device_class"] = json!("signal_strength");
+        rssi["unit_of_measurement"] = json!("dBm");
+        rssi["state_class"] = json!("measurement");
+        rssi["entity_category"] = json!("diagnostic");
+        rssi["value_template"] = json!("{{ value_json.wifi_rssi }}");
+
+        let mut entities = vec![
+            ("sensor", "solid_humidity", moisture),
+            ("sensor", "temperature", temperature),
+            ("sensor", "humidity", humidity),
+            ("switch", "relay", relay),
+            ("number", "pumper_volume", volume),
+            ("sensor", "wifi_rssi", rssi),
+        ];
+        if self.battery {
+            let mut voltage = self.entity(sw_version, "battery_voltage", "Battery Voltage");
+            voltage["device_class"] = json!("voltage");
+            voltage["unit_of_measurement"] = json!("V");
+            voltage["state_class"] = json!("measurement");
+            voltage["entity_category"] = json!("diagnostic");
+            voltage["value_template"] = json!("{{ value_json.battery_voltage }}");
+
+            let mut soc = self.entity(sw_version, "battery_soc", "Battery");
+            soc["device_class"] = json!("battery");
+            soc["unit_of_measurement"] = json!("%");
+            soc["state_class"] = json!("measurement");
+            soc["value_template"] = json!("{{ value_json.battery_soc }}");
+
+            entities.push(("sensor", "battery_voltage", voltage));
+            entities.push(("sensor", "battery_soc", soc));
+        }
+        entities
+    }
+
+    // retained discovery configs as (topic, config), once the client is connected
+    pub fn discovery(&self, sw_version: &str) -> Vec<(String, Value)> {
+        self.entities(sw_version)
+            .into_iter()
+            .map(|(component, object_id, config)| (self.config(component, object_id), config))
+            .collect()
+    }
+}
+
+// "ON"/"OFF" from HA switch
+pub fn parse_switch(data: &[u8]) -> Option<bool> {
+    match core::str::from_utf8(data).ok()?.trim() {
+        "ON" => Some(true),
+        "OFF" => Some(false),
+        _ => None,
+    }
+}
+
+// HA number may send "50" or "50.0", None outside VOLUME_MIN..=VOLUME_MAX
+pub fn parse_number(data: &[u8]) -> Option<u32> {
+    let val = core::str::from_utf8(data).ok()?.trim().parse::<f32>().ok()?;
+    // nan & inf fail the range check too
+    if !(VOLUME_MIN as f32..=VOLUME_MAX as f32).contains(&val) {
+        return None;
+    }
+    Some(val as u32)
+}
@@ -0,0 +1,16 @@
+// pumper rules without esp-idf
+//
+//   ota        what an ota signature covers, shared with ../ota-sign
+//   watering   moisture filter & calibration, when to water, pump flow
+//   ha         home assistant topics, discovery configs & command payloads
+//   command    cloud commands
+//   telemetry  the telemetry json
+//
+// built into the firmware and ../pumper-sim, so the simulator can not drift from the board.
+// no esp-idf-svc in here, `cargo test` runs on the host
+
+pub mod command;
+pub mod ha;
+pub mod ota;
+pub mod telemetry;
+pub mod watering;
@@ -0,0 +1,11 @@
+// ota signatures
+//
+// ota-sign signs & the pumper verifies the same bytes:
+//   "funny-ota-v1\0" + version + "\0" + sha256(image)
+
+pub const SIGNATURE_DOMAIN: &[u8] = b"funny-ota-v1\0";
+
+// what the signature covers, the version is signed too so an old image can not be relabeled
+pub fn signed_message(version: &str, digest: &[u8]) -> Vec<u8> {
+    [SIGNATURE_DOMAIN, version.as_bytes(), b"\0", digest].concat()
+}
@@ -0,0 +1,52 @@
+// telemetry json, to `mqtt_topic` & the home assistant state topic
+//
+// a field is null until known, the event fields (`button`, `pump_refused`) are only there in
+// the publish right after the event
+
+use serde::{Deserialize, Serialize};
+
+#[derive(Serialize, Deserialize, Debug, Default, Clone, PartialEq)]
+pub struct MqttMsg {
+    pub solid_humidity: Option<u32>,
+    pub relay: Option<bool>,
+    pub amount_total: Option<u32>,
+    pub environment_temperature: Option<i32>,
+    pub environment_humidity: Option<u32>,
+    pub pumper_volume: Option<u32>,
+    pub mqtt_broker: Option<String>,
+    pub wifi_ssid: Option<String>,
+    pub wifi_rssi: Option<i8>,
+    pub battery_voltage: Option<f32>,
+    pub battery_soc: Option<u32>,
+    // deep sleep mode only, samples since the last publish, oldest first
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub samples: Option<Vec<BatchSample>>,
+    // float switch, only with water_level_sensor
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub water_low: Option<bool>,
+    // "water", "pause" or "factory_reset", only in the publish right after the press
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub button: Option<String>,
+    // "battery_low" or "water_low", only in the publish right after a pump start was refused
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub pump_refused: Option<String>,
+}
+
+// one deep sleep wake-up, kept in rtc memory until published
+#[derive(Clone, Copy, Debug, Default, PartialEq, Serialize, Deserialize)]
+pub struct Sample {
+    pub solid_humidity: u8,
+    pub environment_temperature: i8,
+    pub environment_humidity: u8,
+    // wake-up the sample was taken on
+    #[serde(skip)]
+    pub wakeup: u32,
+}
+
+#[derive(Clone, Debug, PartialEq, Serialize, Deserialize)]
+pub struct BatchSample {
+    #[serde(flatten)]
+    pub sample: Sample,
+    // seconds before the publish
+    pub age: u32,
+}
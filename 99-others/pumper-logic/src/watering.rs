@@ -0,0 +1,100 @@
+// watering rules, soil sensor to pump
+//
+//   MOISTURE_READS raw adc --filter_moisture--> Calibration::humidity --should_water--> pump
+//
+// the capacitive sensor reads high in dry air and low in water, both ends differ from sensor
+// to sensor. the compiled in ends fit the "Capacltlve Soll Molsture Sensor v2.0", the board
+// measures its own (see pumper/src/calibration.rs), humidity is linear in between.
+
+use serde::{Deserialize, Serialize};
+
+// pumper flow, as ”X ml/min“ usually can be found at motors
+pub const PUMPER_FLOW: u32 = 50;
+// range of the moisture sensor in water & air, defaults until calibrated
+pub const MOISTURE_IN_WATER: u16 = 1450;
+pub const MOISTURE_IN_AIR: u16 = 2837;
+// one soil pass is this many readings, a second apart
+pub const MOISTURE_READS: usize = 10;
+// dry & wet closer than this, one of them was measured wrong
+const MIN_SPAN: u16 = 200;
+
+#[derive(Clone, Copy, Debug, PartialEq, Serialize, Deserialize)]
+#[serde(rename_all = "lowercase")]
+pub enum Point {
+    // sensor in the air
+    Dry,
+    // sensor in a glass of water, up to the line
+    Wet,
+}
+
+// raw adc of the sensor in air & in water
+#[derive(Clone, Copy, Debug, PartialEq)]
+pub struct Calibration {
+    pub dry: u16,
+    pub wet: u16,
+}
+
+impl Default for Calibration {
+    fn default() -> Self {
+        Self {
+            dry: MOISTURE_IN_AIR,
+            wet: MOISTURE_IN_WATER,
+        }
+    }
+}
+
+impl Calibration {
+    // refused if the ends are too close or the wrong way round
+    pub fn new(dry: u16, wet: u16) -> Result<Self, String> {
+        if dry < wet.saturating_add(MIN_SPAN) {
+            return Err(format!("dry {} must be at least {} above wet {}", dry, MIN_SPAN, wet));
+        }
+        Ok(Self { dry, wet })
+    }
+
+    // `raw` as the new dry or wet end
+    pub fn with(self, point: Point, raw: u16) -> Result<Self, String> {
+        match point {
+            Point::Dry => Self::new(raw, self.wet),
+            Point::Wet => Self::new(self.dry, raw),
+        }
+    }
+
+    // outside the calibrated range, the sensor is loose or broken
+    pub fn in_range(&self, raw: u16) -> bool {
+        (self.wet..=self.dry).contains(&raw)
+    }
+
+    // raw adc to humidity in %, clamped to 0..=100
+    pub fn humidity(&self, raw: u16) -> u32 {
+        let (dry, wet) = (self.dry as u32, self.wet as u32);
+        let raw = (raw as u32).clamp(wet, dry);
+        (dry - raw) * 100 / (dry - wet)
+    }
+}
+
+// MOISTURE_READS readings, min & max dropped, the rest averaged. None if reads failed
+pub fn filter_moisture(moistures: &[u16]) -> Option<u16> {
+    if moistures.len() != MOISTURE_READS {
+        return None;
+    }
+    let min_value = *moistures.iter().min()?;
+    let max_value = *moistures.iter().max()?;
+    let sum = moistures.iter().map(|&val| val as u32).sum::<u32>();
+    Some(((sum - min_value as u32 - max_value as u32) / (MOISTURE_READS as u32 - 2)) as u16)
+}
+
+// automatic watering, dry soil & no frost
+pub fn should_water(temperature: i32, solid_humidity: u32) -> bool {
+    temperature >= 2 && solid_humidity < 30
+}
+
+// volume:water to pump,as ml
+pub fn convert_volume_to_pumperworking_time_ms(volume: u32) -> u32 {
+    volume * 60 * 1000 / PUMPER_FLOW
+}
+
+// what a run of `runtime_ms` pumped, as ml
+pub fn watered_ml(runtime_ms: u32) -> u32 {
+    runtime_ms * PUMPER_FLOW / (60 * 1000)
+}
@@ -0,0 +1,40 @@
+// cloud command & telemetry json, what the backend sends & reads
+
+use pumper_logic::command::{CloudCommand, Instruct};
+use pumper_logic::telemetry::{BatchSample, MqttMsg, Sample};
+
+#[test]
+fn cloud_commands() {
+    let command: CloudCommand = serde_json::from_str(r#"{"method":"cmd","params":{"Reboot":null},"id":1}"#).unwrap();
+    assert_eq!(command.params, Instruct::Reboot);
+    assert_eq!(command.id, 1);
+
+    let command: CloudCommand =
+        serde_json::from_str(r#"{"method":"cmd","params":{"Ota":{"url":"http://x/fw.bin","version":"0.2.0","signature":"ab"}},"id":2}"#)
+            .unwrap();
+    assert!(matches!(command.params, Instruct::Ota { ref version, .. } if version == "0.2.0"));
+}
+
+#[test]
+fn telemetry_json() {
+    let msg = MqttMsg {
+        environment_temperature: Some(-3),
+        samples: Some(vec![BatchSample {
+            sample: Sample {
+                solid_humidity: 40,
+                wakeup: 7,
+                ..Default::default()
+            },
+            age: 600,
+        }]),
+        ..Default::default()
+    };
+    let json = serde_json::to_value(&msg).unwrap();
+    assert_eq!(json["environment_temperature"], -3);
+    // unknown values are null, events left out
+    assert!(json["relay"].is_null());
+    assert!(json.get("button").is_none());
+    assert_eq!(json["samples"][0]["solid_humidity"], 40);
+    assert_eq!(json["samples"][0]["age"], 600);
+    assert!(json["samples"][0].get("wakeup").is_none());
+}
@@ -0,0 +1,51 @@
+// home assistant discovery & command payloads, what HA sees of the board & the simulator
+
+use pumper_logic::ha::{parse_number, parse_switch, HaTopics, VOLUME_MAX, VOLUME_MIN};
+
+#[test]
+fn discovery_configs() {
+    let mut ha = HaTopics::new("homeassistant", "pumper_1", "pumper/availability");
+    let discovery = ha.discovery("0.2.0");
+    let (topic, relay) = discovery
+        .iter()
+        .find(|(topic, _)| topic.ends_with("/relay/config"))
+        .expect("relay switch");
+    assert_eq!(topic, "homeassistant/switch/pumper_1/relay/config");
+    assert_eq!(relay["command_topic"], "funny_games/pumper_1/relay/set");
+    assert_eq!(relay["state_topic"], "funny_games/pumper_1/state");
+    assert_eq!(relay["availability_topic"], "pumper/availability");
+    assert_eq!(relay["device"]["sw_version"], "0.2.0");
+
+    let (_, volume) = discovery
+        .iter()
+        .find(|(topic, _)| topic.ends_with("/pumper_volume/config"))
+        .expect("volume number");
+    assert_eq!(volume["min"], VOLUME_MIN);
+    assert_eq!(volume["max"], VOLUME_MAX);
+
+    let entities = discovery.len();
+    ha.battery = true;
+    assert_eq!(ha.discovery("0.2.0").len(), entities + 2);
+}
+
+#[test]
+fn command_payloads() {
+    assert_eq!(parse_switch(b"ON"), Some(true));
+    assert_eq!(parse_switch(b"OFF\n"), Some(false));
+    assert_eq!(parse_switch(b"on"), None);
+    assert_eq!(parse_number(b"50"), Some(50));
+    assert_eq!(parse_number(b"30.0"), Some(30));
+    assert_eq!(parse_number(b"-1"), None);
+}
+
+// anything HA would not offer never reaches the pump, 1e9 ml would overflow the run time
+#[test]
+fn volume_out_of_range() {
+    assert_eq!(parse_number(b"0"), None);
+    assert_eq!(parse_number(b"nan"), None);
+    assert_eq!(parse_number(b"inf"), None);
+    assert_eq!(parse_number(b"1e9"), None);
+    assert_eq!(parse_number(b"9.5"), None);
+    assert_eq!(parse_number(b"500"), Some(VOLUME_MAX));
+    assert_eq!(parse_number(b"10"), Some(VOLUME_MIN));
+}
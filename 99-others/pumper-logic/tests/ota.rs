@@ -0,0 +1,18 @@
+// the exact bytes ota-sign signs & the pumper verifies, a change here breaks every device
+// in the field
+
+use pumper_logic::ota::signed_message;
+
+// sha256("funny")
+const DIGEST: &[u8] = b"\xb2\x2d\x1d\x8f\xe5\x75\x25\x33\x95\x40\x28\x17\x2c\x9b\xf3\xac\x01\xb5\x7f\x40\xc8\x29\x46\xa3\xe7\xb1\xea\xff\x38\x9e\x2b\x87";
+const MESSAGE: &[u8] = b"funny-ota-v1\x000.2.0\x00\xb2\x2d\x1d\x8f\xe5\x75\x25\x33\x95\x40\x28\x17\x2c\x9b\xf3\xac\x01\xb5\x7f\x40\xc8\x29\x46\xa3\xe7\xb1\xea\xff\x38\x9e\x2b\x87";
+
+#[test]
+fn signed_message_bytes() {
+    assert_eq!(signed_message("0.2.0", DIGEST), MESSAGE);
+}
+
+#[test]
+fn version_is_covered() {
+    assert_ne!(signed_message("0.2.1", DIGEST), MESSAGE);
+}
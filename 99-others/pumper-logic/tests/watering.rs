@@ -0,0 +1,40 @@
+// the rules the board & the simulator water by
+
+use pumper_logic::watering::{filter_moisture, should_water, Calibration, Point, MOISTURE_IN_AIR, MOISTURE_IN_WATER};
+
+#[test]
+fn humidity_follows_the_calibration() {
+    let default = Calibration::default();
+    assert_eq!(default.humidity(MOISTURE_IN_AIR), 0);
+    assert_eq!(default.humidity(MOISTURE_IN_WATER), 100);
+    // clamped outside the ends
+    assert_eq!(default.humidity(4000), 0);
+    assert_eq!(default.humidity(1000), 100);
+
+    let calibrated = Calibration::new(2600, 1600).unwrap();
+    assert_eq!(calibrated.humidity(2100), 50);
+    assert!(!calibrated.in_range(2700));
+}
+
+#[test]
+fn calibration_ends_too_close_are_refused() {
+    let default = Calibration::default();
+    assert!(default.with(Point::Dry, MOISTURE_IN_WATER + 100).is_err());
+    assert!(default.with(Point::Wet, MOISTURE_IN_AIR).is_err());
+    assert_eq!(default.with(Point::Wet, 1500).unwrap().wet, 1500);
+}
+
+#[test]
+fn moisture_filter_drops_the_extremes() {
+    let reads = [2000, 2000, 2000, 2000, 2000, 2000, 2000, 2000, 100, 4000];
+    assert_eq!(filter_moisture(&reads), Some(2000));
+    // a failed read, no pass
+    assert_eq!(filter_moisture(&reads[..9]), None);
+}
+
+#[test]
+fn no_watering_in_frost() {
+    assert!(should_water(20, 29));
+    assert!(!should_water(20, 30));
+    assert!(!should_water(-5, 10));
+}
@@ -0,0 +1,53 @@
+// embedded mqtt broker, 3.1.1 on one port, no auth, no tls
+//
+// enough for the simulator & the integration tests. point `--broker` at mosquitto
+// (docker run -p 1883:1883 eclipse-mosquitto) for anything closer to the real setup.
+
+use std::collections::HashMap;
+use std::net::SocketAddr;
+use std::thread;
+
+use anyhow::Result;
+use log::{error, info};
+use rumqttd::{Broker, Config, ConnectionSettings, RouterConfig, ServerSettings};
+
+// runs on its own threads until the process ends
+pub fn start(listen: SocketAddr) -> Result<()> {
+    let server = ServerSettings {
+        name: "v4".into(),
+        listen,
+        tls: None,
+        next_connection_delay_ms: 1,
+        connections: ConnectionSettings {
+            connection_timeout_ms: 5000,
+            max_payload_size: 20 * 1024,
+            max_inflight_count: 100,
+            auth: None,
+            external_auth: None,
+            dynamic_filters: true,
+        },
+    };
+    let config = Config {
+        id: 0,
+        router: RouterConfig {
+            max_connections: 32,
+            max_outgoing_packet_count: 200,
+            max_segment_size: 1024 * 1024,
+            max_segment_count: 10,
+            custom_segment: None,
+            initialized_filters: None,
+            shared_subscriptions_strategy: Default::default(),
+        },
+        v4: Some(HashMap::from([("v4".to_string(), server)])),
+        ..Default::default()
+    };
+
+    let mut broker = Broker::new(config);
+    thread::Builder::new().name("broker".into()).spawn(move || {
+        if let Err(e) = broker.start() {
+            error!("broker error:{}", e);
+        }
+    })?;
+    info!("embedded broker on {}", listen);
+    Ok(())
+}
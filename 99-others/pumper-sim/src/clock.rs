@@ -0,0 +1,39 @@
+// simulated clock
+//
+// the firmware waits 15s between passes, 10s per soil pass & a minute per 50ml. the
+// simulation runs `speed` times faster, every wait in the simulated firmware and every
+// change in the pot goes through here.
+
+use std::time::Duration;
+
+use tokio::time::Instant;
+
+#[derive(Clone, Copy, Debug)]
+pub struct Clock {
+    started: Instant,
+    speed: f64,
+}
+
+impl Clock {
+    // `speed` simulated seconds per real second
+    pub fn new(speed: f64) -> Self {
+        Self {
+            started: Instant::now(),
+            speed: speed.max(0.001),
+        }
+    }
+
+    // simulated time since boot
+    pub fn now(&self) -> Duration {
+        self.started.elapsed().mul_f64(self.speed)
+    }
+
+    pub async fn sleep(&self, duration: Duration) {
+        tokio::time::sleep(duration.div_f64(self.speed)).await;
+    }
+
+    // tokio deadline for a simulated `duration` from now
+    pub fn deadline(&self, duration: Duration) -> Instant {
+        Instant::now() + duration.div_f64(self.speed)
+    }
+}
@@ -0,0 +1,143 @@
+// simulated pot & the devices around it
+//
+//   soil moisture sensor  raw adc like the capacitive sensor, high in dry air, low in water
+//   dht11                 temperature & air humidity, can be made to fail
+//   relay                 the pump, water reaches the pot at PUMPER_FLOW while it is on
+//
+// the soil dries by `dry_rate` adc counts per simulated hour, every ml brings it down by
+// ADC_PER_ML, always between the ends of the stock sensor. the firmware side may be
+// calibrated to other ends, see SimConfig::calibration.
+
+use std::sync::{Arc, Mutex};
+use std::time::Duration;
+
+use pumper_logic::watering::{MOISTURE_IN_AIR, MOISTURE_IN_WATER, PUMPER_FLOW};
+
+use crate::clock::Clock;
+
+// 50ml takes a dry pot ~18% up
+const ADC_PER_ML: f64 = 5.0;
+// +- adc counts on every soil reading
+const SOIL_NOISE: u32 = 4;
+
+#[derive(Clone, Debug)]
+pub struct PotConfig {
+    // raw adc, MOISTURE_IN_WATER (wet) .. MOISTURE_IN_AIR (dry)
+    pub moisture: u16,
+    // adc counts per simulated hour
+    pub dry_rate: f64,
+    pub temperature: i8,
+    pub humidity: u8,
+}
+
+impl Default for PotConfig {
+    fn default() -> Self {
+        Self {
+            moisture: 2000,
+            dry_rate: 60.0,
+            temperature: 22,
+            humidity: 45,
+        }
+    }
+}
+
+struct Pot {
+    moisture: f64,
+    // simulated time of the last update
+    updated: Duration,
+    relay: bool,
+    watered_ml: f64,
+    temperature: i8,
+    humidity: u8,
+    dht_fail: bool,
+    noise: u32,
+}
+
+#[derive(Clone)]
+pub struct Devices {
+    clock: Clock,
+    dry_rate: f64,
+    pot: Arc<Mutex<Pot>>,
+}
+
+impl Devices {
+    pub fn new(clock: Clock, config: &PotConfig) -> Self {
+        Self {
+            clock,
+            dry_rate: config.dry_rate,
+            pot: Arc::new(Mutex::new(Pot {
+                moisture: config.moisture as f64,
+                updated: clock.now(),
+                relay: false,
+                watered_ml: 0.0,
+                temperature: config.temperature,
+                humidity: config.humidity,
+                dht_fail: false,
+                noise: 0x2545_f491,
+            })),
+        }
+    }
+
+    // drying & watering up to now
+    fn update(&self, pot: &mut Pot) {
+        let now = self.clock.now();
+        let elapsed = now.saturating_sub(pot.updated);
+        pot.updated = now;
+        pot.moisture += self.dry_rate * elapsed.as_secs_f64() / 3600.0;
+        if pot.relay {
+            let ml = elapsed.as_secs_f64() / 60.0 * PUMPER_FLOW as f64;
+            pot.watered_ml += ml;
+            pot.moisture -= ml * ADC_PER_ML;
+        }
+        pot.moisture = pot.moisture.clamp(MOISTURE_IN_WATER as f64, MOISTURE_IN_AIR as f64);
+    }
+
+    // one adc reading of the moisture sensor
+    pub fn read_soil(&self) -> u16 {
+        let mut pot = self.pot.lock().unwrap();
+        self.update(&mut pot);
+        // xorshift, repeatable runs
+        pot.noise ^= pot.noise << 13;
+        pot.noise ^= pot.noise >> 17;
+        pot.noise ^= pot.noise << 5;
+        let noise = (pot.noise % (2 * SOIL_NOISE + 1)) as f64 - SOIL_NOISE as f64;
+        (pot.moisture + noise).round() as u16
+    }
+
+    // temperature & humidity, None like a dht11 checksum error
+    pub fn read_dht(&self) -> Option<(i8, u8)> {
+        let pot = self.pot.lock().unwrap();
+        (!pot.dht_fail).then_some((pot.temperature, pot.humidity))
+    }
+
+    pub fn set_relay(&self, on: bool) {
+        let mut pot = self.pot.lock().unwrap();
+        self.update(&mut pot);
+        pot.relay = on;
+    }
+
+    pub fn relay(&self) -> bool {
+        self.pot.lock().unwrap().relay
+    }
+
+    // water that reached the pot since start
+    pub fn watered_ml(&self) -> f64 {
+        let mut pot = self.pot.lock().unwrap();
+        self.update(&mut pot);
+        pot.watered_ml
+    }
+
+    pub fn set_moisture(&self, raw: u16) {
+        let mut pot = self.pot.lock().unwrap();
+        self.update(&mut pot);
+        pot.moisture = raw as f64;
+    }
+
+    pub fn set_temperature(&self, temperature: i8) {
+        self.pot.lock().unwrap().temperature = temperature;
+    }
+
+    pub fn set_dht_fail(&self, fail: bool) {
+        self.pot.lock().unwrap().dht_fail = fail;
+    }
+}
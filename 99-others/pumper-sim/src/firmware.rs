@@ -0,0 +1,12 @@
+// what pumper/ has outside pumper_logic & the simulation still needs, mirrored by hand
+// - LOOP_INTERVAL, pumper/src/main.rs
+// - availability payloads, funny_core::mqtt
+// the rules, topics & payloads themselves come from ../pumper-logic, shared with the board.
+
+use std::time::Duration;
+
+// between two sampling passes
+pub const LOOP_INTERVAL: Duration = Duration::from_secs(15);
+
+pub const AVAILABILITY_ONLINE: &str = "online";
+pub const AVAILABILITY_OFFLINE: &str = "offline";
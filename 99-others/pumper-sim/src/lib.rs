@@ -0,0 +1,13 @@
+// pumper firmware on the host, see readme.md
+
+pub mod broker;
+pub mod clock;
+pub mod devices;
+pub mod firmware;
+pub mod tasks;
+
+pub use clock::Clock;
+pub use devices::{Devices, PotConfig};
+pub use pumper_logic::telemetry::MqttMsg;
+pub use pumper_logic::watering::Calibration;
+pub use tasks::{SimConfig, Simulator};
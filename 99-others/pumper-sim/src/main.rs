@@ -0,0 +1,109 @@
+// pumper-sim
+// the pumper firmware on linux, simulated pot, dht11 & relay, real mqtt
+//
+//   pumper-sim --speed 60 --moisture 2700
+//   pumper-sim --broker mqtt://127.0.0.1:1883 --ha-node-id pumper_sim
+
+use std::net::SocketAddr;
+
+use anyhow::{anyhow, Context, Result};
+use clap::Parser;
+use log::info;
+use pumper_sim::{broker, Calibration, Clock, Devices, PotConfig, SimConfig, Simulator};
+
+#[derive(Parser, Debug)]
+#[command(version, about = "simulate a funny_games pumper on the host")]
+struct Args {
+    /// outside broker, eg mqtt://127.0.0.1:1883 for mosquitto, default an embedded one
+    #[arg(long)]
+    broker: Option<String>,
+    /// where the embedded broker listens
+    #[arg(long, default_value = "127.0.0.1:1883")]
+    listen: SocketAddr,
+    /// simulated seconds per real second
+    #[arg(long, default_value_t = 1.0)]
+    speed: f64,
+    /// raw soil moisture at start, 1450 (in water) .. 2837 (in air)
+    #[arg(long, default_value_t = 2000)]
+    moisture: u16,
+    /// calibrated dry end, raw adc of the sensor in air, like moisture_dry on the board
+    #[arg(long, default_value_t = Calibration::default().dry)]
+    moisture_dry: u16,
+    /// calibrated wet end, raw adc of the sensor in water
+    #[arg(long, default_value_t = Calibration::default().wet)]
+    moisture_wet: u16,
+    /// adc counts the soil dries per simulated hour
+    #[arg(long, default_value_t = 60.0)]
+    dry_rate: f64,
+    /// °C, the dht11 goes below 0 too
+    #[arg(long, default_value_t = 22, allow_negative_numbers = true)]
+    temperature: i8,
+    #[arg(long, default_value_t = 45)]
+    humidity: u8,
+    /// watering volume, as ml
+    #[arg(long, default_value_t = 50)]
+    volume: u32,
+    #[arg(long, default_value = "pumper-sim")]
+    client_id: String,
+    #[arg(long, default_value = "attributes")]
+    topic: String,
+    #[arg(long, default_value = "command/send/+")]
+    subscribe_topic: String,
+    #[arg(long, default_value = "")]
+    availability_topic: String,
+    /// home assistant topics under funny_games/<id>/ & discovery
+    #[arg(long)]
+    ha_node_id: Option<String>,
+    #[arg(long, default_value = "homeassistant")]
+    ha_discovery_prefix: String,
+}
+
+// mqtt://host:port or host:port
+fn parse_broker(url: &str) -> Result<(String, u16)> {
+    let address = url.strip_prefix("mqtt://").unwrap_or(url);
+    match address.rsplit_once(':') {
+        Some((host, port)) => Ok((host.to_string(), port.parse().context("invalid broker port")?)),
+        None => Ok((address.to_string(), 1883)),
+    }
+}
+
+#[tokio::main]
+async fn main() -> Result<()> {
+    env_logger::Builder::from_env(env_logger::Env::default().default_filter_or("info,rumqttd=warn,tracing=warn")).init();
+    let args = Args::parse();
+    let calibration = Calibration::new(args.moisture_dry, args.moisture_wet).map_err(|e| anyhow!(e))?;
+
+    let (mqtt_host, mqtt_port) = match &args.broker {
+        Some(url) => parse_broker(url)?,
+        None => {
+            broker::start(args.listen)?;
+            (args.listen.ip().to_string(), args.listen.port())
+        }
+    };
+    let config = SimConfig {
+        mqtt_host,
+        mqtt_port,
+        client_id: args.client_id,
+        mqtt_topic: args.topic,
+        mqtt_subscribe_topic: args.subscribe_topic,
+        availability_topic: args.availability_topic,
+        ha_node_id: args.ha_node_id,
+        ha_discovery_prefix: args.ha_discovery_prefix,
+        pumper_volume: args.volume,
+        calibration,
+    };
+    let pot = PotConfig {
+        moisture: args.moisture,
+        dry_rate: args.dry_rate,
+        temperature: args.temperature,
+        humidity: args.humidity,
+    };
+
+    // the pot outlives a reboot, the firmware state doesn't
+    let clock = Clock::new(args.speed);
+    let devices = Devices::new(clock, &pot);
+    loop {
+        Simulator::new(config.clone(), clock, devices.clone()).run().await?;
+        info!("simulated reboot");
+    }
+}
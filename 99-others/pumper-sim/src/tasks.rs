@@ -0,0 +1,419 @@
+// the pumper's awake mode tasks on tokio, same flow as pumper/src/tasks.rs
+//
+//   mqtt eventloop --> mqtt_task --pump--> pump_task
+//                         |                   |
+//                         +------network------+----> network_task (mqtt client)
+//                                             |
+//   sensor_task ------------------------------+
+//
+// the rules, ha discovery & payloads are pumper_logic, the same code the board runs.
+// left out: battery, float switch, button, led, calibrating at runtime, http api, ota, broker
+// failover, mqtt 5. a reboot command ends Simulator::run, the caller starts it over like the
+// board would.
+
+use std::time::Duration;
+
+use anyhow::Result;
+use log::{error, info, warn};
+use pumper_logic::command::{CloudCommand, Instruct};
+use pumper_logic::ha::{parse_number, parse_switch, HaTopics, VOLUME_MAX, VOLUME_MIN};
+use pumper_logic::telemetry::MqttMsg;
+use pumper_logic::watering::{
+    convert_volume_to_pumperworking_time_ms, filter_moisture, should_water, watered_ml, Calibration, MOISTURE_READS,
+};
+use rumqttc::{AsyncClient, Event, EventLoop, LastWill, MqttOptions, Packet, QoS};
+use tokio::sync::mpsc::{self, Receiver, Sender};
+
+use crate::clock::Clock;
+use crate::devices::Devices;
+use crate::firmware::{AVAILABILITY_OFFLINE, AVAILABILITY_ONLINE, LOOP_INTERVAL};
+
+// rumqttc retries on the next poll, real time
+const RECONNECT_DELAY: Duration = Duration::from_secs(1);
+
+#[derive(Clone, Debug)]
+pub struct SimConfig {
+    pub mqtt_host: String,
+    pub mqtt_port: u16,
+    pub client_id: String,
+    pub mqtt_topic: String,
+    pub mqtt_subscribe_topic: String,
+    // empty, no birth message & no last will
+    pub availability_topic: String,
+    // home assistant topics & discovery, like ha_node_id in pumper's cfg.toml
+    pub ha_node_id: Option<String>,
+    pub ha_discovery_prefix: String,
+    pub pumper_volume: u32,
+    // dry & wet ends the simulated board was calibrated to
+    pub calibration: Calibration,
+}
+
+impl Default for SimConfig {
+    fn default() -> Self {
+        Self {
+            mqtt_host: "127.0.0.1".into(),
+            mqtt_port: 1883,
+            client_id: "pumper-sim".into(),
+            mqtt_topic: "attributes".into(),
+            mqtt_subscribe_topic: "command/send/+".into(),
+            availability_topic: String::new(),
+            ha_node_id: None,
+            ha_discovery_prefix: "homeassistant".into(),
+            pumper_volume: 50,
+            calibration: Calibration::default(),
+        }
+    }
+}
+
+enum PumpRequest {
+    // on request, the configured volume
+    Water,
+    // pump the configured volume, soil is dry
+    AutoWater,
+    // stop a running pump
+    Stop,
+    // watering volume, as ml
+    SetVolume(u32),
+}
+
+enum NetworkRequest {
+    // a full sampling pass, published right away
+    Sensors { solid_humidity: u32, temperature: i32, humidity: u32 },
+    // relay switched, `watered` ml once it is off again
+    Relay { on: bool, watered: Option<u32> },
+    Volume(u32),
+    MqttConnected,
+    Reboot,
+}
+
+pub struct Simulator {
+    config: SimConfig,
+    clock: Clock,
+    devices: Devices,
+}
+
+impl Simulator {
+    pub fn new(config: SimConfig, clock: Clock, devices: Devices) -> Self {
+        Self { config, clock, devices }
+    }
+
+    // one boot, returns on a reboot command
+    pub async fn run(&self) -> Result<()> {
+        let config = &self.config;
+        let mut options = MqttOptions::new(&config.client_id, &config.mqtt_host, config.mqtt_port);
+        options.set_keep_alive(Duration::from_secs(30));
+        if !config.availability_topic.is_empty() {
+            options.set_last_will(LastWill::new(
+                &config.availability_topic,
+                AVAILABILITY_OFFLINE,
+                QoS::AtLeastOnce,
+                true,
+            ));
+        }
+        let (client, eventloop) = AsyncClient::new(options, 32);
+        let ha = config
+            .ha_node_id
+            .as_deref()
+            .map(|node_id| HaTopics::new(&config.ha_discovery_prefix, node_id, &config.availability_topic));
+
+        let (network_tx, network_rx) = mpsc::channel(8);
+        let (pump_tx, pump_rx) = mpsc::channel(4);
+
+        let result = tokio::select! {
+            res = mqtt_task(eventloop, ha.as_ref(), network_tx.clone(), pump_tx.clone()) => res,
+            res = sensor_task(self.clock, &self.devices, config.calibration, network_tx.clone(), pump_tx) => res,
+            res = pump_task(self.clock, &self.devices, pump_rx, network_tx, config.pumper_volume) => res,
+            res = network_task(&client, config, ha.as_ref(), network_rx) => res,
+        };
+        // the relay pin resets with the board
+        self.devices.set_relay(false);
+        result
+    }
+}
+
+// what pumper's mqtt callback & command_task do, never wait for room
+async fn mqtt_task(
+    mut eventloop: EventLoop,
+    ha: Option<&HaTopics>,
+    network: Sender<NetworkRequest>,
+    pump: Sender<PumpRequest>,
+) -> Result<()> {
+    loop {
+        match eventloop.poll().await {
+            Ok(Event::Incoming(Packet::ConnAck(_))) => {
+                info!("MQTT Connected");
+                send(&network, NetworkRequest::MqttConnected);
+            }
+            Ok(Event::Incoming(Packet::Publish(publish))) => {
+                info!("MQTT Message: {} {:?}", publish.topic, publish.payload);
+                dispatch(&publish.topic, &publish.payload, ha, &network, &pump);
+            }
+            Ok(_) => {}
+            Err(e) => {
+                warn!("MQTT Disconnected:{}", e);
+                tokio::time::sleep(RECONNECT_DELAY).await;
+            }
+        }
+    }
+}
+
+fn dispatch(topic: &str, data: &[u8], ha: Option<&HaTopics>, network: &Sender<NetworkRequest>, pump: &Sender<PumpRequest>) {
+    if let Some(ha) = ha {
+        if topic == ha.relay_command() {
+            match parse_switch(data) {
+                Some(true) => {
+                    info!("receive pump on command");
+                    send(pump, PumpRequest::Water);
+                }
+                Some(false) => {
+                    info!("receive pump off command");
+                    send(pump, PumpRequest::Stop);
+                }
+                None => error!("invalid relay command"),
+            }
+            return;
+        }
+        if topic == ha.volume_command() {
+            match parse_number(data) {
+                Some(val) => {
+                    info!("receive watering volume: {}ml", val);
+                    send(pump, PumpRequest::SetVolume(val));
+                }
+                None => error!("invalid volume command, must be {}..={}", VOLUME_MIN, VOLUME_MAX),
+            }
+            return;
+        }
+    }
+
+    let command: CloudCommand = match serde_json::from_slice(data) {
+        Ok(command) => command,
+        Err(e) => {
+            error!("invalid command:{}", e);
+            return;
+        }
+    };
+    match command.params {
+        Instruct::Volumn(val) => info!("receive volume command: {}ml, not used by the pumper", val),
+        Instruct::Reboot => send(network, NetworkRequest::Reboot),
+        Instruct::Ota { version, .. } => warn!("ota {} not simulated", version),
+        Instruct::LogLevel(level) => warn!("log level {} not simulated", level),
+    }
+}
+
+fn send<T>(tx: &Sender<T>, request: T) {
+    if tx.try_send(request).is_err() {
+        error!("local command channel full, command dropped");
+    }
+}
+
+async fn sensor_task(
+    clock: Clock,
+    devices: &Devices,
+    calibration: Calibration,
+    network: Sender<NetworkRequest>,
+    pump: Sender<PumpRequest>,
+) -> Result<()> {
+    loop {
+        let environment = match devices.read_dht() {
+            Some((temperature, humidity)) => Some((temperature as i32, humidity as u32)),
+            None => {
+                error!("dht11 error:checksum mismatch");
+                None
+            }
+        };
+        let solid_humidity = read_soil_humidity(clock, devices, calibration).await;
+
+        if let (Some((temperature, humidity)), Some(solid_humidity)) = (environment, solid_humidity) {
+            info!("humidity:{}", solid_humidity);
+            network
+                .send(NetworkRequest::Sensors {
+                    solid_humidity,
+                    temperature,
+                    humidity,
+                })
+                .await?;
+
+            if should_water(temperature, solid_humidity) {
+                pump.send(PumpRequest::AutoWater).await?;
+            } else {
+                info!("skip run pumper");
+            }
+        }
+
+        clock.sleep(LOOP_INTERVAL).await;
+    }
+}
+
+// soil humidity in %
+async fn read_soil_humidity(clock: Clock, devices: &Devices, calibration: Calibration) -> Option<u32> {
+    let mut moistures = Vec::with_capacity(MOISTURE_READS);
+    for _ in 0..MOISTURE_READS {
+        moistures.push(devices.read_soil());
+        clock.sleep(Duration::from_secs(1)).await;
+    }
+    let moisture = filter_moisture(&moistures)?;
+    if !calibration.in_range(moisture) {
+        error!("moisture sensor error:{}", moisture);
+    }
+    info!("moisture:{}", moisture);
+    Some(calibration.humidity(moisture))
+}
+
+async fn pump_task(
+    clock: Clock,
+    devices: &Devices,
+    mut requests: Receiver<PumpRequest>,
+    network: Sender<NetworkRequest>,
+    mut volume: u32,
+) -> Result<()> {
+    while let Some(request) = requests.recv().await {
+        match request {
+            PumpRequest::Water | PumpRequest::AutoWater => {}
+            PumpRequest::Stop => continue,
+            PumpRequest::SetVolume(val) => {
+                volume = val;
+                network.send(NetworkRequest::Volume(val)).await?;
+                continue;
+            }
+        }
+
+        // run pumper time
+        let time = convert_volume_to_pumperworking_time_ms(volume);
+        info!("pump starting!\nwater: {}ml, working time: {}ms", volume, time);
+        devices.set_relay(true);
+        let started = clock.now();
+        network.send(NetworkRequest::Relay { on: true, watered: None }).await?;
+
+        // until done, or cut short
+        let done = clock.deadline(Duration::from_millis(time as u64));
+        loop {
+            tokio::select! {
+                _ = tokio::time::sleep_until(done) => break,
+                request = requests.recv() => match request {
+                    Some(PumpRequest::Stop) | None => {
+                        info!("pump stopped early");
+                        break;
+                    }
+                    Some(PumpRequest::Water | PumpRequest::AutoWater) => info!("pump already running"),
+                    // for the next run
+                    Some(PumpRequest::SetVolume(val)) => {
+                        volume = val;
+                        network.send(NetworkRequest::Volume(val)).await?;
+                    }
+                },
+            }
+        }
+
+        devices.set_relay(false);
+        info!("pump stopped!");
+        let runtime_ms = (clock.now() - started).as_millis() as u32;
+        let watered = watered_ml(runtime_ms);
+        network
+            .send(NetworkRequest::Relay {
+                on: false,
+                watered: Some(watered),
+            })
+            .await?;
+    }
+    Ok(())
+}
+
+async fn network_task(
+    client: &AsyncClient,
+    config: &SimConfig,
+    ha: Option<&HaTopics>,
+    mut requests: Receiver<NetworkRequest>,
+) -> Result<()> {
+    // latest state of everything, sent as a whole
+    let mut mqtt_msg = MqttMsg {
+        relay: Some(false),
+        pumper_volume: Some(config.pumper_volume),
+        mqtt_broker: Some(format!("mqtt://{}:{}", config.mqtt_host, config.mqtt_port)),
+        wifi_ssid: Some("simulated".into()),
+        ..Default::default()
+    };
+
+    while let Some(request) = requests.recv().await {
+        match request {
+            NetworkRequest::Sensors {
+                solid_humidity,
+                temperature,
+                humidity,
+            } => {
+                mqtt_msg.solid_humidity = Some(solid_humidity);
+                mqtt_msg.environment_temperature = Some(temperature);
+                mqtt_msg.environment_humidity = Some(humidity);
+            }
+            NetworkRequest::Relay { on, watered } => {
+                mqtt_msg.relay = Some(on);
+                if watered.is_some() {
+                    mqtt_msg.amount_total = watered;
+                }
+            }
+            NetworkRequest::Volume(val) => mqtt_msg.pumper_volume = Some(val),
+            NetworkRequest::MqttConnected => {
+                subscribe_topics(client, config, ha).await;
+                publish_availability(client, config, AVAILABILITY_ONLINE).await;
+                if let Some(ha) = ha {
+                    publish_discovery(client, ha).await;
+                }
+                continue;
+            }
+            NetworkRequest::Reboot => {
+                warn!("device restarting");
+                publish_availability(client, config, AVAILABILITY_OFFLINE).await;
+                // give the eventloop some time to flush
+                tokio::time::sleep(Duration::from_millis(500)).await;
+                return Ok(());
+            }
+        }
+
+        let payload = serde_json::to_string(&mqtt_msg)?;
+        info!("send mqtt msg:{}", payload);
+        let mut topics = vec![config.mqtt_topic.clone()];
+        topics.extend(ha.map(HaTopics::state));
+        for topic in topics {
+            if let Err(e) = client.publish(topic, QoS::AtMostOnce, false, payload.clone()).await {
+                error!("mqtt client error:{}", e);
+            }
+        }
+    }
+    Ok(())
+}
+
+async fn subscribe_topics(client: &AsyncClient, config: &SimConfig, ha: Option<&HaTopics>) {
+    let mut topics = vec![config.mqtt_subscribe_topic.clone()];
+    if let Some(ha) = ha {
+        topics.push(ha.relay_command());
+        topics.push(ha.volume_command());
+    }
+    for topic in topics.into_iter().filter(|topic| !topic.is_empty()) {
+        match client.subscribe(&topic, QoS::AtMostOnce).await {
+            Ok(_) => info!("Subscribed to topic:{}", topic),
+            Err(e) => error!("Subscribed error:{} topic:{}", e, topic),
+        }
+    }
+}
+
+// retained, so a late subscriber sees the current state
+async fn publish_availability(client: &AsyncClient, config: &SimConfig, payload: &str) {
+    if config.availability_topic.is_empty() {
+        return;
+    }
+    if let Err(e) = client
+        .publish(&config.availability_topic, QoS::AtLeastOnce, true, payload)
+        .await
+    {
+        error!("availability publish error:{}", e);
+    }
+}
+
+// retained discovery configs, the board publishes the same on every connect
+async fn publish_discovery(client: &AsyncClient, ha: &HaTopics) {
+    for (topic, config) in ha.discovery(env!("CARGO_PKG_VERSION")) {
+        match client.publish(&topic, QoS::AtLeastOnce, true, config.to_string()).await {
+            Ok(_) => info!("ha discovery published:{}", topic),
+            Err(e) => error!("ha discovery error:{} topic:{}", e, topic),
+        }
+    }
+}
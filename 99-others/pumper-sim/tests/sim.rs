@@ -0,0 +1,173 @@
+// simulator against the embedded broker, a test client drives commands & reads what the
+// pumper publishes
+
+use std::net::{SocketAddr, TcpListener};
+use std::time::Duration;
+
+use pumper_sim::{broker, Calibration, Clock, Devices, MqttMsg, PotConfig, SimConfig, Simulator};
+use rumqttc::{AsyncClient, Event, EventLoop, MqttOptions, Packet, QoS};
+use tokio::time::timeout;
+
+const WAIT: Duration = Duration::from_secs(20);
+
+struct Harness {
+    client: AsyncClient,
+    eventloop: EventLoop,
+    devices: Devices,
+}
+
+// broker on a free port, a subscribed test client & the simulator running
+// `config` without the mqtt settings, those are set up per test
+async fn start(name: &str, speed: f64, pot: PotConfig, config: SimConfig) -> Harness {
+    let port = TcpListener::bind("127.0.0.1:0").unwrap().local_addr().unwrap().port();
+    broker::start(SocketAddr::from(([127, 0, 0, 1], port))).unwrap();
+
+    let (client, mut eventloop) = AsyncClient::new(MqttOptions::new(format!("{}-test", name), "127.0.0.1", port), 16);
+    // every test has a broker of its own
+    client.subscribe("#", QoS::AtLeastOnce).await.unwrap();
+    timeout(WAIT, async {
+        loop {
+            match eventloop.poll().await {
+                Ok(Event::Incoming(Packet::SubAck(_))) => break,
+                Ok(_) => {}
+                // broker thread not listening yet
+                Err(_) => tokio::time::sleep(Duration::from_millis(50)).await,
+            }
+        }
+    })
+    .await
+    .expect("test client subscribed");
+
+    let config = SimConfig {
+        mqtt_port: port,
+        client_id: name.into(),
+        mqtt_topic: format!("{}/attributes", name),
+        mqtt_subscribe_topic: format!("{}/command/send/+", name),
+        availability_topic: format!("{}/availability", name),
+        ..config
+    };
+    let clock = Clock::new(speed);
+    let devices = Devices::new(clock, &pot);
+    let simulator = Simulator::new(config, clock, devices.clone());
+    tokio::spawn(async move { simulator.run().await });
+
+    Harness { client, eventloop, devices }
+}
+
+impl Harness {
+    // next payload on `topic` that `accept` takes
+    async fn wait_for<T>(&mut self, topic: &str, mut accept: impl FnMut(&[u8]) -> Option<T>) -> T {
+        timeout(WAIT, async {
+            loop {
+                if let Event::Incoming(Packet::Publish(publish)) = self.eventloop.poll().await.unwrap() {
+                    if publish.topic == topic {
+                        if let Some(found) = accept(&publish.payload) {
+                            return found;
+                        }
+                    }
+                }
+            }
+        })
+        .await
+        .unwrap_or_else(|_| panic!("nothing matching on {}", topic))
+    }
+
+    async fn wait_for_state(&mut self, topic: &str, accept: impl Fn(&MqttMsg) -> bool) -> MqttMsg {
+        self.wait_for(topic, |payload| {
+            let msg: MqttMsg = serde_json::from_slice(payload).expect("telemetry json");
+            accept(&msg).then_some(msg)
+        })
+        .await
+    }
+
+    async fn wait_for_availability(&mut self, topic: &str, payload: &str) {
+        self.wait_for(topic, |data| (data == payload.as_bytes()).then_some(())).await
+    }
+
+    async fn publish(&self, topic: &str, payload: &str) {
+        self.client.publish(topic, QoS::AtLeastOnce, false, payload).await.unwrap();
+    }
+}
+
+#[tokio::test(flavor = "multi_thread")]
+async fn dry_soil_waters_the_configured_volume() {
+    let pot = PotConfig {
+        moisture: 2700,
+        ..Default::default()
+    };
+    let mut sim = start("dry", 1000.0, pot, SimConfig::default()).await;
+
+    let msg = sim.wait_for_state("dry/attributes", |msg| msg.solid_humidity.is_some()).await;
+    assert!(msg.solid_humidity.unwrap() < 30);
+    assert_eq!(msg.environment_temperature, Some(22));
+    assert_eq!(msg.environment_humidity, Some(45));
+    assert_eq!(msg.pumper_volume, Some(50));
+
+    sim.wait_for_state("dry/attributes", |msg| msg.relay == Some(true)).await;
+    let msg = sim.wait_for_state("dry/attributes", |msg| msg.relay == Some(false)).await;
+    // a late timer is `speed` times later in simulated time
+    assert!((50..=52).contains(&msg.amount_total.unwrap()));
+    assert!(sim.devices.watered_ml() >= 50.0);
+}
+
+#[tokio::test(flavor = "multi_thread")]
+async fn home_assistant_commands() {
+    let pot = PotConfig {
+        moisture: 1600,
+        ..Default::default()
+    };
+    let config = SimConfig {
+        ha_node_id: Some("ha".into()),
+        ..Default::default()
+    };
+    let mut sim = start("ha", 10.0, pot, config).await;
+    sim.wait_for_availability("ha/availability", "online").await;
+
+    // retained discovery, what HA creates the entities from
+    let relay = sim
+        .wait_for("homeassistant/switch/ha/relay/config", |payload| {
+            serde_json::from_slice::<serde_json::Value>(payload).ok()
+        })
+        .await;
+    assert_eq!(relay["command_topic"], "funny_games/ha/relay/set");
+    assert_eq!(relay["availability_topic"], "ha/availability");
+
+    // wet soil, no automatic watering
+    let msg = sim.wait_for_state("funny_games/ha/state", |msg| msg.solid_humidity.is_some()).await;
+    assert!(msg.solid_humidity.unwrap() > 30);
+    assert_eq!(msg.relay, Some(false));
+
+    sim.publish("funny_games/ha/volume/set", "30.0").await;
+    sim.wait_for_state("funny_games/ha/state", |msg| msg.pumper_volume == Some(30)).await;
+
+    sim.publish("funny_games/ha/relay/set", "ON").await;
+    sim.wait_for_state("funny_games/ha/state", |msg| msg.relay == Some(true)).await;
+    assert!(sim.devices.relay());
+
+    // 30ml take 36 simulated seconds, cut short well before
+    sim.publish("funny_games/ha/relay/set", "OFF").await;
+    let msg = sim.wait_for_state("ha/attributes", |msg| msg.relay == Some(false)).await;
+    assert!(msg.amount_total.unwrap() < 30);
+    assert!(!sim.devices.relay());
+
+    sim.publish("ha/command/send/1", r#"{"method":"cmd","params":{"Reboot":null},"id":1}"#).await;
+    sim.wait_for_availability("ha/availability", "offline").await;
+}
+
+#[tokio::test(flavor = "multi_thread")]
+async fn calibration_decides_when_the_soil_is_dry() {
+    // moist for the stock sensor, dry for one that reads 1500..2100
+    let pot = PotConfig {
+        moisture: 2000,
+        ..Default::default()
+    };
+    let config = SimConfig {
+        calibration: Calibration::new(2100, 1500).unwrap(),
+        ..Default::default()
+    };
+    let mut sim = start("calibrated", 1000.0, pot, config).await;
+
+    let msg = sim.wait_for_state("calibrated/attributes", |msg| msg.solid_humidity.is_some()).await;
+    assert!(msg.solid_humidity.unwrap() < 30);
+    sim.wait_for_state("calibrated/attributes", |msg| msg.relay == Some(true)).await;
+}
@@ -1,3 +1,20 @@
+use std::env;
+use std::fs::{self, File};
+use std::io::Write;
+use std::path::PathBuf;
+
+use flate2::write::GzEncoder;
+use flate2::Compression;
+
 fn main() {
     embuild::espidf::sysenv::output();
+
+    // dashboard, stored & served gzipped, see src/api.rs
+    println!("cargo:rerun-if-changed=build.rs");
+    println!("cargo:rerun-if-changed=assets/dashboard.html");
+    let html = fs::read("assets/dashboard.html").expect("assets/dashboard.html");
+    let out = PathBuf::from(env::var("OUT_DIR").unwrap()).join("dashboard.html.gz");
+    let mut gz = GzEncoder::new(File::create(out).unwrap(), Compression::best());
+    gz.write_all(&html).unwrap();
+    gz.finish().unwrap();
 }
@@ -0,0 +1,273 @@
+// local http api, port 80 on the station ip, for scripting the device without a broker
+//
+//   GET  /              dashboard, assets/dashboard.html gzipped by build.rs
+//   GET  /api/status    latest readings & pump state, same fields as the telemetry
+//   GET  /api/history   readings & waterings since boot, see history.rs
+//   POST /api/water     {"ml":50}, water once
+//   POST /api/calibrate {"point":"dry"} or "wet", the next moisture reading becomes that end
+//   GET  /api/config    watering volume, moisture calibration, log level, wifi & mqtt settings,
+//                       no passwords
+//   PUT  /api/config    {"pumper_volume":60,"wifi_ssid":"..",...}, any subset,
+//                       volume & log level apply right away, wifi & mqtt after a reboot
+//   POST /api/reboot
+//   GET  /metrics       prometheus text format, see metrics.rs
+//
+// POST & PUT need `Authorization: Bearer <http_api_token>`, refused while no token is set.
+// plain http, the token is only as safe as the lan.
+//
+//   curl -X POST -H "Authorization: Bearer secret" -d '{"ml":50}' http://192.168.1.50/api/water
+
+use std::sync::{Arc, Mutex};
+
+use anyhow::{bail, Result};
+use esp_idf_svc::http::server::{Configuration as HttpConfiguration, EspHttpConnection, EspHttpServer, Request};
+use esp_idf_svc::http::{Headers, Method};
+use esp_idf_svc::io::{Read, Write};
+use esp_idf_svc::nvs::EspDefaultNvsPartition;
+use funny_core::prometheus;
+use funny_core::wifi::WifiState;
+use log::{info, warn};
+use serde::Deserialize;
+use serde_json::{json, Value};
+
+use crate::calibration::{self, Point};
+use crate::history;
+use crate::metrics;
+use crate::remote_log;
+use crate::settings::Settings;
+use crate::{send_local_command, LocalCommand, MqttMsg, CONFIG};
+
+// also advertised over mdns
+pub const HTTP_PORT: u16 = 80;
+const MAX_BODY_LEN: usize = 1024;
+// one manual dose, more than this is a typo
+const MAX_WATER_ML: u32 = 1000;
+
+const DASHBOARD: &[u8] = include_bytes!(concat!(env!("OUT_DIR"), "/dashboard.html.gz"));
+
+// latest telemetry, updated by the network task
+static STATUS: Mutex<Value> = Mutex::new(Value::Null);
+
+pub fn set_status(mqtt_msg: &MqttMsg) {
+    match serde_json::to_value(mqtt_msg) {
+        Ok(status) => *STATUS.lock().unwrap() = status,
+        Err(e) => warn!("api status error:{}", e),
+    }
+}
+
+#[derive(Deserialize)]
+struct WaterRequest {
+    ml: u32,
+}
+
+#[derive(Deserialize)]
+struct CalibrateRequest {
+    point: Point,
+}
+
+#[derive(Deserialize)]
+struct ConfigUpdate {
+    pumper_volume: Option<u32>,
+    log_level: Option<String>,
+    wifi_ssid: Option<String>,
+    wifi_psk: Option<String>,
+    mqtt_host: Option<String>,
+    mqtt_user: Option<String>,
+    mqtt_pass: Option<String>,
+}
+
+// keep the server alive as long as the api should be reachable
+pub fn start(nvs: &EspDefaultNvsPartition, settings: &Settings, wifi: Arc<WifiState>) -> Result<EspHttpServer<'static>> {
+    let mut server = EspHttpServer::new(&HttpConfiguration {
+        http_port: HTTP_PORT,
+        ..Default::default()
+    })?;
+
+    server.fn_handler::<anyhow::Error, _>("/", Method::Get, |req| {
+        req.into_response(
+            200,
+            None,
+            &[("Content-Type", "text/html; charset=utf-8"), ("Content-Encoding", "gzip")],
+        )?
+        .write_all(DASHBOARD)?;
+        Ok(())
+    })?;
+
+    server.fn_handler::<anyhow::Error, _>("/api/status", Method::Get, |req| {
+        let status = STATUS.lock().unwrap().clone();
+        respond(req, 200, &status)
+    })?;
+
+    server.fn_handler::<anyhow::Error, _>("/api/history", Method::Get, |req| {
+        respond(req, 200, &history::to_json())
+    })?;
+
+    // no token, scrapers are read only like the other GETs
+    server.fn_handler::<anyhow::Error, _>("/metrics", Method::Get, move |req| {
+        req.into_response(200, None, &[("Content-Type", prometheus::CONTENT_TYPE)])?
+            .write_all(metrics::render(&wifi).as_bytes())?;
+        Ok(())
+    })?;
+
+    server.fn_handler::<anyhow::Error, _>("/api/calibrate", Method::Post, |mut req| {
+        if !authorized(&req) {
+            return respond(req, 401, &json!({ "error": "unauthorized" }));
+        }
+        let calibrate = match read_json::<CalibrateRequest>(&mut req) {
+            Ok(calibrate) => calibrate,
+            Err(e) => return respond(req, 400, &json!({ "error": e.to_string() })),
+        };
+        info!("api calibrate {:?}", calibrate.point);
+        if !send_local_command(LocalCommand::Calibrate(calibrate.point)) {
+            return busy(req);
+        }
+        respond(req, 202, &json!({ "point": calibrate.point }))
+    })?;
+
+    server.fn_handler::<anyhow::Error, _>("/api/water", Method::Post, |mut req| {
+        if !authorized(&req) {
+            return respond(req, 401, &json!({ "error": "unauthorized" }));
+        }
+        let water = match read_json::<WaterRequest>(&mut req) {
+            Ok(water) => water,
+            Err(e) => return respond(req, 400, &json!({ "error": e.to_string() })),
+        };
+        if water.ml == 0 || water.ml > MAX_WATER_ML {
+            return respond(req, 400, &json!({ "error": format!("ml must be 1..={}", MAX_WATER_ML) }));
+        }
+        info!("api water {}ml", water.ml);
+        if !send_local_command(LocalCommand::Water(water.ml)) {
+            return busy(req);
+        }
+        respond(req, 202, &json!({ "ml": water.ml }))
+    })?;
+
+    let settings = Arc::new(Mutex::new(settings.clone()));
+    let current = settings.clone();
+    server.fn_handler::<anyhow::Error, _>("/api/config", Method::Get, move |req| {
+        let settings = current.lock().unwrap().clone();
+        let volume = STATUS.lock().unwrap()["pumper_volume"].clone();
+        respond(
+            req,
+            200,
+            &json!({
+                "pumper_volume": volume,
+                "moisture_dry": calibration::dry(),
+                "moisture_wet": calibration::wet(),
+                "log_level": remote_log::level().as_str().to_lowercase(),
+                "wifi_ssid": settings.wifi_ssid,
+                "mqtt_host": settings.mqtt_host,
+                "mqtt_user": settings.mqtt_user,
+            }),
+        )
+    })?;
+
+    let nvs = nvs.clone();
+    server.fn_handler::<anyhow::Error, _>("/api/config", Method::Put, move |mut req| {
+        if !authorized(&req) {
+            return respond(req, 401, &json!({ "error": "unauthorized" }));
+        }
+        let update = match read_json::<ConfigUpdate>(&mut req) {
+            Ok(update) => update,
+            Err(e) => return respond(req, 400, &json!({ "error": e.to_string() })),
+        };
+        // everything is checked before anything is applied, a 400 changes nothing
+        // same limit as one manual dose
+        if update.pumper_volume.is_some_and(|volume| volume == 0 || volume > MAX_WATER_ML) {
+            return respond(req, 400, &json!({ "error": format!("pumper_volume must be 1..={}", MAX_WATER_ML) }));
+        }
+        let level = match update.log_level.as_deref().map(remote_log::parse_level).transpose() {
+            Ok(level) => level,
+            Err(e) => return respond(req, 400, &json!({ "error": e })),
+        };
+        let mut settings = settings.lock().unwrap();
+        let mut changed = settings.clone();
+        for (field, value) in [
+            (&mut changed.wifi_ssid, update.wifi_ssid),
+            (&mut changed.wifi_psk, update.wifi_psk),
+            (&mut changed.mqtt_host, update.mqtt_host),
+            (&mut changed.mqtt_user, update.mqtt_user),
+            (&mut changed.mqtt_pass, update.mqtt_pass),
+        ] {
+            if let Some(value) = value {
+                *field = value;
+            }
+        }
+        if let Err(e) = changed.check() {
+            return respond(req, 400, &json!({ "error": e }));
+        }
+
+        // the only step that can be refused, so it goes first
+        if let Some(volume) = update.pumper_volume {
+            if !send_local_command(LocalCommand::Volume(volume)) {
+                return busy(req);
+            }
+        }
+        if let Some(level) = level {
+            remote_log::store_level(level);
+        }
+        let reboot_required = changed.wifi_ssid != settings.wifi_ssid
+            || changed.wifi_psk != settings.wifi_psk
+            || changed.mqtt_host != settings.mqtt_host
+            || changed.mqtt_user != settings.mqtt_user
+            || changed.mqtt_pass != settings.mqtt_pass;
+        if reboot_required {
+            changed.save(&nvs)?;
+            *settings = changed;
+        }
+        respond(req, 200, &json!({ "reboot_required": reboot_required }))
+    })?;
+
+    server.fn_handler::<anyhow::Error, _>("/api/reboot", Method::Post, |req| {
+        if !authorized(&req) {
+            return respond(req, 401, &json!({ "error": "unauthorized" }));
+        }
+        info!("api reboot");
+        if !send_local_command(LocalCommand::Reboot) {
+            return busy(req);
+        }
+        respond(req, 202, &json!({}))
+    })?;
+
+    info!("http api started");
+    Ok(server)
+}
+
+// the command channel was full, the command was dropped
+fn busy(req: Request<&mut EspHttpConnection>) -> Result<()> {
+    respond(req, 503, &json!({ "error": "busy, command dropped" }))
+}
+
+fn authorized(req: &Request<&mut EspHttpConnection>) -> bool {
+    let token = CONFIG.http_api_token;
+    if token.is_empty() {
+        return false;
+    }
+    let Some(given) = req.header("Authorization").and_then(|value| value.strip_prefix("Bearer ")) else {
+        return false;
+    };
+    // same time for every wrong guess
+    given.len() == token.len() && given.bytes().zip(token.bytes()).fold(0, |diff, (a, b)| diff | (a ^ b)) == 0
+}
+
+fn read_json<T: serde::de::DeserializeOwned>(req: &mut Request<&mut EspHttpConnection>) -> Result<T> {
+    let mut body = Vec::new();
+    let mut buf = [0u8; 256];
+    loop {
+        let len = req.read(&mut buf)?;
+        if len == 0 {
+            break;
+        }
+        if body.len() + len > MAX_BODY_LEN {
+            bail!("body too large");
+        }
+        body.extend_from_slice(&buf[..len]);
+    }
+    Ok(serde_json::from_slice(&body)?)
+}
+
+fn respond(req: Request<&mut EspHttpConnection>, status: u16, body: &Value) -> Result<()> {
+    req.into_response(status, None, &[("Content-Type", "application/json")])?
+        .write_all(body.to_string().as_bytes())?;
+    Ok(())
+}
@@ -0,0 +1,42 @@
+// battery / supply voltage, measuring is funny_core::battery, set up in main
+//
+// pack voltage through a resistor divider on gpio1 (adc1 ch1):
+//   pack+ --- R1 --- gpio1 --- R2 --- gnd,   battery_divider = (R1 + R2) / R2
+// the state of charge reads low while the pump is running.
+//
+// the pump pulls the pack down hard when it starts, below `battery_pump_cutoff_mv` it is
+// not started at all, better a dry plant than a brown-out reset with the relay on.
+
+use funny_core::battery::{battery, Chemistry};
+use log::warn;
+
+use crate::CONFIG;
+
+pub use funny_core::battery::{init, last, measure};
+
+// per cell, with some margin for the sag when the pump starts
+fn default_cutoff_mv(chemistry: Chemistry) -> u32 {
+    match chemistry {
+        Chemistry::LiIon => 3500,
+        Chemistry::LiFePo4 => 3050,
+    }
+}
+
+// false when the pack is too low to start the pump safely
+pub fn pump_allowed() -> bool {
+    let Some(battery) = battery() else {
+        return true;
+    };
+    let Some(reading) = battery.last() else {
+        return true;
+    };
+    let cutoff_mv = match CONFIG.battery_pump_cutoff_mv {
+        0 => default_cutoff_mv(battery.chemistry) * battery.cells,
+        mv => mv,
+    };
+    if reading.voltage_mv < cutoff_mv {
+        warn!("battery {}mV below pump cutoff {}mV, pump not started", reading.voltage_mv, cutoff_mv);
+        return false;
+    }
+    true
+}
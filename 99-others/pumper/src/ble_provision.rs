@@ -0,0 +1,137 @@
+// ble gatt provisioning, alternative to the softap portal (feature `ble-provision`)
+//
+// a small custom protocol, one writable characteristic per setting:
+//
+//   service   f0a40001-6a8f-4f5b-9d2e-8c3b1f2a0c01
+//   0002 wifi_ssid  write
+//   0003 wifi_psk   write
+//   0004 mqtt_host  write
+//   0005 mqtt_user  write
+//   0006 mqtt_pass  write
+//   0007 command    write   "commit" saves to nvs & reboots, "cancel" drops pending values
+//   0008 status     read/notify  "idle", "pending", "saved", "error: ..."
+//
+// values are utf-8, unset fields keep their current value. `ble_passkey` enables pairing,
+// then all writes need an encrypted link. the host side lives in ../provision-tool,
+// keep the uuids in sync with provision-tool/src/protocol.rs
+
+use std::sync::{Arc, Mutex};
+use std::thread;
+use std::time::{Duration, Instant};
+
+use anyhow::Result;
+use esp32_nimble::enums::{AuthReq, SecurityIOCap};
+use esp32_nimble::utilities::BleUuid;
+use esp32_nimble::{uuid128, BLEAdvertisementData, BLEDevice, NimbleProperties};
+use esp_idf_svc::hal::delay::FreeRtos;
+use esp_idf_svc::nvs::EspDefaultNvsPartition;
+use log::{info, warn};
+
+use crate::settings::Settings;
+
+const SERVICE_UUID: BleUuid = uuid128!("f0a40001-6a8f-4f5b-9d2e-8c3b1f2a0c01");
+const WIFI_SSID_UUID: BleUuid = uuid128!("f0a40002-6a8f-4f5b-9d2e-8c3b1f2a0c01");
+const WIFI_PSK_UUID: BleUuid = uuid128!("f0a40003-6a8f-4f5b-9d2e-8c3b1f2a0c01");
+const MQTT_HOST_UUID: BleUuid = uuid128!("f0a40004-6a8f-4f5b-9d2e-8c3b1f2a0c01");
+const MQTT_USER_UUID: BleUuid = uuid128!("f0a40005-6a8f-4f5b-9d2e-8c3b1f2a0c01");
+const MQTT_PASS_UUID: BleUuid = uuid128!("f0a40006-6a8f-4f5b-9d2e-8c3b1f2a0c01");
+const COMMAND_UUID: BleUuid = uuid128!("f0a40007-6a8f-4f5b-9d2e-8c3b1f2a0c01");
+const STATUS_UUID: BleUuid = uuid128!("f0a40008-6a8f-4f5b-9d2e-8c3b1f2a0c01");
+
+// same as softap, nobody came, go back to station mode and try again
+const PROVISION_TIMEOUT: Duration = Duration::from_secs(10 * 60);
+
+type Setter = fn(&mut Settings, String);
+
+pub fn run(nvs: &EspDefaultNvsPartition, settings: &Settings, device_suffix: &str, passkey: u32) -> Result<()> {
+    let device = BLEDevice::take();
+    let name = format!("funny-pumper-{}", device_suffix);
+    BLEDevice::set_device_name(&name)?;
+
+    let write = if passkey > 0 {
+        device
+            .security()
+            .set_auth(AuthReq::all())
+            .set_passkey(passkey)
+            .set_io_cap(SecurityIOCap::DisplayOnly);
+        NimbleProperties::WRITE | NimbleProperties::WRITE_ENC
+    } else {
+        NimbleProperties::WRITE
+    };
+
+    let server = device.get_server();
+    server.on_connect(|_, desc| info!("ble provisioning client connected:{:?}", desc.address()));
+    server.on_disconnect(|desc, _| info!("ble provisioning client gone:{:?}", desc.address()));
+    let service = server.create_service(SERVICE_UUID);
+
+    let pending = Arc::new(Mutex::new(settings.clone()));
+
+    let status = service
+        .lock()
+        .create_characteristic(STATUS_UUID, NimbleProperties::READ | NimbleProperties::NOTIFY);
+    status.lock().set_value(b"idle");
+
+    let fields: [(BleUuid, Setter); 5] = [
+        (WIFI_SSID_UUID, |s, v| s.wifi_ssid = v),
+        (WIFI_PSK_UUID, |s, v| s.wifi_psk = v),
+        (MQTT_HOST_UUID, |s, v| s.mqtt_host = v),
+        (MQTT_USER_UUID, |s, v| s.mqtt_user = v),
+        (MQTT_PASS_UUID, |s, v| s.mqtt_pass = v),
+    ];
+    for (uuid, setter) in fields {
+        let pending = pending.clone();
+        let status = status.clone();
+        service.lock().create_characteristic(uuid, write).lock().on_write(move |args| {
+            let value = String::from_utf8_lossy(args.recv_data()).into_owned();
+            setter(&mut pending.lock().unwrap(), value);
+            status.lock().set_value(b"pending").notify();
+        });
+    }
+
+    let nvs = nvs.clone();
+    let current = settings.clone();
+    let command_status = status.clone();
+    service.lock().create_characteristic(COMMAND_UUID, write).lock().on_write(move |args| {
+        let reply: String = match args.recv_data() {
+            b"commit" => {
+                let settings = pending.lock().unwrap().clone();
+                if let Err(e) = settings.check() {
+                    format!("error: {}", e)
+                } else {
+                    match settings.save(&nvs) {
+                        Ok(_) => {
+                            // let the notify go out first
+                            thread::spawn(|| {
+                                FreeRtos::delay_ms(2000);
+                                esp_idf_svc::hal::reset::restart();
+                            });
+                            "saved".into()
+                        }
+                        Err(e) => format!("error: {}", e),
+                    }
+                }
+            }
+            b"cancel" => {
+                *pending.lock().unwrap() = current.clone();
+                "idle".into()
+            }
+            other => format!("error: unknown command {}", String::from_utf8_lossy(other)),
+        };
+        info!("ble provisioning status:{}", reply);
+        command_status.lock().set_value(reply.as_bytes()).notify();
+    });
+
+    let advertising = device.get_advertising();
+    advertising
+        .lock()
+        .set_data(BLEAdvertisementData::new().name(&name).add_service_uuid(SERVICE_UUID))?;
+    advertising.lock().start()?;
+    info!("ble provisioning advertising as:{}", name);
+
+    let started = Instant::now();
+    while started.elapsed() < PROVISION_TIMEOUT {
+        FreeRtos::delay_ms(1000);
+    }
+    warn!("provisioning timeout, restart into station mode");
+    esp_idf_svc::hal::reset::restart();
+}
@@ -0,0 +1,121 @@
+// mqtt broker failover
+//
+// brokers are tried in order, the first one is the primary (mqtt_host/mqtt_user/mqtt_pass),
+// the rest come from `mqtt_fallback_brokers`:
+//   "mqtt://10.0.0.2:1883|user|pass;mqtts://backup.example.com:8883|user|pass"
+//
+// - connection lost longer than `mqtt_failover_secs` -> switch to the next broker
+// - on a fallback for `mqtt_primary_retry_secs` -> try the primary again
+
+use std::sync::atomic::{AtomicBool, Ordering};
+use std::time::{Duration, Instant};
+
+use log::{info, warn};
+
+// updated by the mqtt callback
+static CONNECTED: AtomicBool = AtomicBool::new(false);
+
+pub fn set_connected(connected: bool) {
+    CONNECTED.store(connected, Ordering::Relaxed);
+}
+
+pub fn is_connected() -> bool {
+    CONNECTED.load(Ordering::Relaxed)
+}
+
+#[derive(Clone, Debug)]
+pub struct BrokerEndpoint {
+    pub url: String,
+    pub user: String,
+    pub pass: String,
+}
+
+impl BrokerEndpoint {
+    // url without scheme, safe to report in telemetry
+    pub fn host(&self) -> &str {
+        self.url.split("://").last().unwrap_or(&self.url)
+    }
+}
+
+pub struct BrokerList {
+    endpoints: Vec<BrokerEndpoint>,
+    active: usize,
+    failover_after: Duration,
+    primary_retry_after: Duration,
+    // when the active broker was selected
+    selected_at: Instant,
+    // last time we saw the active broker connected
+    last_connected: Instant,
+}
+
+impl BrokerList {
+    pub fn new(primary: BrokerEndpoint, fallbacks: &str, failover_secs: u32, primary_retry_secs: u32) -> Self {
+        let mut endpoints = vec![primary];
+        endpoints.extend(parse_endpoints(fallbacks));
+        info!("mqtt brokers:{:?}", endpoints.iter().map(|e| e.host()).collect::<Vec<_>>());
+
+        let now = Instant::now();
+        Self {
+            endpoints,
+            active: 0,
+            failover_after: Duration::from_secs(failover_secs as u64),
+            primary_retry_after: Duration::from_secs(primary_retry_secs as u64),
+            selected_at: now,
+            last_connected: now,
+        }
+    }
+
+    pub fn active(&self) -> &BrokerEndpoint {
+        &self.endpoints[self.active]
+    }
+
+    // call periodically, returns the broker to switch to if the active one should be replaced
+    pub fn check(&mut self) -> Option<&BrokerEndpoint> {
+        let now = Instant::now();
+        if is_connected() {
+            self.last_connected = now;
+            // healthy on a fallback, time to give the primary another chance
+            if self.active != 0 && now.duration_since(self.selected_at) >= self.primary_retry_after {
+                info!("mqtt trying primary broker again");
+                return Some(self.select(0));
+            }
+            return None;
+        }
+
+        let since = self.last_connected.max(self.selected_at);
+        if self.endpoints.len() > 1 && now.duration_since(since) >= self.failover_after {
+            let next = (self.active + 1) % self.endpoints.len();
+            warn!(
+                "mqtt broker {} down for {}s, failover to {}",
+                self.active().host(),
+                now.duration_since(since).as_secs(),
+                self.endpoints[next].host()
+            );
+            return Some(self.select(next));
+        }
+        None
+    }
+
+    fn select(&mut self, index: usize) -> &BrokerEndpoint {
+        self.active = index;
+        self.selected_at = Instant::now();
+        set_connected(false);
+        &self.endpoints[index]
+    }
+}
+
+// "url|user|pass;url|user|pass", user & pass may be omitted
+fn parse_endpoints(list: &str) -> Vec<BrokerEndpoint> {
+    list.split(';')
+        .map(str::trim)
+        .filter(|item| !item.is_empty())
+        .map(|item| {
+            let mut parts = item.splitn(3, '|');
+            BrokerEndpoint {
+                url: parts.next().unwrap_or_default().trim().to_string(),
+                user: parts.next().unwrap_or_default().to_string(),
+                pass: parts.next().unwrap_or_default().to_string(),
+            }
+        })
+        .collect()
+}
@@ -0,0 +1,71 @@
+// soil moisture sensor calibration
+//
+// "dry" & "wet" are measured on the device (dashboard or POST /api/calibrate) and kept in nvs,
+// the compiled in ends are the fallback. the math is pumper_logic::watering::Calibration.
+
+use std::sync::atomic::{AtomicU16, Ordering};
+
+use anyhow::{anyhow, Result};
+use esp_idf_svc::nvs::{EspDefaultNvsPartition, EspNvs, NvsDefault};
+use log::info;
+use pumper_logic::watering::{Calibration, MOISTURE_IN_AIR, MOISTURE_IN_WATER};
+
+pub use pumper_logic::watering::Point;
+
+const NAMESPACE: &str = "calibration";
+const KEY_DRY: &str = "moisture_dry";
+const KEY_WET: &str = "moisture_wet";
+
+static DRY: AtomicU16 = AtomicU16::new(MOISTURE_IN_AIR);
+static WET: AtomicU16 = AtomicU16::new(MOISTURE_IN_WATER);
+
+// stored calibration, if any
+pub fn load(nvs: &EspDefaultNvsPartition) -> Result<()> {
+    let store = open(nvs)?;
+    if let Some(dry) = store.get_u16(KEY_DRY)? {
+        DRY.store(dry, Ordering::Relaxed);
+    }
+    if let Some(wet) = store.get_u16(KEY_WET)? {
+        WET.store(wet, Ordering::Relaxed);
+    }
+    info!("moisture calibration, dry:{} wet:{}", dry(), wet());
+    Ok(())
+}
+
+// `raw` as the new dry or wet end
+pub fn set(nvs: &EspDefaultNvsPartition, point: Point, raw: u16) -> Result<()> {
+    let Calibration { dry, wet } = current().with(point, raw).map_err(|e| anyhow!(e))?;
+    let mut store = open(nvs)?;
+    store.set_u16(KEY_DRY, dry)?;
+    store.set_u16(KEY_WET, wet)?;
+    DRY.store(dry, Ordering::Relaxed);
+    WET.store(wet, Ordering::Relaxed);
+    info!("moisture calibration saved, dry:{} wet:{}", dry, wet);
+    Ok(())
+}
+
+pub fn current() -> Calibration {
+    Calibration { dry: dry(), wet: wet() }
+}
+
+pub fn dry() -> u16 {
+    DRY.load(Ordering::Relaxed)
+}
+
+pub fn wet() -> u16 {
+    WET.load(Ordering::Relaxed)
+}
+
+// outside the calibrated range, the sensor is loose or broken
+pub fn in_range(raw: u16) -> bool {
+    current().in_range(raw)
+}
+
+// raw adc to humidity in %, clamped to 0..=100
+pub fn humidity(raw: u16) -> u32 {
+    current().humidity(raw)
+}
+
+fn open(nvs: &EspDefaultNvsPartition) -> Result<EspNvs<NvsDefault>> {
+    Ok(EspNvs::new(nvs.clone(), NAMESPACE, true)?)
+}
@@ -0,0 +1,22 @@
+// home assistant mqtt discovery
+// https://www.home-assistant.io/integrations/mqtt/#mqtt-discovery
+//
+// topics, discovery configs & command payloads are pumper_logic::ha, shared with ../pumper-sim.
+// this only puts the configs on the wire.
+
+use anyhow::Result;
+use esp_idf_svc::mqtt::client::EspMqttClient;
+use esp_idf_svc::mqtt::client::QoS::AtLeastOnce;
+use log::info;
+
+pub use pumper_logic::ha::{parse_number, parse_switch, HaTopics, VOLUME_MAX, VOLUME_MIN};
+
+// publish retained discovery configs, should be called once the client is connected
+pub fn publish_discovery(ha: &HaTopics, client: &mut EspMqttClient<'static>) -> Result<()> {
+    for (topic, config) in ha.discovery(env!("CARGO_PKG_VERSION")) {
+        let payload = serde_json::to_string(&config)?;
+        client.enqueue(&topic, AtLeastOnce, true, payload.as_bytes())?;
+        info!("ha discovery published:{}", topic);
+    }
+    Ok(())
+}
@@ -0,0 +1,80 @@
+// recent readings & waterings for the dashboard charts
+//
+// ram only, a reboot starts over. times are seconds since boot, there is no clock.
+
+use std::collections::VecDeque;
+use std::sync::Mutex;
+
+use embassy_time::Instant;
+use serde::Serialize;
+use serde_json::{json, Value};
+
+// one reading per 5 minutes, 24h
+const READING_INTERVAL_SECS: u32 = 5 * 60;
+const MAX_READINGS: usize = 288;
+const MAX_WATERINGS: usize = 50;
+
+#[derive(Clone, Copy, Serialize)]
+struct Reading {
+    t: u32,
+    solid_humidity: u32,
+    temperature: i32,
+    humidity: u32,
+}
+
+#[derive(Clone, Copy, Serialize)]
+struct Watering {
+    t: u32,
+    ml: u32,
+}
+
+struct History {
+    readings: VecDeque<Reading>,
+    waterings: VecDeque<Watering>,
+}
+
+static HISTORY: Mutex<History> = Mutex::new(History {
+    readings: VecDeque::new(),
+    waterings: VecDeque::new(),
+});
+
+fn uptime() -> u32 {
+    Instant::now().as_secs() as u32
+}
+
+// every sampling pass, only kept once per READING_INTERVAL_SECS
+pub fn push_reading(solid_humidity: u32, temperature: i32, humidity: u32) {
+    let t = uptime();
+    let mut history = HISTORY.lock().unwrap();
+    if history.readings.back().is_some_and(|last| t - last.t < READING_INTERVAL_SECS) {
+        return;
+    }
+    if history.readings.len() == MAX_READINGS {
+        history.readings.pop_front();
+    }
+    history.readings.push_back(Reading {
+        t,
+        solid_humidity,
+        temperature,
+        humidity,
+    });
+}
+
+pub fn push_watering(ml: u32) {
+    let t = uptime();
+    let mut history = HISTORY.lock().unwrap();
+    if history.waterings.len() == MAX_WATERINGS {
+        history.waterings.pop_front();
+    }
+    history.waterings.push_back(Watering { t, ml });
+}
+
+// GET /api/history
+pub fn to_json() -> Value {
+    let history = HISTORY.lock().unwrap();
+    json!({
+        "uptime": uptime(),
+        "readings": history.readings,
+        "waterings": history.waterings,
+    })
+}
@@ -0,0 +1,169 @@
+// status led, the ws2812 on gpio8 of the esp32-c3-devkitm-1, driven by rmt
+//
+// the other tasks only raise & clear conditions, led_task shows the most important one:
+//
+//   pumping        cyan, breathing
+//   low water      orange, blinking
+//   sensor fault   red, double flash
+//   wifi down      blue, fast blinking
+//   mqtt down      yellow, slow blinking
+//   booting        white
+//   all fine       green, short blip every 3s
+//
+// `status_led_brightness` 0 leaves the led dark, not used in battery mode.
+
+use std::sync::atomic::{AtomicU8, Ordering};
+use std::time::Duration;
+
+use anyhow::Result;
+use embassy_time::{Instant, Ticker};
+use esp_idf_svc::hal::gpio::OutputPin;
+use esp_idf_svc::hal::peripheral::Peripheral;
+use esp_idf_svc::hal::rmt::config::TransmitConfig;
+use esp_idf_svc::hal::rmt::{FixedLengthSignal, PinState, Pulse, RmtChannel, TxRmtDriver};
+
+// animation step
+const FRAME_MS: u64 = 20;
+
+#[derive(Clone, Copy, Debug, PartialEq)]
+#[repr(u8)]
+pub enum Condition {
+    Booting = 1 << 0,
+    MqttDown = 1 << 1,
+    WifiDown = 1 << 2,
+    SensorFault = 1 << 3,
+    LowWater = 1 << 4,
+    Pumping = 1 << 5,
+}
+
+impl Condition {
+    // most important first
+    const PRIORITY: [Self; 6] = [
+        Self::Pumping,
+        Self::LowWater,
+        Self::SensorFault,
+        Self::WifiDown,
+        Self::MqttDown,
+        Self::Booting,
+    ];
+}
+
+static CONDITIONS: AtomicU8 = AtomicU8::new(Condition::Booting as u8);
+
+pub fn set(condition: Condition, active: bool) {
+    if active {
+        CONDITIONS.fetch_or(condition as u8, Ordering::Relaxed);
+    } else {
+        CONDITIONS.fetch_and(!(condition as u8), Ordering::Relaxed);
+    }
+}
+
+fn current() -> Option<Condition> {
+    let conditions = CONDITIONS.load(Ordering::Relaxed);
+    Condition::PRIORITY
+        .into_iter()
+        .find(|condition| conditions & *condition as u8 != 0)
+}
+
+#[derive(Clone, Copy, Debug, PartialEq)]
+struct Rgb {
+    r: u8,
+    g: u8,
+    b: u8,
+}
+
+impl Rgb {
+    const OFF: Self = Self::new(0, 0, 0);
+
+    const fn new(r: u8, g: u8, b: u8) -> Self {
+        Self { r, g, b }
+    }
+
+    // level 0..=255
+    fn scale(&self, level: u32) -> Self {
+        let scale = |c: u8| (c as u32 * level / 255) as u8;
+        Self::new(scale(self.r), scale(self.g), scale(self.b))
+    }
+}
+
+// color of `condition`, `ms` into the animation
+fn frame(condition: Option<Condition>, ms: u64) -> Rgb {
+    let blink = |color: Rgb, period: u64| if ms % period < period / 2 { color } else { Rgb::OFF };
+    match condition {
+        Some(Condition::Pumping) => {
+            // triangle wave, 2s
+            let phase = (ms % 2000) as u32;
+            let level = if phase < 1000 { phase } else { 2000 - phase };
+            Rgb::new(0, 255, 255).scale(level * 255 / 1000)
+        }
+        Some(Condition::LowWater) => blink(Rgb::new(255, 80, 0), 1000),
+        Some(Condition::SensorFault) => match ms % 1500 {
+            0..150 | 300..450 => Rgb::new(255, 0, 0),
+            _ => Rgb::OFF,
+        },
+        Some(Condition::WifiDown) => blink(Rgb::new(0, 0, 255), 400),
+        Some(Condition::MqttDown) => blink(Rgb::new(255, 200, 0), 2000),
+        Some(Condition::Booting) => Rgb::new(255, 255, 255),
+        None => match ms % 3000 {
+            0..100 => Rgb::new(0, 255, 0),
+            _ => Rgb::OFF,
+        },
+    }
+}
+
+pub struct StatusLed<'d> {
+    tx: TxRmtDriver<'d>,
+    brightness: u8,
+    shown: Option<Rgb>,
+}
+
+impl<'d> StatusLed<'d> {
+    pub fn new(
+        channel: impl Peripheral<P = impl RmtChannel> + 'd,
+        pin: impl Peripheral<P = impl OutputPin> + 'd,
+        brightness: u8,
+    ) -> Result<Self> {
+        // 80MHz, 12.5ns per tick, fine enough for the ws2812 timings
+        let tx = TxRmtDriver::new(channel, pin, &TransmitConfig::new().clock_divider(1))?;
+        let mut led = Self { tx, brightness, shown: None };
+        led.show(frame(current(), 0))?;
+        Ok(led)
+    }
+
+    // skips the write if nothing changed
+    fn show(&mut self, color: Rgb) -> Result<()> {
+        let color = color.scale(self.brightness as u32);
+        if self.shown == Some(color) {
+            return Ok(());
+        }
+        self.write(color)?;
+        self.shown = Some(color);
+        Ok(())
+    }
+
+    // 24 bits, green red blue, msb first
+    fn write(&mut self, color: Rgb) -> Result<()> {
+        let ticks_hz = self.tx.counter_clock()?;
+        let pulse = |state, ns| Pulse::new_with_duration(ticks_hz, state, &Duration::from_nanos(ns));
+        let zero = (pulse(PinState::High, 350)?, pulse(PinState::Low, 800)?);
+        let one = (pulse(PinState::High, 700)?, pulse(PinState::Low, 600)?);
+
+        let bits = (color.g as u32) << 16 | (color.r as u32) << 8 | color.b as u32;
+        let mut signal = FixedLengthSignal::<24>::new();
+        for i in 0..24 {
+            let bit = bits & (1 << (23 - i)) != 0;
+            signal.set(i, if bit { &one } else { &zero })?;
+        }
+        self.tx.start_blocking(&signal)?;
+        Ok(())
+    }
+}
+
+pub async fn led_task(led: &mut StatusLed<'_>) -> Result<()> {
+    let started = Instant::now();
+    let mut ticker = Ticker::every(embassy_time::Duration::from_millis(FRAME_MS));
+    loop {
+        led.show(frame(current(), started.elapsed().as_millis()))?;
+        ticker.next().await;
+    }
+}
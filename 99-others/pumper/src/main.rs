@@ -1,46 +1,58 @@
+mod api;
+mod battery;
+mod broker;
+mod calibration;
+#[cfg(feature = "ble-provision")]
+mod ble_provision;
+mod ha;
+mod history;
+mod led;
+mod metrics;
+mod ota;
+mod provision;
+mod remote_log;
+mod settings;
+mod sleep;
+mod supervisor;
+mod tasks;
+
 use core::str;
-use std::time::{Duration, SystemTime};
+use std::time::{Duration, Instant};
 
-use anyhow::{Result,Error};
+use anyhow::{bail, Result,Error};
+use broker::BrokerEndpoint;
 use dht_sensor::{dht11, DhtReading};
+use embassy_time::Timer;
+use embassy_futures::select::{select, select3, select4, Either, Either3, Either4};
 use esp_idf_svc::eventloop::EspSystemEventLoop;
 use esp_idf_svc::hal::{adc, delay, gpio};
 use esp_idf_svc::hal::adc::attenuation::DB_11;
 use esp_idf_svc::hal::adc::oneshot::config::AdcChannelConfig;
 use esp_idf_svc::hal::adc::oneshot::{AdcChannelDriver, AdcDriver};
 use esp_idf_svc::hal::delay::{Delay, FreeRtos};
-use esp_idf_svc::hal::gpio::{AnyIOPin, Gpio0, InputOutput, Level, PinDriver, Pins};
-use esp_idf_svc::hal::prelude::Peripherals;
-use esp_idf_svc::mqtt;
-use esp_idf_svc::mqtt::client::QoS::AtMostOnce;
-use esp_idf_svc::mqtt::client::{EspMqttClient, EventPayload, MqttProtocolVersion};
-use esp_idf_svc::nvs::EspDefaultNvsPartition;
-use esp_idf_svc::sys::{payload_transfer_func, EspError};
-use esp_idf_svc::wifi::{BlockingWifi, ClientConfiguration, Configuration, EspWifi};
+use esp_idf_svc::hal::gpio::{AnyIOPin, Gpio0, Gpio1, Gpio3, Gpio9, InputOutput, Level, PinDriver, Pins, Pull};
+use esp_idf_svc::hal::task::block_on;
+use esp_idf_svc::hal::task::watchdog::{TWDTConfig, TWDTDriver};
+use esp_idf_svc::mqtt::client::QoS::{AtLeastOnce, AtMostOnce};
+use esp_idf_svc::mqtt::client::EspMqttClient;
+use esp_idf_svc::sys::payload_transfer_func;
+use esp_idf_svc::wifi::EspWifi;
+use funny_core::crash::{self, CrashReport};
+use funny_core::mdns;
+use funny_core::mqtt::{self, MqttClient, AVAILABILITY_OFFLINE, AVAILABILITY_ONLINE};
+use funny_core::mqtt5::{self, PublishProperties, ReplyTo};
+use funny_core::wifi::{self, KnownNetwork, WifiManager};
+use funny_core::{embedded_certificates, Device, MqttConfig, MqttEvent, Protocol, Telemetry, WifiConfig};
+use ha::HaTopics;
 use log::{error, info, warn};
+use ota::{OtaStatus, PendingConfirm};
+use pumper_logic::command::{CloudCommand, Instruct};
+use pumper_logic::telemetry::{BatchSample, MqttMsg};
+use pumper_logic::watering::{convert_volume_to_pumperworking_time_ms, filter_moisture, should_water, MOISTURE_READS};
+use settings::Settings;
+use sleep::{RtcState, Sample};
 use serde::{Deserialize, Serialize};
 
-#[derive(Serialize, Deserialize,Debug)]
-struct MqttMsg{
-    solid_humidity:Option<u32>,
-    relay:Option<bool>,
-    amount_total:Option<u32>,
-    environment_temperature:Option<u32>,
-    environment_humidity:Option<u32>,
-}
-impl MqttMsg {
-    fn new()->Self {
-        Self{
-            solid_humidity:None,
-            relay:None,
-            amount_total:None,
-            environment_humidity:None,
-            environment_temperature:None,
-        }
-    }
-}
-
-
 #[derive(Serialize, Deserialize,Debug)]
 struct SolidHumidity {
     solid_humidity: u32,
@@ -65,32 +77,72 @@ struct WateringAmount {
     amount_total: u32,
 }
 
-#[derive(Serialize, Deserialize)]
-enum Instruct {
-    Volumn(u32),
+// commands from mqtt callback to the tasks, see tasks::COMMANDS
+enum LocalCommand {
+    // switch pump on/off
+    Relay(bool),
+    // water once, as ml, from the http api
+    Water(u32),
+    // take the current moisture reading as dry or wet end
+    Calibrate(calibration::Point),
+    // change watering volume, as ml
+    Volume(u32),
+    // (re)connected to broker, publish birth message
+    MqttConnected,
+    // planned reboot
+    Reboot,
+    // firmware update
+    Ota { url: String, version: String, signature: String },
 }
 
-#[derive(Serialize, Deserialize)]
-struct CloudCommand {
-    method: String,
-    params: Instruct,
+// mqtt 5 request/response, the answer to cloud command `id`, see tasks::REPLIES
+struct Reply {
+    to: ReplyTo,
     id: u32,
+    result: Result<(), String>,
 }
 
-// pumper flow, as ”X ml/min“ usually can be found at motors
-const PUMPER_FLOW: u32 = 50;
+// routes incoming mqtt messages to the tasks
+// runs in the funny_core::mqtt callback, 3.1.1 & 5 alike
+struct MessageRouter {
+    ha_relay_topic: String,
+    ha_volume_topic: String,
+}
 
-// range of Plant Moisture Meter in water & air
-// as the max & min value can read from adcpin
+impl MessageRouter {
+    fn dispatch(&self, topic: &str, data: &[u8], reply: Option<ReplyTo>) {
+        info!("Received from MQTT topic:{:?}", topic);
+        if !self.ha_relay_topic.is_empty() && topic == self.ha_relay_topic {
+            match ha::parse_switch(data) {
+                Some(on) => {
+                    send_local_command(LocalCommand::Relay(on));
+                }
+                None => error!("Phase HA switch command failed"),
+            }
+        } else if !self.ha_volume_topic.is_empty() && topic == self.ha_volume_topic {
+            match ha::parse_number(data) {
+                Some(val) => {
+                    send_local_command(LocalCommand::Volume(val));
+                }
+                None => error!("Phase HA number command failed, volume must be {}..={}", ha::VOLUME_MIN, ha::VOLUME_MAX),
+            }
+        } else {
+            received_message(data, reply);
+        }
+    }
+}
 
-// only fit for "Capacltlve Soll Molsture Sensor v2.0"
-const MOISTURE_IN_WATER: u16 = 1450;
-const MOISTURE_IN_AIR: u16 = 2837;
+// pump flow, moisture sensor range & when to water are pumper_logic::watering
 
 // sample time
 // as ms
 // default 30s( 30*1000 )
 const LOOP_INTERVAL: u32 = 15 * 1000;
+const RELAY_GPIO: i32 = 9;
+// battery mode, how long a publishing wake-up waits for wifi & mqtt
+const WAKE_CONNECT_TIMEOUT: Duration = Duration::from_secs(20);
+// battery mode, stay connected a bit for queued commands & to flush the outbox
+const WAKE_LINGER_MS: u32 = 2000;
 
 #[toml_cfg::toml_config]
 pub struct Config {
@@ -112,32 +164,148 @@ pub struct Config {
     mqtt_subscribe_topic: &'static str,
     #[default("")]
     pumper_volume: &'static str,
+    // home assistant mqtt discovery
+    #[default(false)]
+    ha_discovery: bool,
+    #[default("homeassistant")]
+    ha_discovery_prefix: &'static str,
+    // default to mqtt_clientid
+    #[default("")]
+    ha_node_id: &'static str,
+    // retained "online" birth & "offline" last will
+    // empty to disable
+    #[default("")]
+    mqtt_availability_topic: &'static str,
+    // "3.1.1" or "5", keep 3.1.1 for ThingsCloud
+    #[default("3.1.1")]
+    mqtt_protocol: &'static str,
+    // mqtt 5 only, telemetry expires on the broker after N seconds, 0 to disable
+    #[default(0)]
+    mqtt_message_expiry: u32,
+    // backup brokers after mqtt_host, "url|user|pass;url|user|pass"
+    #[default("")]
+    mqtt_fallback_brokers: &'static str,
+    // switch to next broker when disconnected for N seconds
+    #[default(60)]
+    mqtt_failover_secs: u32,
+    // try primary broker again after N seconds on a fallback
+    #[default(600)]
+    mqtt_primary_retry_secs: u32,
+    // more known networks after wifi_ssid, "ssid|psk;ssid|psk", the strongest visible one is used
+    #[default("")]
+    wifi_networks: &'static str,
+    // reboot into provisioning ap after N failed wifi connects in a row, 0 to disable
+    // only while the credentials never got an ip
+    #[default(8)]
+    wifi_ap_fallback_failures: u32,
+    // status led on gpio8, 0..255, 0 to keep it dark
+    #[default(32)]
+    status_led_brightness: u8,
+    // float switch in the water tank on gpio5, open = low water
+    #[default(false)]
+    water_level_sensor: bool,
+    // local http api on port 80, see api.rs
+    #[default(true)]
+    http_api: bool,
+    // bearer token for the api's POST/PUT endpoints, empty refuses them
+    #[default("")]
+    http_api_token: &'static str,
+    // answer as funny-pumper-xxxx.local & advertise the services, see mdns.rs
+    #[default(true)]
+    mdns: bool,
+    // reset reason & last panic after every boot, empty to use mqtt_topic
+    #[default("")]
+    crash_topic: &'static str,
+    // offline for N seconds: reconnect, restart the network stack, reboot. 0 skips the level
+    #[default(300)]
+    supervisor_reconnect_secs: u32,
+    #[default(900)]
+    supervisor_restart_secs: u32,
+    #[default(3600)]
+    supervisor_reboot_secs: u32,
+    // copy log lines to "mqtt" or "syslog", empty to disable, see remote_log.rs
+    #[default("")]
+    remote_log: &'static str,
+    // "error", "warn", "info", "debug" or "trace", changeable at runtime
+    #[default("warn")]
+    remote_log_level: &'static str,
+    #[default("log")]
+    remote_log_topic: &'static str,
+    // syslog server, host:port
+    #[default("")]
+    remote_log_syslog: &'static str,
+    // button long press pauses automatic watering for N minutes
+    #[default(60)]
+    button_pause_minutes: u32,
+    // ble provisioning pairing passkey, 6 digits, 0 for no pairing
+    #[default(0)]
+    ble_passkey: u32,
+    // ed25519 public key from `ota-sign keygen`, hex, empty disables ota
+    #[default("")]
+    ota_public_key: &'static str,
+    // new firmware must reach wifi & mqtt within N minutes, or it rolls back
+    #[default(5)]
+    ota_confirm_minutes: u32,
+    // battery mode, deep sleep N seconds between samples, 0 to stay awake
+    #[default(0)]
+    deep_sleep_secs: u32,
+    // battery mode, connect & publish every N wake-ups
+    #[default(4)]
+    deep_sleep_batch: u32,
+    // "li-ion" or "lifepo4", empty to disable battery monitoring
+    #[default("")]
+    battery_chemistry: &'static str,
+    // (R1 + R2) / R2 of the divider in front of gpio1
+    #[default(2.0)]
+    battery_divider: f32,
+    // cells in series
+    #[default(1)]
+    battery_cells: u32,
+    // don't start the pump below this pack voltage, 0 for the chemistry default
+    #[default(0)]
+    battery_pump_cutoff_mv: u32,
 }
 
 fn main() -> anyhow::Result<()> {
-    // It is necessary to call this function once. Otherwise some patches to the runtime
-    // implemented by esp-idf-sys might not link properly. See https://github.com/esp-rs/esp-idf-template/issues/71
-    esp_idf_svc::sys::link_patches();
-
-    // Bind the log crate to the ESP Logging facilities
-    esp_idf_svc::log::EspLogger::initialize_default();
+    // sysloop, nvs & peripherals, see funny-core
+    // the log crate goes to the ESP Logging facilities, copies to remote_log
+    let Device { sysloop, nvs, peripherals } = Device::take_with_logger(remote_log::init)?;
 
-    let sysloop = EspSystemEventLoop::take()?;
-    let nvs = EspDefaultNvsPartition::take()?;
-    let peripherals = Peripherals::take()?;
+    // reset reason & last panic, published once mqtt is up
+    let crash_report = match crash::boot(&nvs, ota::FIRMWARE_VERSION) {
+        Ok(report) => report,
+        Err(e) => {
+            error!("crash report error:{}", e);
+            None
+        }
+    };
 
     // Hardware Setup
     // wifi
-    let mut wifi = BlockingWifi::wrap(
-        EspWifi::new(peripherals.modem, sysloop.clone(), Some(nvs))?,
-        sysloop.clone(),
-    )?;
+    let wifi_driver = wifi::driver(peripherals.modem, &sysloop, &nvs)?;
+
+    // status led, shows booting right away
+    // use pin: gpio8, the devkit's ws2812, see led.rs
+    let mut status_led = match CONFIG.status_led_brightness {
+        // battery mode, it draws current even when dark
+        _ if CONFIG.deep_sleep_secs > 0 => None,
+        0 => None,
+        brightness => Some(led::StatusLed::new(peripherals.rmt.channel0, peripherals.pins.gpio8, brightness)?),
+    };
+
+    // button
+    // use pin: gpio4, to gnd
+    // hold it while power on to start provisioning, later see tasks::button_task
+    let mut button = PinDriver::input(peripherals.pins.gpio4)?;
+    button.set_pull(Pull::Up)?;
     // relay
     // control the pump suck the water
     // use pin: gpio9
     // set low to stop & high to start,pump should keep stop as default
+    // held low during deep sleep, release before use
     let mut relay_pin: PinDriver<'_, esp_idf_svc::hal::gpio::Gpio9, esp_idf_svc::hal::gpio::InputOutput> = PinDriver::input_output(peripherals.pins.gpio9)?;
     relay_pin.set_low()?;
+    sleep::release_hold(RELAY_GPIO);
 
     // Plant Moisture Meter
     // One-shot ADC get the sample data from adc
@@ -155,6 +323,24 @@ fn main() -> anyhow::Result<()> {
     let mut adc: AdcChannelDriver<'_, Gpio0, &AdcDriver<'_, esp_idf_svc::hal::adc::ADC1>> =
         AdcChannelDriver::new(&adc_1_channel_0, peripherals.pins.gpio0, &config)?;
 
+    // battery voltage, optional
+    // use pin: gpio1, through a resistor divider, see battery.rs
+    let mut battery_channel = match battery::init(CONFIG.battery_chemistry, CONFIG.battery_cells, CONFIG.battery_divider) {
+        Some(_) => Some(AdcChannelDriver::new(&adc_1_channel_0, peripherals.pins.gpio1, &config)?),
+        None => None,
+    };
+
+    // water level, optional
+    // use pin: gpio5, float switch to gnd, closed while there is water
+    let water_level = match CONFIG.water_level_sensor {
+        true => {
+            let mut pin = PinDriver::input(peripherals.pins.gpio5)?;
+            pin.set_pull(Pull::Up)?;
+            Some(pin)
+        }
+        false => None,
+    };
+
     // dht11
     let pin = peripherals.pins.gpio3;
     let mut dht_sensor = gpio::PinDriver::input_output(pin)?;
@@ -165,310 +351,694 @@ fn main() -> anyhow::Result<()> {
     
     // Process Init
     let app_config = CONFIG;
+    // wifi & mqtt credentials, from nvs or cfg.toml
+    let settings = Settings::load(&nvs)?;
+    calibration::load(&nvs)?;
+
+    // provisioning, never returns, restarts after settings are saved
+    let provision_requested = settings::take_provisioning_request(&nvs)?;
+    if !settings.is_provisioned() || provision_requested || button.is_low() {
+        info!("start provisioning");
+        provision::run(wifi_driver, &sysloop, &nvs, &settings)?;
+    }
+
     // connect wifi
-    while let Err(_) = wifi_connect(&mut wifi) {
-        wifi_connect(&mut wifi)?;
+    // reconnects by itself in background, never blocks the loop
+    let networks = WifiConfig {
+        ssid: &settings.wifi_ssid,
+        psk: &settings.wifi_psk,
+        networks: app_config.wifi_networks,
     }
+    .known_networks();
+    // first boot after an ota update, confirmed once wifi & mqtt are up
+    let ota_pending = PendingConfirm::check_boot(app_config.ota_confirm_minutes)?;
 
-    // init mqtt client
-    let mut client = mqtt_client_connect()?;
+    // home assistant
+    let ha = if app_config.ha_discovery {
+        let node_id = match app_config.ha_node_id {
+            "" => app_config.mqtt_clientid,
+            id => id,
+        };
+        let mut ha = HaTopics::new(app_config.ha_discovery_prefix, node_id, app_config.mqtt_availability_topic);
+        ha.battery = battery_channel.is_some();
+        Some(ha)
+    } else {
+        None
+    };
 
-    // delays
-    
+    // battery mode, one sample per wake-up, never returns
+    if app_config.deep_sleep_secs > 0 {
+        return low_power_cycle(
+            wifi_driver,
+            &sysloop,
+            networks,
+            &settings,
+            ha.as_ref(),
+            ota_pending,
+            crash_report,
+            &mut relay_pin,
+            &adc_1_channel_0,
+            &mut adc,
+            battery_channel.as_mut(),
+            &mut dht_sensor,
+        );
+    }
 
-    // loop
-    loop {
-        info!("start loop at:{:?}",SystemTime::now());
-        // check wifi status
-        wifi_health_checker(&mut wifi);
+    let wifi = WifiManager::start(wifi_driver, &sysloop, networks, None)?;
 
-        // init mqtt msg struct
-        let mut mqtt_msg = MqttMsg::new();
+    // watering volume, can be changed from home assistant
+    let volume = app_config.pumper_volume.parse::<u32>()?;
 
+    // local control & /metrics, handlers run on the httpd task & talk to the tasks like mqtt does
+    let api_server = match app_config.http_api {
+        true => Some(api::start(&nvs, &settings, wifi.state.clone())?),
+        false => None,
+    };
 
-        // may use a struct hold all device handler,like a tree
-        // may much much better
-        // may do better at next version 
+    // no router lookup for the ip, the http services only while the api runs
+    let _mdns = match app_config.mdns {
+        true => {
+            let http_port = api_server.as_ref().map(|_| api::HTTP_PORT);
+            let caps = mdns_capabilities(http_port.is_some());
+            mdns::start("pumper", ota::FIRMWARE_VERSION, &caps, http_port)
+                .map_err(|e| error!("mdns error:{}", e))
+                .ok()
+        }
+        false => None,
+    };
 
-        // get relay status
-        match relay_pin.get_level() {
-            Level::Low => mqtt_msg.relay = Some(false),
-            Level::High => mqtt_msg.relay = Some(true),
+    // the main task runs all the tasks below, the supervisor feeds the watchdog for it
+    let mut twdt = TWDTDriver::new(
+        peripherals.twdt,
+        &TWDTConfig {
+            duration: supervisor::WATCHDOG_TIMEOUT,
+            panic_on_trigger: true,
+            ..Default::default()
+        },
+    )?;
+    let wifi_state = wifi.state.clone();
+
+    // sensing, network, commands, pump, button, led & supervisor side by side, see tasks.rs
+    // they run forever, only an error ends them
+    info!("start tasks");
+    led::set(led::Condition::Booting, false);
+    block_on(async {
+        let tasks = select4(
+            tasks::sensor_task(&adc_1_channel_0, &mut adc, battery_channel.as_mut(), &mut dht_sensor, water_level.as_ref(), &nvs),
+            tasks::network_task(wifi, &nvs, &settings, ha.as_ref(), ota_pending, crash_report, volume),
+            tasks::command_task(),
+            tasks::pump_task(&mut relay_pin, water_level.as_ref(), volume),
+        );
+        let status_led = async {
+            match status_led.as_mut() {
+                Some(status_led) => led::led_task(status_led).await,
+                None => core::future::pending().await,
+            }
+        };
+        let local = select3(
+            tasks::button_task(&mut button),
+            status_led,
+            tasks::supervisor_task(&mut twdt, wifi_state),
+        );
+        match select(tasks, local).await {
+            Either::First(Either4::First(result) | Either4::Second(result) | Either4::Third(result) | Either4::Fourth(result))
+            | Either::Second(Either3::First(result) | Either3::Second(result) | Either3::Third(result)) => result,
         }
-        // get dht11 
-        match dht11::Reading::read(&mut delay::Ets , &mut dht_sensor) {
-            Ok(res) => {
-                mqtt_msg.environment_temperature = Some(res.temperature as u32);
-                mqtt_msg.environment_humidity = Some(res.relative_humidity as u32);
-            },
-            Err(e) => {
-                error!("dht11 error:{:?}",e);
-                continue;
-            },
+    })
+    .inspect_err(crash::record_error)
+}
+
+// battery mode, one wake-up: sample, maybe water, maybe publish, deep sleep again
+#[allow(clippy::too_many_arguments)]
+fn low_power_cycle(
+    wifi_driver: EspWifi<'static>,
+    sysloop: &EspSystemEventLoop,
+    networks: Vec<KnownNetwork>,
+    settings: &Settings,
+    ha: Option<&HaTopics>,
+    ota_pending: Option<PendingConfirm>,
+    crash_report: Option<CrashReport>,
+    relay_pin: &mut PinDriver<'_, Gpio9, InputOutput>,
+    adc_1_channel_0: &AdcDriver<'_, adc::ADC1>,
+    adc: &mut AdcChannelDriver<'_, Gpio0, &AdcDriver<'_, adc::ADC1>>,
+    battery_channel: Option<&mut AdcChannelDriver<'_, Gpio1, &AdcDriver<'_, adc::ADC1>>>,
+    dht_sensor: &mut PinDriver<'_, Gpio3, InputOutput>,
+) -> Result<()> {
+    let app_config = CONFIG;
+    let mut rtc = sleep::load();
+    rtc.wakeups += 1;
+    info!("wake-up {}, by timer:{}", rtc.wakeups, sleep::woke_from_timer());
+
+    let mut volume = app_config.pumper_volume.parse::<u32>()?;
+    if let Some(channel) = battery_channel {
+        if let Err(e) = battery::measure(adc_1_channel_0, channel) {
+            error!("battery measure error:{}", e);
         }
+    }
+    let mut sample = Sample {
+        wakeup: rtc.wakeups,
+        ..Default::default()
+    };
+    let mut temperature = None;
+    match dht11::Reading::read(&mut delay::Ets, dht_sensor) {
+        Ok(res) => {
+            sample.environment_temperature = res.temperature;
+            sample.environment_humidity = res.relative_humidity;
+            temperature = Some(res.temperature);
+        }
+        Err(e) => error!("dht11 error:{:?}", e),
+    }
 
-        // read adc
-        // should do adc adjust,make moisture into 2 stage, low value enable pumper water
-        // and high value do next check
-        //
-        // filter： do read value 10 times in 10 secs
-        // then cal total sum of 10 times，and sub max&min value，
-        // todo! make pumper threshold tobe a var
-
-        let mut moistures = Vec::new();
-
-        for mut _i in 0..10 {
-            match adc_1_channel_0.read(&mut adc) {
-                Ok(val) => {
-                    if val < MOISTURE_IN_WATER || val > MOISTURE_IN_AIR {
-                        error!("moisture sensor error:{}",val);
-                        // restart();
-                    }
-                    moistures.push(val);
-                },
-                Err(e) => error!("read adc error:{}",e),
+    // same rule as awake mode, but on the filtered value, one bad reading should not water
+    let mut watered = false;
+    if let Some(humidity) = block_on(read_soil_humidity(adc_1_channel_0, adc)) {
+        let filtered = rtc.board.filter_humidity(rtc.wakeups, humidity);
+        info!("humidity:{} filtered:{}", humidity, filtered);
+        sample.solid_humidity = humidity as u8;
+        rtc.push_sample(sample);
+        if temperature.is_some_and(|t| should_water(t as i32, filtered)) {
+            if battery::pump_allowed() {
+                pumper_run_offline(relay_pin, volume)?;
+                rtc.board.amount_total += volume;
+                watered = true;
+            } else {
+                // no wifi just for this, it goes out with the next batch
+                rtc.board.pump_refused = true;
             }
-            
-            FreeRtos::delay_ms(1000);
-            _i += 1;
         }
-        let humidity:u32;
-        if moistures.len() == 10 {
-            let min_value = *moistures.iter().min().unwrap_or(&MOISTURE_IN_WATER);
-            let max_value = *moistures.iter().max().unwrap_or(&MOISTURE_IN_AIR);
-            let moisture = (moistures.iter().sum::<u16>() - min_value - max_value) / 8;
-            humidity = convert_moisture_to_humidity_u16(moisture);
-
-        }else {
-            error!("read moisture sensor 10 times");
-            continue;
-        }
-        
-        info!("humidity:{}",humidity);
-        mqtt_msg.solid_humidity = Some(humidity);
+    }
 
-        match mqtt_send_msg(&mut client,&mut mqtt_msg){
+    // first wake-up after power on, every `deep_sleep_batch` wake-ups, after watering,
+    // and right away when a new firmware has to confirm itself
+    let batch = app_config.deep_sleep_batch.max(1);
+    if rtc.wakeups == 1 || rtc.wakeups % batch == 0 || watered || ota_pending.is_some() {
+        let wifi = WifiManager::start(wifi_driver, sysloop, networks, rtc.ap_hint())?;
+        let endpoint = BrokerEndpoint {
+            url: settings.mqtt_host.clone(),
+            user: settings.mqtt_user.clone(),
+            pass: settings.mqtt_pass.clone(),
+        };
+        match low_power_publish(&wifi, &endpoint, ha, ota_pending.as_ref(), crash_report.as_ref(), &mut rtc, relay_pin, &mut volume) {
             Ok(_) => {
-                info!("send step 1");
-            },
-            Err(e) => error!("mqtt client error:{}",e),
+                rtc.clear_samples();
+                rtc.board.pump_refused = false;
+            }
+            Err(e) => error!("publish error:{}, keep {} samples", e, rtc.samples().len()),
         }
+    }
 
-        // adjust moistures
-        // commet for real run
-        // let value = adc_1_channel_0.read(&mut adc)?;
-        // info!("sample moistures: {:?}", value);
+    sleep::store(&rtc);
+    relay_pin.set_low()?;
+    sleep::hold_during_sleep(RELAY_GPIO);
+    sleep::deep_sleep(Duration::from_secs(app_config.deep_sleep_secs as u64))
+}
 
-        if  mqtt_msg.environment_temperature != None &&  mqtt_msg.environment_temperature < Some(2){
-            continue;
+// battery mode, bring up mqtt, publish the samples & take commands for a moment
+fn low_power_publish(
+    wifi: &WifiManager,
+    endpoint: &BrokerEndpoint,
+    ha: Option<&HaTopics>,
+    ota_pending: Option<&PendingConfirm>,
+    crash_report: Option<&CrashReport>,
+    rtc: &mut RtcState,
+    relay_pin: &mut PinDriver<'_, Gpio9, InputOutput>,
+    volume: &mut u32,
+) -> Result<()> {
+    let app_config = CONFIG;
+    // a new firmware gets its whole confirm window
+    let timeout = match ota_pending {
+        Some(_) => Duration::from_secs(app_config.ota_confirm_minutes as u64 * 60),
+        None => WAKE_CONNECT_TIMEOUT,
+    };
+    let started = Instant::now();
+    while !wifi.state.is_up() && started.elapsed() < timeout {
+        FreeRtos::delay_ms(100);
+    }
+    if !wifi.state.is_up() {
+        // cached ap is gone?, scan next time
+        rtc.set_ap_hint(None);
+        if let Some(pending) = ota_pending {
+            pending.check(false)?;
         }
+        bail!("wifi not up in {:?}", timeout);
+    }
+    rtc.set_ap_hint(wifi.state.ap_hint());
 
-        // commet "match xxx" code if you adjust Plant Moisture Meter threshold
-        match humidity {
-            0..30 => {
-                
-                // run pumper time
-                let volume = app_config.pumper_volume.parse::<u32>()?;
-                let time = convert_volume_to_pumperworking_time_ms(volume
-                );
-                info!(
-                    "pump starting!\nwater: {}ml, working time: {}ms",
-                    app_config.pumper_volume, time
-                );
-
-                relay_pin.set_high()?;
-                mqtt_msg.relay = Some(true);
-
-                // mqtt publish gap
-                FreeRtos::delay_ms(5000);
-                match mqtt_send_msg(&mut client,&mut mqtt_msg){
-                    Ok(_) => {
-                        info!("send step 2");
-                    },
-                    Err(e) => error!("mqtt client error:{}",e),
-                };
+    let mut client = mqtt_client_connect(endpoint, ha)?;
+    while !broker::is_connected() && started.elapsed() < timeout {
+        FreeRtos::delay_ms(100);
+    }
+    if let Some(pending) = ota_pending {
+        if pending.check(broker::is_connected())? {
+            mqtt_send_ota_status(&mut client, &OtaStatus::new("confirmed", 100, ota::FIRMWARE_VERSION));
+        }
+    }
+    if !broker::is_connected() {
+        bail!("mqtt not connected in {:?}", timeout);
+    }
+    if let Some(report) = crash_report {
+        mqtt_send_crash_report(&mut client, report);
+    }
 
-                FreeRtos::delay_ms(time -5000);
+    // latest sample as the usual telemetry, all of them in `samples`
+    let mut mqtt_msg = MqttMsg::default();
+    if let Some(latest) = rtc.samples().last() {
+        mqtt_msg.solid_humidity = Some(latest.solid_humidity as u32);
+        mqtt_msg.environment_temperature = Some(latest.environment_temperature as i32);
+        mqtt_msg.environment_humidity = Some(latest.environment_humidity as u32);
+    }
+    mqtt_msg.relay = Some(false);
+    mqtt_msg.amount_total = Some(rtc.board.amount_total);
+    mqtt_msg.pumper_volume = Some(*volume);
+    mqtt_msg.mqtt_broker = Some(endpoint.host().to_string());
+    mqtt_msg.wifi_ssid = wifi.state.network();
+    mqtt_msg.wifi_rssi = wifi.state.rssi();
+    set_battery_fields(&mut mqtt_msg);
+    if rtc.board.pump_refused {
+        mqtt_msg.pump_refused = Some("battery_low".to_string());
+    }
+    mqtt_msg.samples = Some(
+        rtc.samples()
+            .iter()
+            .map(|sample| BatchSample {
+                sample: *sample,
+                age: (rtc.wakeups - sample.wakeup) * app_config.deep_sleep_secs,
+            })
+            .collect(),
+    );
 
-                while let Level::High = relay_pin.get_level(){
-                    relay_pin.set_low().ok();
-                    FreeRtos::delay_ms(100);
-                }
-                info!("pump stopped!");
+    // subscriptions & discovery, see LocalCommand::MqttConnected
+    handle_local_commands(relay_pin, &mut client, ha, &mut mqtt_msg, volume);
+    mqtt_send_msg(&mut client, ha, &mut mqtt_msg)?;
+    FreeRtos::delay_ms(WAKE_LINGER_MS);
+    // commands that came in meanwhile
+    handle_local_commands(relay_pin, &mut client, ha, &mut mqtt_msg, volume);
+    // planned sleep, gone until the next publishing wake-up. the last will only fires once
+    // the broker times out the session
+    mqtt_publish_availability(&mut client, AVAILABILITY_OFFLINE);
+    // give the mqtt task some time to flush
+    FreeRtos::delay_ms(500);
+    Ok(())
+}
 
-                mqtt_msg.relay = Some(false);
-                mqtt_msg.amount_total = Some(volume);
+// txt `caps` for mdns, comma separated there
+fn mdns_capabilities(http: bool) -> Vec<&'static str> {
+    let mut caps = vec!["mqtt", "water"];
+    if http {
+        caps.extend(["http", "metrics", "dashboard"]);
+    }
+    if CONFIG.ha_discovery {
+        caps.push("ha");
+    }
+    if !CONFIG.ota_public_key.is_empty() {
+        caps.push("ota");
+    }
+    if !CONFIG.battery_chemistry.is_empty() {
+        caps.push("battery");
+    }
+    if CONFIG.water_level_sensor {
+        caps.push("water_level");
+    }
+    caps
+}
 
-                match mqtt_send_msg(&mut client,&mut mqtt_msg){
-                    Ok(_) => {
-                        info!("send step 3");
-                    },
-                    Err(e) => error!("mqtt client error:{}",e),
-                }
-                
+// last battery measurement into telemetry
+fn set_battery_fields(mqtt_msg: &mut MqttMsg) {
+    if let Some(reading) = battery::last() {
+        mqtt_msg.battery_voltage = Some(reading.volts());
+        mqtt_msg.battery_soc = Some(reading.soc);
+    }
+}
 
-            }
-            30.. => {
-                info!("skip run pumper");
-            }
-        }
+// battery mode watering, nobody to report to yet
+fn pumper_run_offline(relay_pin: &mut PinDriver<'_, Gpio9, InputOutput>, volume: u32) -> Result<()> {
+    let time = convert_volume_to_pumperworking_time_ms(volume);
+    info!("pump starting!\nwater: {}ml, working time: {}ms", volume, time);
+    relay_pin.set_high()?;
+    FreeRtos::delay_ms(time);
+    while let Level::High = relay_pin.get_level() {
+        relay_pin.set_low().ok();
+        FreeRtos::delay_ms(100);
+    }
+    info!("pump stopped!");
+    Ok(())
+}
 
-        // loop interval
-        FreeRtos::delay_ms(LOOP_INTERVAL);
+// cfg.toml's mqtt keys, the broker itself from settings or the failover list
+fn mqtt_config(endpoint: &BrokerEndpoint) -> MqttConfig<'_> {
+    let app_config = CONFIG;
+    MqttConfig {
+        url: &endpoint.url,
+        user: &endpoint.user,
+        pass: &endpoint.pass,
+        client_id: app_config.mqtt_clientid,
+        protocol: Protocol::parse(app_config.mqtt_protocol),
+        availability_topic: app_config.mqtt_availability_topic,
+        network_timeout: Some(Duration::from_secs(5)),
     }
 }
 
-fn wifi_connect(wifi: &mut BlockingWifi<EspWifi>) -> Result<(), EspError> {
+fn telemetry() -> Telemetry<'static> {
     let app_config = CONFIG;
-    wifi.set_configuration(&Configuration::Client(ClientConfiguration {
-        ssid: app_config.wifi_ssid.try_into().unwrap(),
-        password: app_config.wifi_psk.try_into().unwrap(),
-        ..Default::default()
-    }))?;
+    Telemetry {
+        topic: app_config.mqtt_topic,
+        protocol: Protocol::parse(app_config.mqtt_protocol),
+        message_expiry: app_config.mqtt_message_expiry,
+        device: "pumper",
+        version: ota::FIRMWARE_VERSION,
+    }
+}
 
-    match wifi.start() {
-        Ok(_) => info!("Wifi started"),
-        Err(e) => {
-            error!("wifi start error:{}", e);
-            return Err(e);
+fn mqtt_client_connect(endpoint: &BrokerEndpoint, ha: Option<&HaTopics>) -> Result<MqttClient> {
+    let router = MessageRouter {
+        ha_relay_topic: ha.map(|ha| ha.relay_command()).unwrap_or_default(),
+        ha_volume_topic: ha.map(|ha| ha.volume_command()).unwrap_or_default(),
+    };
+    mqtt::connect(&mqtt_config(endpoint), &embedded_certificates!(), move |event| match event {
+        MqttEvent::Connected => {
+            broker::set_connected(true);
+            send_local_command(LocalCommand::MqttConnected);
         }
+        MqttEvent::Disconnected => broker::set_connected(false),
+        MqttEvent::Received { topic, data, reply } => router.dispatch(topic, data, reply),
+    })
+}
+
+// (re)subscribe after connect, a new or failed-over broker knows nothing about us
+fn mqtt_subscribe_topics(client: &mut EspMqttClient<'static>, ha: Option<&HaTopics>) {
+    let app_config = CONFIG;
+    let mut topics = vec![app_config.mqtt_subscribe_topic.to_string()];
+    if let Some(ha) = ha {
+        topics.push(ha.relay_command());
+        topics.push(ha.volume_command());
     }
-    match wifi.connect() {
-        Ok(_) => info!("Wifi connected"),
-        Err(e) => {
-            error!("wifi connect error:{}", e);
-            return Err(e);
+    for topic in topics {
+        match client.subscribe(&topic, AtMostOnce) {
+            Ok(_) => info!("Subscribed to topic:{}", topic),
+            Err(e) => error!("Subscribed error:{} topic:{}", e, topic),
         }
     }
-    match wifi.wait_netif_up() {
-        Ok(_) => info!("Wifi netif up"),
-        Err(e) => {
-            error!("wifi netif up error:{}", e);
-            return Err(e);
+}
+
+// called from the mqtt task, never wait for room
+// false when the command was dropped
+fn send_local_command(command: LocalCommand) -> bool {
+    if tasks::COMMANDS.try_send(command).is_err() {
+        error!("local command channel full, command dropped");
+        return false;
+    }
+    true
+}
+
+// battery mode, deal commands queued by mqtt callback right here, no tasks are running
+fn handle_local_commands(
+    relay_pin: &mut PinDriver<'_, Gpio9, InputOutput>,
+    client: &mut EspMqttClient<'static>,
+    ha: Option<&HaTopics>,
+    mqtt_msg: &mut MqttMsg,
+    volume: &mut u32,
+) {
+    while let Ok(command) = tasks::COMMANDS.try_receive() {
+        match command {
+            LocalCommand::Relay(true) => {
+                info!("receive pump on command, water: {}ml", volume);
+                if let Err(e) = pumper_run(relay_pin, client, ha, mqtt_msg, *volume) {
+                    error!("pumper run error:{}", e);
+                }
+            }
+            LocalCommand::Water(ml) => {
+                info!("receive water command: {}ml", ml);
+                if let Err(e) = pumper_run(relay_pin, client, ha, mqtt_msg, ml) {
+                    error!("pumper run error:{}", e);
+                }
+            }
+            LocalCommand::Calibrate(point) => warn!("calibrate {:?} not in battery mode", point),
+            LocalCommand::Relay(false) => {
+                info!("receive pump off command");
+                if let Err(e) = relay_pin.set_low() {
+                    error!("relay set low error:{}", e);
+                }
+                mqtt_msg.relay = Some(false);
+                let _ = mqtt_send_msg(client, ha, mqtt_msg);
+            }
+            LocalCommand::Volume(val) => {
+                info!("receive watering volume: {}ml", val);
+                *volume = val;
+                mqtt_msg.pumper_volume = Some(val);
+                let _ = mqtt_send_msg(client, ha, mqtt_msg);
+            }
+            LocalCommand::MqttConnected => {
+                mqtt_subscribe_topics(client, ha);
+                mqtt_publish_availability(client, AVAILABILITY_ONLINE);
+                if let Some(ha) = ha {
+                    if let Err(e) = ha::publish_discovery(ha, client) {
+                        error!("ha discovery error:{}", e);
+                    }
+                }
+            }
+            LocalCommand::Reboot => {
+                relay_pin.set_low().ok();
+                device_restart(client);
+            }
+            LocalCommand::Ota { url, version, signature } => {
+                // no watering while flashing, the download may take a minute
+                relay_pin.set_low().ok();
+                mqtt_send_replies(client);
+                let public_key = CONFIG.ota_public_key;
+                if ota::update(&url, &version, &signature, public_key, |status| mqtt_send_ota_status(client, status)).is_ok() {
+                    device_restart(client);
+                }
+            }
         }
     }
+    mqtt_send_replies(client);
+}
 
-    Ok(())
+// answer a mqtt 5 request, correlation data copied from it
+fn mqtt_send_reply(client: &mut EspMqttClient<'static>, reply: &Reply) {
+    let payload = match &reply.result {
+        Ok(()) => serde_json::json!({ "id": reply.id, "result": "ok" }),
+        Err(e) => serde_json::json!({ "id": reply.id, "result": "error", "error": e }),
+    };
+    let properties = PublishProperties {
+        correlation_data: Some(&reply.to.correlation_data),
+        ..Default::default()
+    };
+    let payload = payload.to_string();
+    if let Err(e) = mqtt5::enqueue(client, &reply.to.topic, AtMostOnce, false, payload.as_bytes(), &properties) {
+        error!("mqtt reply error:{}", e);
+    }
 }
 
-// wifi healthy check
-// if status down re-connect
-fn wifi_health_checker(wifi:&mut BlockingWifi<EspWifi>) {
-    if !wifi_is_ok(wifi) {
-        while let Some(_true) =  wifi.is_started().ok() {
-            let _ = wifi.stop();
-        };
+// every queued reply, before anything that does not come back (restart, ota)
+fn mqtt_send_replies(client: &mut EspMqttClient<'static>) {
+    while let Ok(reply) = tasks::REPLIES.try_receive() {
+        mqtt_send_reply(client, &reply);
+    }
+}
+
+// reset reason, boot count & last panic, to crash_topic or mqtt_topic
+fn mqtt_send_crash_report(client: &mut EspMqttClient<'static>, report: &CrashReport) {
+    let app_config = CONFIG;
+    let topic = match app_config.crash_topic {
+        "" => app_config.mqtt_topic,
+        topic => topic,
+    };
+    crash::publish(client, topic, report);
+}
 
-        while let Err(_) = wifi_connect(wifi) {
-            let _ = wifi_connect(wifi);
+// ota progress, to the telemetry topic
+fn mqtt_send_ota_status(client: &mut EspMqttClient<'static>, status: &OtaStatus) {
+    let app_config = CONFIG;
+    let payload = match serde_json::to_string(status) {
+        Ok(payload) => payload,
+        Err(e) => {
+            error!("Serialize ota status error:{}", e);
+            return;
         }
+    };
+    match client.enqueue(app_config.mqtt_topic, AtLeastOnce, false, payload.as_bytes()) {
+        Ok(_) => info!("send ota status:{}", payload),
+        Err(e) => error!("send ota status error:{}", e),
     }
-    info!("wifi reconnected!!");
 }
 
-// check if wifi status is ok
-fn wifi_is_ok(wifi:&mut BlockingWifi<EspWifi>) -> bool{
-    match wifi.is_up() {
-        Ok(status) => {
-            return status
-        },
-        Err(_) => false,
-    }
+// retained availability state, if enabled
+fn mqtt_publish_availability(client: &mut EspMqttClient<'static>, payload: &str) {
+    mqtt::publish_availability(client, CONFIG.mqtt_availability_topic, payload);
 }
 
-fn mqtt_client_connect() -> Result<EspMqttClient<'static>> {
-    // mqtt client
-    let app_config = CONFIG;
+// planned reboot
+// tell the broker we are going offline first, the last will only fire on unexpected disconnects
+fn device_restart(client: &mut EspMqttClient<'static>) -> ! {
+    warn!("device restarting");
+    // the reboot command's own reply is among them
+    mqtt_send_replies(client);
+    mqtt_publish_availability(client, AVAILABILITY_OFFLINE);
+    // give the mqtt task some time to flush
+    FreeRtos::delay_ms(500);
+    esp_idf_svc::hal::reset::restart();
+}
 
-    let mut client: EspMqttClient = EspMqttClient::new_cb(
-        app_config.mqtt_host,
-        &mqtt::client::MqttClientConfiguration {
-            client_id: Some(app_config.mqtt_clientid),
-            username: Some(app_config.mqtt_user),
-            password: Some(&app_config.mqtt_pass),
-            protocol_version: Some(MqttProtocolVersion::V3_1_1),
-            network_timeout: Duration::from_secs(5),
+// run pumper, pump `volume` ml water
+// battery mode only, when awake tasks::pump_task does this
+fn pumper_run(
+    relay_pin: &mut PinDriver<'_, Gpio9, InputOutput>,
+    client: &mut EspMqttClient<'static>,
+    ha: Option<&HaTopics>,
+    mqtt_msg: &mut MqttMsg,
+    volume: u32,
+) -> Result<()> {
+    if !battery::pump_allowed() {
+        mqtt_msg.pump_refused = Some("battery_low".to_string());
+        let _ = mqtt_send_msg(client, ha, mqtt_msg);
+        mqtt_msg.pump_refused = None;
+        return Ok(());
+    }
+    // run pumper time
+    let time = convert_volume_to_pumperworking_time_ms(volume);
+    info!(
+        "pump starting!\nwater: {}ml, working time: {}ms",
+        volume, time
+    );
 
-            ..Default::default()
+    relay_pin.set_high()?;
+    mqtt_msg.relay = Some(true);
+
+    // mqtt publish gap
+    FreeRtos::delay_ms(5000.min(time));
+    match mqtt_send_msg(client, ha, mqtt_msg){
+        Ok(_) => {
+            info!("send step 2");
         },
-        move |message_event| match message_event.payload() {
-            // EventPayload::Error(e) => error!("MQTT error {:?}", e),
-            // e => warn!("MQTT event {:?}", e),
-            EventPayload::Received {
-                id: _,
-                topic,
-                data,
-                details: _,
-            } => {
-                info!("Received from MQTT topic:{:?}", topic.unwrap_or_default());
-                received_message(data);
-            }
-            EventPayload::Error(e) => error!("MQTT error {:?}", e),
-            e => warn!("MQTT event {:?}", e),
+        Err(e) => error!("mqtt client error:{}",e),
+    };
+
+    FreeRtos::delay_ms(time.saturating_sub(5000));
+
+    while let Level::High = relay_pin.get_level(){
+        relay_pin.set_low().ok();
+        FreeRtos::delay_ms(100);
+    }
+    info!("pump stopped!");
+
+    mqtt_msg.relay = Some(false);
+    mqtt_msg.amount_total = Some(volume);
+
+    match mqtt_send_msg(client, ha, mqtt_msg){
+        Ok(_) => {
+            info!("send step 3");
         },
-    )?;
+        Err(e) => error!("mqtt client error:{}",e),
+    }
 
+    Ok(())
+}
 
-    let mut i: u32 = 0;
-    loop {
-        match client.subscribe(app_config.mqtt_subscribe_topic, AtMostOnce) {
-            Ok(_) => {
-                info!("Subscribed to topic:{}", app_config.mqtt_subscribe_topic);
-                break;
-            }
-            Err(e) => {
-                error!("Subscribed error:{} at {} times", e, i);
-                i += 1;
-            }
-        }
-        FreeRtos::delay_ms(1000);
+// telemetry to mqtt_topic, and the ha state topic if enabled
+fn mqtt_send_msg(client:&mut EspMqttClient<'static>,ha:Option<&HaTopics>,mqtt_msg:&mut MqttMsg)->Result<(),Error>{
+    let ha_state = ha.map(HaTopics::state);
+    if let Err(e) = telemetry().publish(client, ha_state.as_deref(), &*mqtt_msg) {
+        error!("mqtt client error:{}",e);
+        return Err(e);
     }
+    Ok(())
+}
 
-    Ok(client)
+// soil humidity in %
+async fn read_soil_humidity(
+    adc_1_channel_0: &AdcDriver<'_, adc::ADC1>,
+    adc: &mut AdcChannelDriver<'_, Gpio0, &AdcDriver<'_, adc::ADC1>>,
+) -> Option<u32> {
+    let Some(moisture) = read_soil_moisture(adc_1_channel_0, adc).await else {
+        metrics::sensor_error();
+        return None;
+    };
+    if !calibration::in_range(moisture) {
+        error!("moisture sensor error:{}",moisture);
+    }
+    let humidity = convert_moisture_to_humidity_u16(moisture);
+    metrics::set_moisture(moisture, humidity);
+    Some(humidity)
 }
 
-fn mqtt_send_msg(client:&mut EspMqttClient<'static>,mqtt_msg:&mut MqttMsg)->Result<(),Error>{
-    let app_config = CONFIG;
-    match serde_json::to_string(&mqtt_msg){
-        Ok(payload) => {
-            match client.enqueue(
-                &app_config.mqtt_topic,
-                AtMostOnce,
-                false,
-                payload.as_bytes(),
-            ){
-                Ok(_) => {
-                    info!("send mqtt msg:{}",payload);
-                    return Ok(());
-                },
-                Err(e) => {
-                    error!("mqtt client error:{}",e);
-                    return Err(e.into());
-                },
-            }
-        },
-        Err(e) => {
-            error!("Serialize msg error:{}",e);
-            return Err(e.into());
-        },
+// raw adc value of the moisture sensor, filtered
+async fn read_soil_moisture(
+    adc_1_channel_0: &AdcDriver<'_, adc::ADC1>,
+    adc: &mut AdcChannelDriver<'_, Gpio0, &AdcDriver<'_, adc::ADC1>>,
+) -> Option<u16> {
+    // read adc
+    // should do adc adjust,make moisture into 2 stage, low value enable pumper water
+    // and high value do next check
+    //
+    // filter： do read value MOISTURE_READS times, a second apart
+    // then pumper_logic::watering::filter_moisture drops max&min value，
+    // todo! make pumper threshold tobe a var
+
+    let mut moistures = Vec::new();
+
+    for _ in 0..MOISTURE_READS {
+        match adc_1_channel_0.read(adc) {
+            Ok(val) => moistures.push(val),
+            Err(e) => error!("read adc error:{}",e),
+        }
+        
+        Timer::after_millis(1000).await;
     }
-    
+    let moisture = filter_moisture(&moistures);
+    if moisture.is_none() {
+        error!("read moisture sensor {} times", MOISTURE_READS);
+    }
+    moisture
 }
 
+// calibrated, see calibration.rs
 fn convert_moisture_to_humidity_u16(moisture: u16) -> u32 {
-    let a = (MOISTURE_IN_AIR as u32  - moisture as u32 ) *100  / MOISTURE_IN_WATER as u32;
-    return a;
+    calibration::humidity(moisture)
 }
 
 // deal commands recieved from cloud
-fn received_message(data: &[u8]) {
+fn received_message(data: &[u8], reply: Option<ReplyTo>) {
     match str::from_utf8(data) {
         Ok(res) => match serde_json::from_str::<CloudCommand>(res) {
-            Ok(command) => match command.params {
-                Instruct::Volumn(val) => {
-                    info!("receive cloud command pumper water: {}ml", val);
+            Ok(command) => {
+                let queued = |command| match send_local_command(command) {
+                    true => Ok(()),
+                    false => Err("busy, command dropped".to_string()),
+                };
+                let result = match command.params {
+                    Instruct::Volumn(val) => {
+                        info!("receive cloud command pumper water: {}ml", val);
+                        Ok(())
+                    }
+                    Instruct::Reboot => {
+                        info!("receive cloud command reboot");
+                        queued(LocalCommand::Reboot)
+                    }
+                    Instruct::Ota { url, version, signature } => {
+                        info!("receive cloud command ota, version:{}", version);
+                        queued(LocalCommand::Ota { url, version, signature })
+                    }
+                    Instruct::LogLevel(level) => match remote_log::set_level(&level) {
+                        Ok(_) => {
+                            info!("receive cloud command remote log level {}", level);
+                            Ok(())
+                        }
+                        Err(e) => {
+                            error!("{}", e);
+                            Err(e.to_string())
+                        }
+                    },
+                };
+                // after the command, so it tells whether the command was taken
+                // reboot & ota send queued replies before they go, see mqtt_send_replies
+                if let Some(to) = reply {
+                    let reply = Reply { to, id: command.id, result };
+                    if tasks::REPLIES.try_send(reply).is_err() {
+                        error!("reply channel full, reply dropped");
+                    }
                 }
-            },
+            }
             Err(e) => {
                 error!("Phase Cloud Command Json failed:{}", e);
             }
@@ -478,8 +1048,3 @@ fn received_message(data: &[u8]) {
         }
     }
 }
-
-// volume:water to pump,as ml
-fn convert_volume_to_pumperworking_time_ms(volume: u32) -> u32 {
-    return volume * 60 * 1000 / PUMPER_FLOW;
-}
@@ -0,0 +1,83 @@
+// prometheus metrics, GET /metrics in text exposition format
+//
+//   scrape_configs:
+//     - job_name: pumper
+//       static_configs:
+//         - targets: ["192.168.1.50:80"]
+//
+// the tasks record into here, the http handler renders a snapshot on every scrape, the text
+// format itself is funny_core::prometheus.
+// nothing is reported before the first reading.
+
+use std::sync::Mutex;
+
+use funny_core::prometheus::Exposition;
+use funny_core::wifi::WifiState;
+
+use crate::{battery, broker, ota};
+
+struct Metrics {
+    moisture_raw: Option<u16>,
+    moisture_percent: Option<u32>,
+    temperature: Option<i8>,
+    humidity: Option<u32>,
+    sensor_errors: u32,
+    pump_runs: u32,
+    pump_runtime_ms: u64,
+}
+
+static METRICS: Mutex<Metrics> = Mutex::new(Metrics {
+    moisture_raw: None,
+    moisture_percent: None,
+    temperature: None,
+    humidity: None,
+    sensor_errors: 0,
+    pump_runs: 0,
+    pump_runtime_ms: 0,
+});
+
+pub fn set_moisture(raw: u16, percent: u32) {
+    let mut metrics = METRICS.lock().unwrap();
+    metrics.moisture_raw = Some(raw);
+    metrics.moisture_percent = Some(percent);
+}
+
+pub fn set_environment(temperature: i8, humidity: u32) {
+    let mut metrics = METRICS.lock().unwrap();
+    metrics.temperature = Some(temperature);
+    metrics.humidity = Some(humidity);
+}
+
+// dht11 or moisture sensor read failed
+pub fn sensor_error() {
+    METRICS.lock().unwrap().sensor_errors += 1;
+}
+
+// a finished pump run, however it ended
+pub fn pump_run(runtime_ms: u64) {
+    let mut metrics = METRICS.lock().unwrap();
+    metrics.pump_runs += 1;
+    metrics.pump_runtime_ms += runtime_ms;
+}
+
+pub fn render(wifi: &WifiState) -> String {
+    let metrics = METRICS.lock().unwrap();
+    let mut out = Exposition::new("pumper", ota::FIRMWARE_VERSION);
+    out.system();
+    out.metric("soil_moisture_raw", "gauge", "Soil moisture sensor, raw adc reading.", metrics.moisture_raw);
+    out.metric("soil_moisture_percent", "gauge", "Soil moisture, calibrated.", metrics.moisture_percent);
+    out.metric("temperature_celsius", "gauge", "DHT11 temperature.", metrics.temperature);
+    out.metric("humidity_percent", "gauge", "DHT11 relative humidity.", metrics.humidity);
+    out.metric("sensor_errors_total", "counter", "Failed DHT11 or moisture readings.", Some(metrics.sensor_errors));
+    out.metric("pump_runs_total", "counter", "Pump runs since boot.", Some(metrics.pump_runs));
+    out.metric(
+        "pump_runtime_seconds_total",
+        "counter",
+        "Pump running time since boot.",
+        Some(metrics.pump_runtime_ms as f32 / 1000.0),
+    );
+    out.wifi(wifi);
+    out.metric("mqtt_connected", "gauge", "1 while connected to a broker.", Some(broker::is_connected() as u8));
+    out.battery(battery::last());
+    out.finish()
+}
@@ -0,0 +1,240 @@
+// firmware update over http(s)
+//
+// triggered by a cloud command, `signature` comes from ../ota-sign:
+//   {"method":"ota","params":{"Ota":{"url":"http://192.168.1.10:8000/pumper.bin","version":"0.2.0","signature":"<hex>"}},"id":1}
+//
+// - older or same versions are refused before anything is downloaded
+// - the image is streamed into the inactive ota slot, esp_ota_end checks it (magic, sha256)
+// - the ed25519 signature over version + sha256 of the image is checked against
+//   `ota_public_key`, anyone who can publish to the command topic can not push their own image
+// - only then the boot slot is switched and the board restarts
+// - the new image boots "pending verify" and has `ota_confirm_minutes` to reach wifi + mqtt,
+//   otherwise it marks itself invalid and the bootloader rolls back to the previous slot
+//
+// progress goes to mqtt_topic as {"ota_state":..,"ota_progress":..,"ota_version":..}
+// needs the two slot partitions.csv & rollback enabled in sdkconfig.defaults
+
+use std::time::{Duration, Instant};
+
+use anyhow::{anyhow, bail, Context, Result};
+use ed25519_dalek::{Signature, VerifyingKey};
+use esp_idf_svc::http::client::{Configuration as HttpConfiguration, EspHttpConnection};
+use esp_idf_svc::http::Method;
+use esp_idf_svc::ota::{EspOta, SlotState};
+use esp_idf_svc::sys;
+use log::{error, info, warn};
+use pumper_logic::ota::signed_message;
+use serde::Serialize;
+use sha2::{Digest, Sha256};
+
+use crate::supervisor::{self, Task};
+
+const CHUNK_SIZE: usize = 4096;
+// per connect / read, the watchdog is fed between them, so well below WATCHDOG_TIMEOUT
+const HTTP_TIMEOUT: Duration = Duration::from_secs(20);
+
+pub const FIRMWARE_VERSION: &str = env!("CARGO_PKG_VERSION");
+
+#[derive(Serialize)]
+pub struct OtaStatus<'a> {
+    // "downloading", "rebooting", "failed", "confirmed"
+    pub ota_state: &'a str,
+    // percent, 0 when the size is unknown
+    pub ota_progress: u32,
+    pub ota_version: &'a str,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub ota_error: Option<String>,
+}
+
+impl<'a> OtaStatus<'a> {
+    pub fn new(ota_state: &'a str, ota_progress: u32, ota_version: &'a str) -> Self {
+        Self {
+            ota_state,
+            ota_progress,
+            ota_version,
+            ota_error: None,
+        }
+    }
+}
+
+// "1.2.10" > "1.2.9"
+fn parse_version(version: &str) -> Result<Vec<u32>> {
+    version
+        .split('.')
+        .map(|part| part.parse::<u32>().with_context(|| format!("bad version {}", version)))
+        .collect()
+}
+
+fn check_version(version: &str) -> Result<()> {
+    if parse_version(version)? <= parse_version(FIRMWARE_VERSION)? {
+        bail!("version {} is not newer than the running {}", version, FIRMWARE_VERSION);
+    }
+    Ok(())
+}
+
+struct Verifier {
+    key: VerifyingKey,
+    signature: Signature,
+}
+
+impl Verifier {
+    fn new(public_key: &str, signature: &str) -> Result<Self> {
+        if public_key.is_empty() {
+            bail!("ota_public_key not set, ota is disabled");
+        }
+        let key: [u8; 32] = hex::decode(public_key)?
+            .try_into()
+            .map_err(|_| anyhow!("ota_public_key must be 32 bytes"))?;
+        let signature: [u8; 64] = hex::decode(signature)?
+            .try_into()
+            .map_err(|_| anyhow!("signature must be 64 bytes"))?;
+        Ok(Self {
+            key: VerifyingKey::from_bytes(&key)?,
+            signature: Signature::from_bytes(&signature),
+        })
+    }
+
+    fn verify(&self, version: &str, digest: &[u8]) -> Result<()> {
+        self.key
+            .verify_strict(&signed_message(version, digest), &self.signature)
+            .map_err(|_| anyhow!("bad signature"))
+    }
+}
+
+// download & flash `url`, on success the next boot runs the new image
+// `report` is called on every 10% and at the end, also on failure
+pub fn update(
+    url: &str,
+    version: &str,
+    signature: &str,
+    public_key: &str,
+    mut report: impl FnMut(&OtaStatus),
+) -> Result<()> {
+    info!("ota update {} -> {} from {}", FIRMWARE_VERSION, version, url);
+    let result = check_version(version)
+        .and_then(|_| Verifier::new(public_key, signature))
+        .and_then(|verifier| download(url, version, &verifier, &mut report));
+    match result {
+        Ok(_) => {
+            report(&OtaStatus::new("rebooting", 100, version));
+            Ok(())
+        }
+        Err(e) => {
+            error!("ota failed:{}", e);
+            let mut status = OtaStatus::new("failed", 0, version);
+            status.ota_error = Some(e.to_string());
+            report(&status);
+            Err(e)
+        }
+    }
+}
+
+fn download(url: &str, version: &str, verifier: &Verifier, report: &mut impl FnMut(&OtaStatus)) -> Result<()> {
+    let mut connection = EspHttpConnection::new(&HttpConfiguration {
+        buffer_size: Some(CHUNK_SIZE),
+        timeout: Some(HTTP_TIMEOUT),
+        crt_bundle_attach: url.starts_with("https://").then_some(sys::esp_crt_bundle_attach),
+        ..Default::default()
+    })?;
+    // connect & response headers can each take up to HTTP_TIMEOUT
+    supervisor::blocking_progress(Task::Network);
+    connection.initiate_request(Method::Get, url, &[])?;
+    supervisor::blocking_progress(Task::Network);
+    connection.initiate_response()?;
+    if connection.status() != 200 {
+        bail!("http status {}", connection.status());
+    }
+    let total = connection
+        .header("Content-Length")
+        .and_then(|len| len.parse::<usize>().ok())
+        .unwrap_or(0);
+
+    let mut ota = EspOta::new()?;
+    // erases the whole partition, seconds on a big one
+    supervisor::blocking_progress(Task::Network);
+    let mut update = ota.initiate_update()?;
+    supervisor::blocking_progress(Task::Network);
+    let mut hasher = Sha256::new();
+    let mut buf = vec![0u8; CHUNK_SIZE];
+    let mut written = 0;
+    let mut reported = 0;
+    report(&OtaStatus::new("downloading", 0, version));
+    loop {
+        let len = match connection.read(&mut buf) {
+            Ok(len) => len,
+            Err(e) => {
+                update.abort()?;
+                return Err(e.into());
+            }
+        };
+        if len == 0 {
+            break;
+        }
+        if let Err(e) = update.write(&buf[..len]) {
+            update.abort()?;
+            return Err(e.into());
+        }
+        hasher.update(&buf[..len]);
+        written += len;
+        // blocks the main task for a minute, keep the watchdog quiet while bytes come in
+        supervisor::blocking_progress(Task::Network);
+
+        if total > 0 {
+            let progress = (written * 100 / total) as u32;
+            if progress >= reported + 10 {
+                reported = progress - progress % 10;
+                info!("ota {}% ({}/{} bytes)", progress, written, total);
+                report(&OtaStatus::new("downloading", progress, version));
+            }
+        }
+    }
+    if total > 0 && written != total {
+        update.abort()?;
+        bail!("download incomplete, {}/{} bytes", written, total);
+    }
+    if let Err(e) = verifier.verify(version, &hasher.finalize()) {
+        update.abort()?;
+        return Err(e);
+    }
+    // validates the image & sets the boot slot
+    update.complete()?;
+    info!("ota image written, {} bytes", written);
+    Ok(())
+}
+
+// the running image was just installed by ota and still has to prove itself
+pub struct PendingConfirm {
+    deadline: Instant,
+}
+
+impl PendingConfirm {
+    pub fn check_boot(confirm_minutes: u32) -> Result<Option<Self>> {
+        let ota = EspOta::new()?;
+        if let Some(slot) = ota.get_last_invalid_slot()? {
+            warn!("rolled back, firmware in {} was invalid", slot.label);
+        }
+        let slot = ota.get_running_slot()?;
+        if slot.state != SlotState::Unverified {
+            return Ok(None);
+        }
+        warn!("new firmware in {}, must be healthy within {}min", slot.label, confirm_minutes);
+        Ok(Some(Self {
+            deadline: Instant::now() + Duration::from_secs(confirm_minutes as u64 * 60),
+        }))
+    }
+
+    // true once confirmed, rolls back (never returns) when the deadline passed
+    pub fn check(&self, healthy: bool) -> Result<bool> {
+        let mut ota = EspOta::new()?;
+        if healthy {
+            ota.mark_running_slot_valid()?;
+            info!("new firmware confirmed");
+            return Ok(true);
+        }
+        if Instant::now() >= self.deadline {
+            error!("new firmware not healthy in time, roll back");
+            return Err(ota.mark_running_slot_invalid_and_reboot().into());
+        }
+        Ok(false)
+    }
+}
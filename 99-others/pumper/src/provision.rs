@@ -0,0 +1,264 @@
+// softap captive portal provisioning
+//
+// the board opens an open access point "funny-pumper-xxxx", every dns query is answered with
+// our own ip so phones pop up the portal page. the form writes wifi & mqtt settings to nvs,
+// then the board reboots into station mode.
+//
+// started when
+// - nothing is provisioned yet (first boot, empty cfg.toml)
+// - the provisioning button is held during boot
+// - station mode kept failing, see `wifi_ap_fallback_failures`
+//
+// with feature `ble-provision` the same settings are written over ble instead, see ble_provision.rs
+
+use std::net::{Ipv4Addr, UdpSocket};
+use std::thread;
+use std::time::{Duration, Instant};
+
+use anyhow::Result;
+use esp_idf_svc::eventloop::EspSystemEventLoop;
+use esp_idf_svc::hal::delay::FreeRtos;
+use esp_idf_svc::http::server::{Configuration as HttpConfiguration, EspHttpServer};
+use esp_idf_svc::http::Method;
+use esp_idf_svc::io::{Read, Write};
+use esp_idf_svc::nvs::EspDefaultNvsPartition;
+use esp_idf_svc::wifi::{AccessPointConfiguration, AuthMethod, BlockingWifi, Configuration, EspWifi};
+use funny_core::device_suffix;
+use log::{error, info, warn};
+
+use crate::settings::Settings;
+#[cfg(feature = "ble-provision")]
+use crate::CONFIG;
+
+// esp-idf default softap address
+const AP_IP: Ipv4Addr = Ipv4Addr::new(192, 168, 71, 1);
+// nobody came, go back to station mode and try again
+const PORTAL_TIMEOUT: Duration = Duration::from_secs(10 * 60);
+const MAX_FORM_LEN: usize = 1024;
+
+// never returns, restarts once settings are saved or nobody showed up
+pub fn run(
+    wifi: EspWifi<'static>,
+    sysloop: &EspSystemEventLoop,
+    nvs: &EspDefaultNvsPartition,
+    settings: &Settings,
+) -> Result<()> {
+    #[cfg(feature = "ble-provision")]
+    {
+        drop(wifi);
+        let _ = sysloop;
+        crate::ble_provision::run(nvs, settings, &device_suffix()?, CONFIG.ble_passkey)
+    }
+    #[cfg(not(feature = "ble-provision"))]
+    run_portal(wifi, sysloop, nvs, settings)
+}
+
+fn run_portal(
+    wifi: EspWifi<'static>,
+    sysloop: &EspSystemEventLoop,
+    nvs: &EspDefaultNvsPartition,
+    settings: &Settings,
+) -> Result<()> {
+    let mut wifi = BlockingWifi::wrap(wifi, sysloop.clone())?;
+    let ssid = format!("funny-pumper-{}", device_suffix()?);
+    wifi.set_configuration(&Configuration::AccessPoint(AccessPointConfiguration {
+        ssid: ssid.as_str().try_into().unwrap(),
+        auth_method: AuthMethod::None,
+        channel: 1,
+        ..Default::default()
+    }))?;
+    wifi.start()?;
+    wifi.wait_netif_up()?;
+    info!("provisioning ap started, ssid:{}, portal: http://{}", ssid, AP_IP);
+
+    thread::Builder::new()
+        .name("dns".into())
+        .stack_size(4 * 1024)
+        .spawn(dns_server)?;
+
+    let mut server = EspHttpServer::new(&HttpConfiguration {
+        uri_match_wildcard: true,
+        ..Default::default()
+    })?;
+
+    let form = form_page(settings);
+    server.fn_handler::<anyhow::Error, _>("/", Method::Get, move |req| {
+        req.into_response(200, None, &[("Content-Type", "text/html; charset=utf-8")])?
+            .write_all(form.as_bytes())?;
+        Ok(())
+    })?;
+
+    let nvs = nvs.clone();
+    let current = settings.clone();
+    server.fn_handler::<anyhow::Error, _>("/save", Method::Post, move |mut req| {
+        let mut body = Vec::new();
+        let mut buf = [0u8; 256];
+        loop {
+            let len = req.read(&mut buf)?;
+            if len == 0 || body.len() + len > MAX_FORM_LEN {
+                break;
+            }
+            body.extend_from_slice(&buf[..len]);
+        }
+
+        let settings = parse_form(&String::from_utf8_lossy(&body), &current);
+        if let Err(e) = settings.check() {
+            req.into_status_response(400)?.write_all(e.as_bytes())?;
+            return Ok(());
+        }
+        settings.save(&nvs)?;
+        req.into_response(200, None, &[("Content-Type", "text/html; charset=utf-8")])?
+            .write_all(SAVED_PAGE.as_bytes())?;
+
+        // let the response go out first
+        thread::spawn(|| {
+            FreeRtos::delay_ms(2000);
+            esp_idf_svc::hal::reset::restart();
+        });
+        Ok(())
+    })?;
+
+    // captive portal detection (android generate_204, apple hotspot-detect, ...) lands here
+    server.fn_handler::<anyhow::Error, _>("/*", Method::Get, |req| {
+        req.into_response(302, Some("Found"), &[("Location", "http://192.168.71.1/")])?;
+        Ok(())
+    })?;
+
+    let started = Instant::now();
+    while started.elapsed() < PORTAL_TIMEOUT {
+        FreeRtos::delay_ms(1000);
+    }
+    warn!("provisioning timeout, restart into station mode");
+    esp_idf_svc::hal::reset::restart();
+}
+
+// minimal dns server, every A query is answered with AP_IP
+fn dns_server() {
+    let socket = match UdpSocket::bind((Ipv4Addr::UNSPECIFIED, 53)) {
+        Ok(socket) => socket,
+        Err(e) => {
+            error!("dns server bind error:{}", e);
+            return;
+        }
+    };
+    let mut buf = [0u8; 512];
+    loop {
+        let (len, peer) = match socket.recv_from(&mut buf) {
+            Ok(res) => res,
+            Err(e) => {
+                error!("dns server recv error:{}", e);
+                continue;
+            }
+        };
+        // header(12) + at least one question
+        if len < 17 {
+            continue;
+        }
+        // end of the first question: qname, qtype(2), qclass(2)
+        let mut end = 12;
+        while end < len && buf[end] != 0 {
+            end += buf[end] as usize + 1;
+        }
+        end += 5;
+        if end > len {
+            continue;
+        }
+
+        let mut answer = Vec::with_capacity(end + 16);
+        answer.extend_from_slice(&buf[..2]); // id
+        answer.extend_from_slice(&[0x81, 0x80]); // response, recursion available, no error
+        answer.extend_from_slice(&[0x00, 0x01, 0x00, 0x01, 0x00, 0x00, 0x00, 0x00]); // 1 question, 1 answer
+        answer.extend_from_slice(&buf[12..end]); // the question
+        answer.extend_from_slice(&[0xc0, 0x0c]); // name: pointer to the question
+        answer.extend_from_slice(&[0x00, 0x01, 0x00, 0x01]); // type A, class IN
+        answer.extend_from_slice(&60u32.to_be_bytes()); // ttl
+        answer.extend_from_slice(&[0x00, 0x04]);
+        answer.extend_from_slice(&AP_IP.octets());
+
+        if let Err(e) = socket.send_to(&answer, peer) {
+            error!("dns server send error:{}", e);
+        }
+    }
+}
+
+// empty fields keep their current value, so the psk does not have to be typed again
+fn parse_form(body: &str, current: &Settings) -> Settings {
+    let mut settings = current.clone();
+    for pair in body.split('&') {
+        let (key, value) = pair.split_once('=').unwrap_or((pair, ""));
+        let value = url_decode(value);
+        if value.is_empty() {
+            continue;
+        }
+        match key {
+            "wifi_ssid" => settings.wifi_ssid = value,
+            "wifi_psk" => settings.wifi_psk = value,
+            "mqtt_host" => settings.mqtt_host = value,
+            "mqtt_user" => settings.mqtt_user = value,
+            "mqtt_pass" => settings.mqtt_pass = value,
+            _ => {}
+        }
+    }
+    settings
+}
+
+// application/x-www-form-urlencoded
+fn url_decode(value: &str) -> String {
+    let bytes = value.as_bytes();
+    let mut out = Vec::with_capacity(bytes.len());
+    let mut i = 0;
+    while i < bytes.len() {
+        match bytes[i] {
+            b'+' => out.push(b' '),
+            b'%' if i + 2 < bytes.len() => match (hex(bytes[i + 1]), hex(bytes[i + 2])) {
+                (Some(high), Some(low)) => {
+                    out.push(high << 4 | low);
+                    i += 2;
+                }
+                _ => out.push(b'%'),
+            },
+            byte => out.push(byte),
+        }
+        i += 1;
+    }
+    String::from_utf8_lossy(&out).into_owned()
+}
+
+fn hex(byte: u8) -> Option<u8> {
+    (byte as char).to_digit(16).map(|digit| digit as u8)
+}
+
+fn html_escape(value: &str) -> String {
+    value
+        .replace('&', "&amp;")
+        .replace('<', "&lt;")
+        .replace('>', "&gt;")
+        .replace('"', "&quot;")
+}
+
+fn form_page(settings: &Settings) -> String {
+    format!(
+        r#"<!DOCTYPE html>
+<html><head><meta charset="utf-8"><meta name="viewport" content="width=device-width, initial-scale=1">
+<title>植物浇水机 配网</title>
+<style>body{{font-family:sans-serif;max-width:24em;margin:2em auto;padding:0 1em}}
+label{{display:block;margin-top:1em}}input{{width:100%;padding:.4em;box-sizing:border-box}}
+button{{margin-top:1.5em;width:100%;padding:.6em}}</style></head>
+<body><h2>植物浇水机 配网</h2>
+<form method="post" action="/save">
+<label>WiFi 名称<input name="wifi_ssid" value="{ssid}" required></label>
+<label>WiFi 密码<input name="wifi_psk" type="password" placeholder="不改就留空"></label>
+<label>MQTT 地址<input name="mqtt_host" value="{host}" placeholder="mqtt://192.168.1.10:1883"></label>
+<label>MQTT 用户名<input name="mqtt_user" value="{user}"></label>
+<label>MQTT 密码<input name="mqtt_pass" type="password" placeholder="不改就留空"></label>
+<button type="submit">保存并重启</button>
+</form></body></html>"#,
+        ssid = html_escape(&settings.wifi_ssid),
+        host = html_escape(&settings.mqtt_host),
+        user = html_escape(&settings.mqtt_user),
+    )
+}
+
+const SAVED_PAGE: &str = r#"<!DOCTYPE html>
+<html><head><meta charset="utf-8"><meta name="viewport" content="width=device-width, initial-scale=1"></head>
+<body style="font-family:sans-serif;text-align:center;margin-top:3em"><h2>已保存</h2><p>设备正在重启，连上家里的WiFi后就开始干活了。</p></body></html>"#;
@@ -0,0 +1,297 @@
+// remote logging, records at or above `remote_log_level` are copied to mqtt or udp syslog
+//
+//   remote_log = "mqtt"     {"level":"WARN","target":"pumper::tasks","uptime":123,"msg":".."}
+//                           on `remote_log_topic`
+//   remote_log = "syslog"   rfc 5424 over udp to `remote_log_syslog`, facility local0,
+//                           no timestamp, the board has no clock
+//
+// everything still goes to the serial console through EspLogger. `log!` only queues the
+// line, try_lock & a bounded queue, so a slow network never holds up the pump. the network
+// task sends the queue once a second, lines over the rate limit or a full queue are dropped
+// and counted. awake mode only, battery mode never sends.
+//
+// the level changes at runtime with the `LogLevel` cloud command or PUT /api/config
+// {"log_level":"debug"}, back to cfg.toml after a reboot. debug & trace need the console
+// level in sdkconfig too, esp-idf filters them before they get here.
+
+use std::cell::Cell;
+use std::collections::VecDeque;
+use std::net::{SocketAddr, ToSocketAddrs, UdpSocket};
+use std::str::FromStr;
+use std::sync::atomic::{AtomicUsize, Ordering};
+use std::sync::{Mutex, OnceLock};
+use std::thread;
+use std::time::Duration;
+
+use embassy_time::Instant;
+use esp_idf_svc::log::EspLogger;
+use esp_idf_svc::mqtt::client::{EspMqttClient, QoS::AtMostOnce};
+use log::{Level, LevelFilter, Log, Metadata, Record};
+use serde_json::json;
+
+use crate::broker;
+use funny_core::device_suffix;
+use crate::CONFIG;
+
+const MAX_LINES: usize = 32;
+// lines per second, a crash loop logging in circles must not flood the broker
+const MAX_LINES_PER_SEC: u32 = 10;
+const MAX_LINE_LEN: usize = 256;
+// rfc 5424 facility local0
+const SYSLOG_FACILITY: u8 = 16;
+// until the syslog hostname resolves, dns is not there before the wifi
+const RESOLVE_RETRY: Duration = Duration::from_secs(5);
+
+struct Line {
+    level: Level,
+    target: String,
+    uptime: u32,
+    msg: String,
+}
+
+struct Queue {
+    lines: VecDeque<Line>,
+    window: u32,
+    in_window: u32,
+    dropped: u32,
+}
+
+static QUEUE: Mutex<Queue> = Mutex::new(Queue {
+    lines: VecDeque::new(),
+    window: 0,
+    in_window: 0,
+    dropped: 0,
+});
+
+// LevelFilter as usize, Off until `init`
+static LEVEL: AtomicUsize = AtomicUsize::new(LevelFilter::Off as usize);
+
+thread_local! {
+    // set while sending, whatever the socket or mqtt client logs is not queued again
+    static SENDING: Cell<bool> = const { Cell::new(false) };
+}
+
+struct RemoteLogger {
+    console: EspLogger,
+}
+
+static LOGGER: RemoteLogger = RemoteLogger { console: EspLogger::new() };
+
+impl Log for RemoteLogger {
+    fn enabled(&self, metadata: &Metadata) -> bool {
+        self.console.enabled(metadata)
+    }
+
+    fn log(&self, record: &Record) {
+        self.console.log(record);
+        if record.level() > level() || SENDING.with(|sending| sending.get()) {
+            return;
+        }
+        // never wait, the queue is only locked for a push or a swap
+        let Ok(mut queue) = QUEUE.try_lock() else {
+            return;
+        };
+        let now = Instant::now().as_secs() as u32;
+        if queue.window != now {
+            queue.window = now;
+            queue.in_window = 0;
+        }
+        if queue.in_window >= MAX_LINES_PER_SEC {
+            queue.dropped += 1;
+            return;
+        }
+        queue.in_window += 1;
+        if queue.lines.len() == MAX_LINES {
+            queue.lines.pop_front();
+            queue.dropped += 1;
+        }
+        let mut msg = record.args().to_string();
+        if msg.len() > MAX_LINE_LEN {
+            let mut end = MAX_LINE_LEN;
+            while !msg.is_char_boundary(end) {
+                end -= 1;
+            }
+            msg.truncate(end);
+        }
+        queue.lines.push_back(Line {
+            level: record.level(),
+            target: record.target().to_string(),
+            uptime: now,
+            msg,
+        });
+    }
+
+    fn flush(&self) {
+        self.console.flush();
+    }
+}
+
+// instead of EspLogger::initialize_default, first thing in main
+pub fn init() {
+    log::set_logger(&LOGGER).map(|()| LOGGER.console.initialize()).unwrap();
+    if !CONFIG.remote_log.is_empty() {
+        if let Err(e) = set_level(CONFIG.remote_log_level) {
+            log::error!("remote_log_level:{}", e);
+        }
+    }
+}
+
+pub fn level() -> LevelFilter {
+    match LEVEL.load(Ordering::Relaxed) {
+        1 => LevelFilter::Error,
+        2 => LevelFilter::Warn,
+        3 => LevelFilter::Info,
+        4 => LevelFilter::Debug,
+        5 => LevelFilter::Trace,
+        _ => LevelFilter::Off,
+    }
+}
+
+// "off", "error", "warn", "info", "debug" or "trace"
+pub fn set_level(level: &str) -> Result<(), String> {
+    store_level(parse_level(level)?);
+    Ok(())
+}
+
+// checked only, for callers that validate everything before applying anything
+pub fn parse_level(level: &str) -> Result<LevelFilter, String> {
+    LevelFilter::from_str(level).map_err(|_| format!("unknown log level {}", level))
+}
+
+pub fn store_level(level: LevelFilter) {
+    LEVEL.store(level as usize, Ordering::Relaxed);
+}
+
+enum Sink {
+    Mqtt(&'static str),
+    Syslog(UdpSocket),
+}
+
+// `remote_log_syslog` resolved, lines queue up until then
+static SYSLOG_ADDR: OnceLock<SocketAddr> = OnceLock::new();
+
+// a literal ip right away, a hostname on a thread of its own, getaddrinfo blocks for seconds
+// without dns and the network task must not wait on it
+fn resolve_syslog(server: &'static str) {
+    if let Ok(addr) = SocketAddr::from_str(server) {
+        let _ = SYSLOG_ADDR.set(addr);
+        return;
+    }
+    let spawned = thread::Builder::new()
+        .name("syslog-dns".into())
+        .stack_size(4 * 1024)
+        .spawn(move || loop {
+            if let Some(addr) = server.to_socket_addrs().ok().and_then(|mut addrs| addrs.next()) {
+                log::info!("syslog server {} at {}", server, addr);
+                let _ = SYSLOG_ADDR.set(addr);
+                return;
+            }
+            thread::sleep(RESOLVE_RETRY);
+        });
+    if let Err(e) = spawned {
+        log::error!("syslog resolver thread error:{}", e);
+    }
+}
+
+pub struct RemoteLog {
+    sink: Sink,
+    hostname: String,
+}
+
+impl RemoteLog {
+    // None while disabled in cfg.toml
+    pub fn new() -> Option<Self> {
+        let sink = match CONFIG.remote_log {
+            "" => return None,
+            "mqtt" => Sink::Mqtt(CONFIG.remote_log_topic),
+            "syslog" => match UdpSocket::bind("0.0.0.0:0") {
+                Ok(socket) => {
+                    resolve_syslog(CONFIG.remote_log_syslog);
+                    Sink::Syslog(socket)
+                }
+                Err(e) => {
+                    log::error!("remote log socket error:{}", e);
+                    return None;
+                }
+            },
+            other => {
+                log::error!("unknown remote_log {}, use mqtt or syslog", other);
+                return None;
+            }
+        };
+        let hostname = format!("funny-pumper-{}", device_suffix().unwrap_or_default());
+        Some(Self { sink, hostname })
+    }
+
+    // queued lines out, kept for later while offline
+    pub fn send(&mut self, client: &mut EspMqttClient<'static>, wifi_up: bool) {
+        let online = match self.sink {
+            Sink::Mqtt(_) => broker::is_connected(),
+            Sink::Syslog(_) => wifi_up && SYSLOG_ADDR.get().is_some(),
+        };
+        if !online {
+            return;
+        }
+        let (lines, dropped) = {
+            let Ok(mut queue) = QUEUE.try_lock() else {
+                return;
+            };
+            (std::mem::take(&mut queue.lines), std::mem::take(&mut queue.dropped))
+        };
+        if lines.is_empty() && dropped == 0 {
+            return;
+        }
+
+        SENDING.with(|sending| sending.set(true));
+        if dropped > 0 {
+            self.send_line(client, &Line {
+                level: Level::Warn,
+                target: module_path!().to_string(),
+                uptime: Instant::now().as_secs() as u32,
+                msg: format!("{} log lines dropped", dropped),
+            });
+        }
+        for line in &lines {
+            self.send_line(client, line);
+        }
+        SENDING.with(|sending| sending.set(false));
+    }
+
+    fn send_line(&mut self, client: &mut EspMqttClient<'static>, line: &Line) {
+        match &self.sink {
+            Sink::Mqtt(topic) => {
+                let payload = json!({
+                    "level": line.level.as_str(),
+                    "target": line.target,
+                    "uptime": line.uptime,
+                    "msg": line.msg,
+                });
+                // lost lines are fine, never block on them
+                let _ = client.enqueue(topic, AtMostOnce, false, payload.to_string().as_bytes());
+            }
+            Sink::Syslog(socket) => {
+                let Some(addr) = SYSLOG_ADDR.get() else {
+                    return;
+                };
+                // <PRI>VERSION TIMESTAMP HOSTNAME APP-NAME PROCID MSGID SD MSG
+                let payload = format!(
+                    "<{}>1 - {} pumper - - - {}: {}",
+                    SYSLOG_FACILITY * 8 + severity(line.level),
+                    self.hostname,
+                    line.target,
+                    line.msg
+                );
+                let _ = socket.send_to(payload.as_bytes(), *addr);
+            }
+        }
+    }
+}
+
+fn severity(level: Level) -> u8 {
+    match level {
+        Level::Error => 3,
+        Level::Warn => 4,
+        Level::Info => 6,
+        Level::Debug | Level::Trace => 7,
+    }
+}
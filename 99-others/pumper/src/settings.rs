@@ -0,0 +1,117 @@
+// runtime settings
+//
+// wifi & mqtt credentials live in nvs, written by provisioning.
+// anything missing in nvs falls back to the values compiled in from cfg.toml
+
+use anyhow::Result;
+use esp_idf_svc::nvs::{EspDefaultNvsPartition, EspNvs, NvsDefault};
+use funny_core::wifi;
+use log::info;
+
+use crate::CONFIG;
+
+const NAMESPACE: &str = "funny_games";
+// longest value we store, wpa2 psk is 64
+const MAX_VALUE_LEN: usize = 128;
+
+// start provisioning on next boot
+const KEY_PROVISION: &str = "provision";
+// the current wifi credentials got an ip at least once, cleared when they change
+const KEY_WIFI_VERIFIED: &str = "wifi_verified";
+
+#[derive(Clone, Debug)]
+pub struct Settings {
+    pub wifi_ssid: String,
+    pub wifi_psk: String,
+    pub mqtt_host: String,
+    pub mqtt_user: String,
+    pub mqtt_pass: String,
+}
+
+impl Settings {
+    // nvs keys, max 15 chars
+    const KEYS: [&'static str; 5] = ["wifi_ssid", "wifi_psk", "mqtt_host", "mqtt_user", "mqtt_pass"];
+
+    pub fn load(nvs: &EspDefaultNvsPartition) -> Result<Self> {
+        let app_config = CONFIG;
+        let store = open(nvs)?;
+        let mut buf = [0u8; MAX_VALUE_LEN];
+        let mut get = |key: &str, default: &str| -> Result<String> {
+            Ok(store.get_str(key, &mut buf)?.unwrap_or(default).to_string())
+        };
+
+        let settings = Self {
+            wifi_ssid: get("wifi_ssid", app_config.wifi_ssid)?,
+            wifi_psk: get("wifi_psk", app_config.wifi_psk)?,
+            mqtt_host: get("mqtt_host", app_config.mqtt_host)?,
+            mqtt_user: get("mqtt_user", app_config.mqtt_user)?,
+            mqtt_pass: get("mqtt_pass", app_config.mqtt_pass)?,
+        };
+        info!("settings loaded, wifi ssid:{}, mqtt host:{}", settings.wifi_ssid, settings.mqtt_host);
+        Ok(settings)
+    }
+
+    pub fn save(&self, nvs: &EspDefaultNvsPartition) -> Result<()> {
+        let mut store = open(nvs)?;
+        let values = [&self.wifi_ssid, &self.wifi_psk, &self.mqtt_host, &self.mqtt_user, &self.mqtt_pass];
+        for (key, value) in Self::KEYS.iter().zip(values) {
+            store.set_str(key, value)?;
+        }
+        store.remove(KEY_WIFI_VERIFIED)?;
+        info!("settings saved, wifi ssid:{}, mqtt host:{}", self.wifi_ssid, self.mqtt_host);
+        Ok(())
+    }
+
+    pub fn is_provisioned(&self) -> bool {
+        !self.wifi_ssid.is_empty()
+    }
+
+    // before saving, values the wifi driver can not take would fail every boot
+    pub fn check(&self) -> Result<(), &'static str> {
+        if !self.is_provisioned() {
+            return Err("wifi ssid is required");
+        }
+        wifi::check_credentials(&self.wifi_ssid, &self.wifi_psk)
+    }
+}
+
+pub fn request_provisioning(nvs: &EspDefaultNvsPartition) -> Result<()> {
+    open(nvs)?.set_u8(KEY_PROVISION, 1)?;
+    Ok(())
+}
+
+// forget the provisioned credentials, back to cfg.toml, provisioning starts on next boot
+pub fn factory_reset(nvs: &EspDefaultNvsPartition) -> Result<()> {
+    let mut store = open(nvs)?;
+    for key in Settings::KEYS {
+        store.remove(key)?;
+    }
+    store.remove(KEY_WIFI_VERIFIED)?;
+    store.set_u8(KEY_PROVISION, 1)?;
+    info!("settings erased");
+    Ok(())
+}
+
+// read & clear the provisioning request
+pub fn take_provisioning_request(nvs: &EspDefaultNvsPartition) -> Result<bool> {
+    let mut store = open(nvs)?;
+    let requested = store.get_u8(KEY_PROVISION)?.unwrap_or(0) == 1;
+    if requested {
+        store.remove(KEY_PROVISION)?;
+    }
+    Ok(requested)
+}
+
+// a router that is down for a while must not send a working board into provisioning
+pub fn wifi_verified(nvs: &EspDefaultNvsPartition) -> Result<bool> {
+    Ok(open(nvs)?.get_u8(KEY_WIFI_VERIFIED)?.unwrap_or(0) == 1)
+}
+
+pub fn set_wifi_verified(nvs: &EspDefaultNvsPartition) -> Result<()> {
+    open(nvs)?.set_u8(KEY_WIFI_VERIFIED, 1)?;
+    Ok(())
+}
+
+fn open(nvs: &EspDefaultNvsPartition) -> Result<EspNvs<NvsDefault>> {
+    Ok(EspNvs::new(nvs.clone(), NAMESPACE, true)?)
+}
@@ -0,0 +1,65 @@
+// deep sleep battery mode, `deep_sleep_secs` > 0
+//
+// every wake-up: sample, maybe water, back to sleep. wifi & mqtt only come up every
+// `deep_sleep_batch` wake-ups (or after watering) to publish the collected samples at once.
+// wake-ups, samples & the last good ap are funny_core::sleep::RtcState, the pumper adds the
+// watered total, the moisture filter & a refused pump start.
+
+use funny_core::sleep::RtcSlot;
+
+pub use funny_core::sleep::{deep_sleep, hold_during_sleep, release_hold, woke_from_timer};
+pub use pumper_logic::telemetry::Sample;
+
+// samples kept while offline, the oldest is dropped when full
+pub const MAX_SAMPLES: usize = 32;
+// wrong magic = cold boot or the layout changed with a firmware update
+const MAGIC: u32 = 0x5075_6d72;
+
+#[derive(Clone, Copy, Default)]
+pub struct Counters {
+    // watered ml since power on
+    pub amount_total: u32,
+    // exponential moving average of soil humidity, x100
+    pub filtered_humidity: u32,
+    // dry but the battery was too low to water, reported with the next publish
+    pub pump_refused: bool,
+}
+
+impl Counters {
+    // smooths out single bad adc readings between wake-ups, returns the filtered value
+    pub fn filter_humidity(&mut self, wakeups: u32, humidity: u32) -> u32 {
+        if wakeups <= 1 || self.filtered_humidity == 0 {
+            self.filtered_humidity = humidity * 100;
+        } else {
+            // 1/4 new, 3/4 history
+            self.filtered_humidity = (self.filtered_humidity * 3 + humidity * 100) / 4;
+        }
+        self.filtered_humidity / 100
+    }
+}
+
+pub type RtcState = funny_core::sleep::RtcState<Counters, Sample, MAX_SAMPLES>;
+
+// rtc memory of the previous wake-up, empty after a cold boot
+pub fn load() -> RtcState {
+    RTC_STATE.load(MAGIC)
+}
+
+pub fn store(state: &RtcState) {
+    RTC_STATE.store(state);
+}
+
+#[link_section = ".rtc.data"]
+static RTC_STATE: RtcSlot<RtcState> = RtcSlot::new(RtcState::new(
+    Counters {
+        amount_total: 0,
+        filtered_humidity: 0,
+        pump_refused: false,
+    },
+    Sample {
+        solid_humidity: 0,
+        environment_temperature: 0,
+        environment_humidity: 0,
+        wakeup: 0,
+    },
+));
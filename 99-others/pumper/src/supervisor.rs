@@ -0,0 +1,116 @@
+// supervision of the awake mode tasks, the loop itself is tasks::supervisor_task
+//
+// every embassy task runs on the main task, and the main task is on the esp-idf task
+// watchdog. the supervisor feeds it only while the sensor & network tasks check in. a task
+// stuck on a lost wakeup starves it, a blocking call that never returns holds up the
+// supervisor as well, either way the watchdog resets the board after WATCHDOG_TIMEOUT and
+// the crash report says task_watchdog.
+//
+// while offline (wifi or mqtt down) it escalates, each level once per outage:
+//   1 reconnect        wifi disconnect & connect again, new mqtt client
+//   2 restart network  wifi driver stop & start, new mqtt client
+//   3 reboot
+// the level that fired is logged, a reboot is kept for the crash report.
+
+use std::sync::atomic::{AtomicU32, Ordering};
+
+use embassy_time::Instant;
+use esp_idf_svc::sys;
+use log::info;
+
+use crate::CONFIG;
+
+// above ota's HTTP_TIMEOUT, a slow connect during the download must not reset the board
+pub const WATCHDOG_TIMEOUT: core::time::Duration = core::time::Duration::from_secs(30);
+
+#[derive(Clone, Copy, Debug)]
+pub enum Task {
+    Sensor,
+    Network,
+}
+
+impl Task {
+    const ALL: [Task; 2] = [Task::Sensor, Task::Network];
+
+    // a sampling pass takes ~25s, the network task ticks every second
+    fn stall_secs(self) -> u32 {
+        match self {
+            Task::Sensor => 120,
+            Task::Network => 60,
+        }
+    }
+}
+
+// uptime of the last check-in, per Task
+static BEATS: [AtomicU32; 2] = [AtomicU32::new(0), AtomicU32::new(0)];
+
+fn uptime() -> u32 {
+    Instant::now().as_secs() as u32
+}
+
+// the task made progress
+pub fn beat(task: Task) {
+    BEATS[task as usize].store(uptime(), Ordering::Relaxed);
+}
+
+// long blocking work on the main task (the ota download), the supervisor can not run
+// meanwhile. call on every bit of progress.
+pub fn blocking_progress(task: Task) {
+    beat(task);
+    unsafe {
+        sys::esp_task_wdt_reset();
+    }
+}
+
+// first task that has not checked in for too long, with the seconds since
+pub fn stalled() -> Option<(Task, u32)> {
+    let now = uptime();
+    Task::ALL.into_iter().find_map(|task| {
+        let since = now.saturating_sub(BEATS[task as usize].load(Ordering::Relaxed));
+        (since > task.stall_secs()).then_some((task, since))
+    })
+}
+
+#[derive(Clone, Copy, Debug, PartialEq, PartialOrd)]
+pub enum Level {
+    Reconnect = 1,
+    RestartNetwork = 2,
+    Reboot = 3,
+}
+
+pub struct Escalation {
+    offline_since: Option<Instant>,
+    fired: Option<Level>,
+}
+
+impl Escalation {
+    pub fn new() -> Self {
+        Self {
+            offline_since: None,
+            fired: None,
+        }
+    }
+
+    // the level due now with the seconds offline, at most one per call
+    pub fn check(&mut self, online: bool) -> Option<(Level, u32)> {
+        if online {
+            if let Some(level) = self.fired.take() {
+                info!("supervisor: back online after level {}", level as u8);
+            }
+            self.offline_since = None;
+            return None;
+        }
+        let offline = self.offline_since.get_or_insert_with(Instant::now).elapsed().as_secs() as u32;
+        let due = [
+            (Level::Reconnect, CONFIG.supervisor_reconnect_secs),
+            (Level::RestartNetwork, CONFIG.supervisor_restart_secs),
+            (Level::Reboot, CONFIG.supervisor_reboot_secs),
+        ]
+        .into_iter()
+        .filter(|(level, secs)| *secs > 0 && offline >= *secs && self.fired.map_or(true, |fired| *level > fired))
+        .map(|(level, _)| level)
+        .last()?;
+        self.fired = Some(due);
+        Some((due, offline))
+    }
+}
@@ -0,0 +1,639 @@
+// awake mode tasks, run concurrently on one executor (see main)
+//
+//   mqtt callback --COMMANDS--> command_task --PUMP--> pump_task
+//                                    |                    |
+//                                    +------NETWORK-------+----> network_task (mqtt client)
+//                                                         |
+//   sensor_task ------------------------------------------+
+//   button_task ------------------------------------------+
+//
+// - sensor_task: battery, dht11, soil moisture every LOOP_INTERVAL, asks for water when dry,
+//   moisture calibration
+// - network_task: owns the mqtt client, publishes telemetry, broker failover, ota, reboot
+// - command_task: sorts commands from the cloud / home assistant to the other tasks
+// - pump_task: owns the relay, a stop request or an empty tank cuts a running pump right away
+// - button_task: gpio4 presses, water / pause automatic watering / factory reset
+// - led::led_task: shows what the others report through led::set
+// - supervisor_task: task watchdog & offline escalation, see supervisor.rs
+//
+// nothing in here may block for long, a slow task holds up all the others.
+// ota is the exception, the pump is halted before and the board restarts after it.
+
+use std::sync::atomic::{AtomicBool, Ordering};
+use std::sync::Arc;
+
+use anyhow::Result;
+use embassy_futures::select::{select, select3, Either, Either3};
+use embassy_sync::blocking_mutex::raw::CriticalSectionRawMutex;
+use embassy_sync::channel::Channel;
+use embassy_sync::signal::Signal;
+use embassy_time::{Duration, Instant, Ticker, Timer};
+use dht_sensor::{dht11, DhtReading};
+use esp_idf_svc::hal::adc::oneshot::{AdcChannelDriver, AdcDriver};
+use esp_idf_svc::hal::adc::ADC1;
+use esp_idf_svc::hal::delay;
+use esp_idf_svc::hal::gpio::{Gpio0, Gpio1, Gpio3, Gpio4, Gpio5, Gpio9, Input, InputOutput, Level, PinDriver};
+use esp_idf_svc::hal::task::watchdog::TWDTDriver;
+use esp_idf_svc::mqtt::client::QoS::AtMostOnce;
+use esp_idf_svc::nvs::EspDefaultNvsPartition;
+use funny_core::crash::{self, CrashReport};
+use funny_core::mqtt::AVAILABILITY_ONLINE;
+use funny_core::wifi::{WifiManager, WifiState};
+use log::{error, info, warn};
+use pumper_logic::watering::{convert_volume_to_pumperworking_time_ms, should_water, watered_ml};
+
+use crate::broker::{self, BrokerEndpoint, BrokerList};
+use crate::calibration::{self, Point};
+use crate::ha::{self, HaTopics};
+use crate::history;
+use crate::led::{self, Condition};
+use crate::metrics;
+use crate::remote_log::RemoteLog;
+use crate::ota::{self, OtaStatus, PendingConfirm};
+use crate::settings::{self, Settings};
+use crate::supervisor::{self, Escalation, Task};
+use crate::{
+    api, battery, device_restart, mqtt_client_connect,
+    mqtt_publish_availability, mqtt_send_crash_report, mqtt_send_msg, mqtt_send_ota_status, mqtt_send_replies,
+    mqtt_send_reply, mqtt_subscribe_topics, read_soil_humidity, read_soil_moisture, set_battery_fields, LocalCommand,
+    MqttMsg, Reply, CONFIG, LOOP_INTERVAL,
+};
+
+// wifi, broker & ota confirm checks
+const NETWORK_CHECK_INTERVAL: Duration = Duration::from_secs(1);
+// watchdog feed & offline check, well below supervisor::WATCHDOG_TIMEOUT
+const SUPERVISOR_INTERVAL: Duration = Duration::from_secs(5);
+// contact bounce settles well within this
+const BUTTON_DEBOUNCE: Duration = Duration::from_millis(30);
+// held at least this long, on release
+const BUTTON_LONG_PRESS: Duration = Duration::from_secs(2);
+const BUTTON_VERY_LONG_PRESS: Duration = Duration::from_secs(10);
+
+// from the mqtt callback, filled outside the executor, see send_local_command
+pub static COMMANDS: Channel<CriticalSectionRawMutex, LocalCommand, 8> = Channel::new();
+// mqtt 5 replies, after their command was queued, see received_message
+pub static REPLIES: Channel<CriticalSectionRawMutex, Reply, 4> = Channel::new();
+static PUMP: Channel<CriticalSectionRawMutex, PumpRequest, 4> = Channel::new();
+static NETWORK: Channel<CriticalSectionRawMutex, NetworkRequest, 8> = Channel::new();
+static CALIBRATE: Channel<CriticalSectionRawMutex, Point, 2> = Channel::new();
+// pump_task answers PumpRequest::Halt here
+static PUMP_HALTED: Signal<CriticalSectionRawMutex, ()> = Signal::new();
+// float switch, the pump must not run dry
+static WATER_LOW: AtomicBool = AtomicBool::new(false);
+
+enum PumpRequest {
+    // on request, this many ml or the configured volume
+    Water(Option<u32>),
+    // pump the configured volume, soil is dry, skipped while paused
+    AutoWater,
+    // no automatic watering for a while, a running automatic one stops
+    Pause(Duration),
+    // stop a running pump
+    Stop,
+    // stop & confirm on PUMP_HALTED, before a restart or ota
+    Halt,
+    // watering volume, as ml
+    SetVolume(u32),
+}
+
+enum NetworkRequest {
+    // a full sampling pass, published right away
+    Sensors { solid_humidity: u32, temperature: i32, humidity: u32, water_low: Option<bool> },
+    // relay switched, `watered` ml once it is off again
+    Relay { on: bool, watered: Option<u32> },
+    Volume(u32),
+    MqttConnected,
+    Reboot,
+    Ota { url: String, version: String, signature: String },
+    Button(ButtonEvent),
+    // pump start refused, "battery_low" or "water_low"
+    PumpRefused(&'static str),
+    // from the supervisor, reconnect or restart the network stack
+    Recover(supervisor::Level),
+}
+
+#[derive(Clone, Copy, Debug, PartialEq)]
+enum ButtonEvent {
+    // short press, one dose
+    Water,
+    // long press, `button_pause_minutes` without automatic watering
+    Pause,
+    // very long press
+    FactoryReset,
+}
+
+impl ButtonEvent {
+    fn from_press(held: Duration) -> Self {
+        if held >= BUTTON_VERY_LONG_PRESS {
+            Self::FactoryReset
+        } else if held >= BUTTON_LONG_PRESS {
+            Self::Pause
+        } else {
+            Self::Water
+        }
+    }
+
+    // as in telemetry
+    fn as_str(&self) -> &'static str {
+        match self {
+            Self::Water => "water",
+            Self::Pause => "pause",
+            Self::FactoryReset => "factory_reset",
+        }
+    }
+}
+
+pub async fn sensor_task(
+    adc_1_channel_0: &AdcDriver<'_, ADC1>,
+    adc: &mut AdcChannelDriver<'_, Gpio0, &AdcDriver<'_, ADC1>>,
+    mut battery_channel: Option<&mut AdcChannelDriver<'_, Gpio1, &AdcDriver<'_, ADC1>>>,
+    dht_sensor: &mut PinDriver<'_, Gpio3, InputOutput>,
+    water_level: Option<&PinDriver<'_, Gpio5, Input>>,
+    nvs: &EspDefaultNvsPartition,
+) -> Result<()> {
+    loop {
+        supervisor::beat(Task::Sensor);
+        let water_low = water_level.map(check_water_level);
+        if water_low == Some(true) {
+            warn!("water tank low");
+        }
+
+        // battery first, the pump checks it before starting
+        if let Some(channel) = battery_channel.as_deref_mut() {
+            if let Err(e) = battery::measure(adc_1_channel_0, channel) {
+                error!("battery measure error:{}", e);
+            }
+        }
+
+        // a few ms of busy waiting, fine
+        let environment = match dht11::Reading::read(&mut delay::Ets, dht_sensor) {
+            Ok(res) => {
+                // the dht11 reads below 0°C too, keep the sign
+                metrics::set_environment(res.temperature, res.relative_humidity as u32);
+                Some((res.temperature as i32, res.relative_humidity as u32))
+            }
+            Err(e) => {
+                error!("dht11 error:{:?}", e);
+                metrics::sensor_error();
+                None
+            }
+        };
+        // takes ~10s, the other tasks keep going meanwhile
+        let solid_humidity = read_soil_humidity(adc_1_channel_0, adc).await;
+        led::set(Condition::SensorFault, environment.is_none() || solid_humidity.is_none());
+
+        if let (Some((temperature, humidity)), Some(solid_humidity)) = (environment, solid_humidity) {
+            info!("humidity:{}", solid_humidity);
+            NETWORK
+                .send(NetworkRequest::Sensors {
+                    solid_humidity,
+                    temperature,
+                    humidity,
+                    water_low,
+                })
+                .await;
+
+            // commet this if you adjust Plant Moisture Meter threshold
+            if should_water(temperature, solid_humidity) {
+                PUMP.send(PumpRequest::AutoWater).await;
+            } else {
+                info!("skip run pumper");
+            }
+        }
+
+        // a calibration request cuts the wait short, the next pass uses it right away
+        if let Either::Second(point) = select(Timer::after_millis(LOOP_INTERVAL as u64), CALIBRATE.receive()).await {
+            match read_soil_moisture(adc_1_channel_0, adc).await {
+                Some(raw) => {
+                    if let Err(e) = calibration::set(nvs, point, raw) {
+                        error!("calibrate {:?} error:{}", point, e);
+                    }
+                }
+                None => error!("calibrate {:?}, no moisture reading", point),
+            }
+        }
+    }
+}
+
+pub async fn network_task(
+    wifi: WifiManager,
+    nvs: &EspDefaultNvsPartition,
+    settings: &Settings,
+    ha: Option<&HaTopics>,
+    mut ota_pending: Option<PendingConfirm>,
+    mut crash_report: Option<CrashReport>,
+    volume: u32,
+) -> Result<()> {
+    let app_config = CONFIG;
+    // subscriptions, birth message & ha discovery are done on every connect, see NetworkRequest::MqttConnected
+    let mut brokers = BrokerList::new(
+        BrokerEndpoint {
+            url: settings.mqtt_host.clone(),
+            user: settings.mqtt_user.clone(),
+            pass: settings.mqtt_pass.clone(),
+        },
+        app_config.mqtt_fallback_brokers,
+        app_config.mqtt_failover_secs,
+        app_config.mqtt_primary_retry_secs,
+    );
+    let mut client = mqtt_client_connect(brokers.active(), ha)?;
+
+    // latest state of everything, sent as a whole
+    let mut mqtt_msg = MqttMsg::default();
+    mqtt_msg.relay = Some(false);
+    mqtt_msg.pumper_volume = Some(volume);
+
+    let mut remote_log = RemoteLog::new();
+    let mut wifi_verified = settings::wifi_verified(nvs).unwrap_or(false);
+
+    let mut ticker = Ticker::every(NETWORK_CHECK_INTERVAL);
+    loop {
+        let request = match select3(NETWORK.receive(), REPLIES.receive(), ticker.next()).await {
+            Either3::First(request) => request,
+            Either3::Second(reply) => {
+                mqtt_send_reply(&mut client, &reply);
+                continue;
+            }
+            Either3::Third(_) => {
+                supervisor::beat(Task::Network);
+                led::set(Condition::WifiDown, !wifi.state.is_up());
+                led::set(Condition::MqttDown, !broker::is_connected());
+                if let Some(remote_log) = remote_log.as_mut() {
+                    remote_log.send(&mut client, wifi.state.is_up());
+                }
+
+                if wifi.state.is_up() && !wifi_verified {
+                    match settings::set_wifi_verified(nvs) {
+                        Ok(_) => wifi_verified = true,
+                        Err(e) => error!("settings save error:{}", e),
+                    }
+                }
+
+                // wifi reconnects in background, sensing & pump keep running when offline
+                // credentials that never worked keep failing, typo in the psk, let a human fix it
+                // ones that did are waited out, the router may just be down for a while
+                if !wifi.state.is_up()
+                    && !wifi_verified
+                    && app_config.wifi_ap_fallback_failures > 0
+                    && wifi.state.failures() >= app_config.wifi_ap_fallback_failures
+                {
+                    warn!("wifi failed {} times in a row, fallback to provisioning", wifi.state.failures());
+                    halt_pump().await;
+                    settings::request_provisioning(nvs)?;
+                    device_restart(&mut client);
+                }
+
+                // check mqtt broker, failover if needed
+                if let Some(endpoint) = brokers.check() {
+                    match mqtt_client_connect(endpoint, ha) {
+                        Ok(new_client) => client = new_client,
+                        Err(e) => error!("mqtt client create error:{}", e),
+                    }
+                }
+
+                // new firmware healthy?, rolls back & reboots if not in time
+                if let Some(pending) = &ota_pending {
+                    if pending.check(wifi.state.is_up() && broker::is_connected())? {
+                        ota_pending = None;
+                        mqtt_send_ota_status(&mut client, &OtaStatus::new("confirmed", 100, ota::FIRMWARE_VERSION));
+                    }
+                }
+                continue;
+            }
+        };
+
+        match request {
+            NetworkRequest::Sensors {
+                solid_humidity,
+                temperature,
+                humidity,
+                water_low,
+            } => {
+                if !wifi.state.is_up() {
+                    warn!("wifi down, working offline (reconnects:{})", wifi.state.reconnects());
+                }
+                history::push_reading(solid_humidity, temperature, humidity);
+                mqtt_msg.solid_humidity = Some(solid_humidity);
+                mqtt_msg.environment_temperature = Some(temperature);
+                mqtt_msg.environment_humidity = Some(humidity);
+                mqtt_msg.water_low = water_low;
+            }
+            NetworkRequest::Relay { on, watered } => {
+                mqtt_msg.relay = Some(on);
+                // a run cut short by the float switch shows up right away
+                if CONFIG.water_level_sensor {
+                    mqtt_msg.water_low = Some(WATER_LOW.load(Ordering::Relaxed));
+                }
+                if let Some(ml) = watered {
+                    history::push_watering(ml);
+                    mqtt_msg.amount_total = watered;
+                }
+            }
+            NetworkRequest::Volume(val) => mqtt_msg.pumper_volume = Some(val),
+            NetworkRequest::MqttConnected => {
+                mqtt_subscribe_topics(&mut client, ha);
+                mqtt_publish_availability(&mut client, AVAILABILITY_ONLINE);
+                // once, on the first connect after boot
+                if let Some(report) = crash_report.take() {
+                    mqtt_send_crash_report(&mut client, &report);
+                }
+                if let Some(ha) = ha {
+                    if let Err(e) = ha::publish_discovery(ha, &mut client) {
+                        error!("ha discovery error:{}", e);
+                    }
+                }
+                continue;
+            }
+            NetworkRequest::Reboot => {
+                halt_pump().await;
+                device_restart(&mut client);
+            }
+            NetworkRequest::Ota { url, version, signature } => {
+                // no watering while flashing, the download blocks every task for a minute
+                halt_pump().await;
+                mqtt_send_replies(&mut client);
+                let public_key = app_config.ota_public_key;
+                if ota::update(&url, &version, &signature, public_key, |status| mqtt_send_ota_status(&mut client, status)).is_ok() {
+                    device_restart(&mut client);
+                }
+                supervisor::beat(Task::Network);
+                continue;
+            }
+            NetworkRequest::Recover(level) => {
+                let result = match level {
+                    supervisor::Level::RestartNetwork => wifi.restart(),
+                    _ if !wifi.state.is_up() => wifi.reconnect(),
+                    _ => Ok(()),
+                };
+                if let Err(e) = result {
+                    error!("supervisor recover error:{}", e);
+                }
+                // the old client may be the stuck part
+                match mqtt_client_connect(brokers.active(), ha) {
+                    Ok(new_client) => client = new_client,
+                    Err(e) => error!("mqtt client create error:{}", e),
+                }
+                continue;
+            }
+            NetworkRequest::Button(ButtonEvent::FactoryReset) => {
+                // the event goes out before everything is wiped
+                mqtt_msg.button = Some(ButtonEvent::FactoryReset.as_str().to_string());
+                let _ = mqtt_send_msg(&mut client, ha, &mut mqtt_msg);
+                halt_pump().await;
+                settings::factory_reset(nvs)?;
+                device_restart(&mut client);
+            }
+            NetworkRequest::Button(event) => mqtt_msg.button = Some(event.as_str().to_string()),
+            NetworkRequest::PumpRefused(reason) => mqtt_msg.pump_refused = Some(reason.to_string()),
+        }
+
+        mqtt_msg.mqtt_broker = Some(brokers.active().host().to_string());
+        mqtt_msg.wifi_ssid = wifi.state.network();
+        mqtt_msg.wifi_rssi = wifi.state.rssi();
+        set_battery_fields(&mut mqtt_msg);
+        api::set_status(&mqtt_msg);
+        if let Err(e) = mqtt_send_msg(&mut client, ha, &mut mqtt_msg) {
+            error!("mqtt client error:{}", e);
+        }
+        // events go out once
+        mqtt_msg.button = None;
+        mqtt_msg.pump_refused = None;
+    }
+}
+
+// relay off & no more watering until the restart
+async fn halt_pump() {
+    PUMP_HALTED.reset();
+    PUMP.send(PumpRequest::Halt).await;
+    PUMP_HALTED.wait().await;
+}
+
+pub async fn command_task() -> Result<()> {
+    loop {
+        match COMMANDS.receive().await {
+            LocalCommand::Relay(true) => {
+                info!("receive pump on command");
+                PUMP.send(PumpRequest::Water(None)).await;
+            }
+            LocalCommand::Water(ml) => PUMP.send(PumpRequest::Water(Some(ml))).await,
+            LocalCommand::Calibrate(point) => {
+                info!("receive calibrate command: {:?}", point);
+                CALIBRATE.send(point).await;
+            }
+            LocalCommand::Relay(false) => {
+                info!("receive pump off command");
+                PUMP.send(PumpRequest::Stop).await;
+            }
+            LocalCommand::Volume(val) => {
+                info!("receive watering volume: {}ml", val);
+                PUMP.send(PumpRequest::SetVolume(val)).await;
+            }
+            LocalCommand::MqttConnected => NETWORK.send(NetworkRequest::MqttConnected).await,
+            LocalCommand::Reboot => NETWORK.send(NetworkRequest::Reboot).await,
+            LocalCommand::Ota { url, version, signature } => {
+                NETWORK.send(NetworkRequest::Ota { url, version, signature }).await
+            }
+        }
+    }
+}
+
+// float switch into WATER_LOW & the led, the sensor pass reads it and a running pump polls it
+fn check_water_level(pin: &PinDriver<'_, Gpio5, Input>) -> bool {
+    let low = pin.is_high();
+    WATER_LOW.store(low, Ordering::Relaxed);
+    led::set(Condition::LowWater, low);
+    low
+}
+
+pub async fn pump_task(
+    relay_pin: &mut PinDriver<'_, Gpio9, InputOutput>,
+    water_level: Option<&PinDriver<'_, Gpio5, Input>>,
+    mut volume: u32,
+) -> Result<()> {
+    let mut paused_until: Option<Instant> = None;
+    loop {
+        let (auto, dose) = match PUMP.receive().await {
+            PumpRequest::Water(ml) => (false, ml.unwrap_or(volume)),
+            PumpRequest::AutoWater => (true, volume),
+            PumpRequest::Stop => continue,
+            PumpRequest::Halt => {
+                relay_pin.set_low()?;
+                PUMP_HALTED.signal(());
+                continue;
+            }
+            PumpRequest::SetVolume(val) => {
+                volume = val;
+                report_status(NetworkRequest::Volume(val));
+                continue;
+            }
+            PumpRequest::Pause(duration) => {
+                info!("automatic watering paused for {}min", duration.as_secs() / 60);
+                paused_until = Some(Instant::now() + duration);
+                continue;
+            }
+        };
+        if auto && paused_until.is_some_and(|until| Instant::now() < until) {
+            info!("automatic watering paused, skip run pumper");
+            continue;
+        }
+        if !battery::pump_allowed() {
+            report_status(NetworkRequest::PumpRefused("battery_low"));
+            continue;
+        }
+        if let Some(pin) = water_level {
+            check_water_level(pin);
+        }
+        if WATER_LOW.load(Ordering::Relaxed) {
+            warn!("water tank low, pump not started");
+            report_status(NetworkRequest::PumpRefused("water_low"));
+            continue;
+        }
+
+        // run pumper time
+        let time = convert_volume_to_pumperworking_time_ms(dose);
+        info!("pump starting!\nwater: {}ml, working time: {}ms", dose, time);
+        relay_pin.set_high()?;
+        led::set(Condition::Pumping, true);
+        let started = Instant::now();
+        report_status(NetworkRequest::Relay { on: true, watered: None });
+
+        // until done, or cut short
+        let done = started + Duration::from_millis(time as u64);
+        let mut halted = false;
+        let mut ticker = Ticker::every(Duration::from_millis(100));
+        loop {
+            match select3(Timer::at(done), PUMP.receive(), ticker.next()).await {
+                Either3::First(_) => break,
+                Either3::Second(PumpRequest::Stop) => {
+                    info!("pump stopped early");
+                    break;
+                }
+                Either3::Second(PumpRequest::Halt) => {
+                    halted = true;
+                    break;
+                }
+                Either3::Second(PumpRequest::Pause(duration)) => {
+                    info!("automatic watering paused for {}min", duration.as_secs() / 60);
+                    paused_until = Some(Instant::now() + duration);
+                    if auto {
+                        break;
+                    }
+                }
+                Either3::Second(PumpRequest::Water(_) | PumpRequest::AutoWater) => info!("pump already running"),
+                // for the next run
+                Either3::Second(PumpRequest::SetVolume(val)) => {
+                    volume = val;
+                    report_status(NetworkRequest::Volume(val));
+                }
+                // the tank can run dry halfway through a dose
+                Either3::Third(_) => {
+                    if let Some(pin) = water_level {
+                        check_water_level(pin);
+                    }
+                    if WATER_LOW.load(Ordering::Relaxed) {
+                        warn!("water tank ran low, pump stopped");
+                        break;
+                    }
+                }
+            }
+        }
+
+        while let Level::High = relay_pin.get_level() {
+            relay_pin.set_low().ok();
+            Timer::after_millis(100).await;
+        }
+        info!("pump stopped!");
+        led::set(Condition::Pumping, false);
+        if halted {
+            PUMP_HALTED.signal(());
+        }
+        let runtime_ms = started.elapsed().as_millis();
+        metrics::pump_run(runtime_ms);
+        let watered = watered_ml(runtime_ms as u32);
+        report_status(NetworkRequest::Relay {
+            on: false,
+            watered: Some(watered),
+        });
+    }
+}
+
+// pump status to the network task, never waits. the network task itself halts the pump before
+// a restart or ota (halt_pump) and does not drain NETWORK meanwhile, a pump task blocked on a
+// full channel would never see the Halt
+fn report_status(request: NetworkRequest) {
+    if NETWORK.try_send(request).is_err() {
+        error!("network channel full, pump status dropped");
+    }
+}
+
+// feeds the task watchdog while the other tasks check in, escalates while offline,
+// see supervisor.rs
+pub async fn supervisor_task(twdt: &mut TWDTDriver<'_>, wifi: Arc<WifiState>) -> Result<()> {
+    let mut watchdog = twdt.watch_current_task()?;
+    let mut escalation = Escalation::new();
+    let mut starving = false;
+    // setup before the tasks took a while, count from here
+    supervisor::beat(Task::Sensor);
+    supervisor::beat(Task::Network);
+
+    let mut ticker = Ticker::every(SUPERVISOR_INTERVAL);
+    loop {
+        ticker.next().await;
+        if let Some((task, secs)) = supervisor::stalled() {
+            // no more feeding, the watchdog resets the board
+            if !starving {
+                let message = format!("supervisor: {:?} task stalled {}s, watchdog reset", task, secs);
+                error!("{}", message);
+                crash::record_message(&message);
+                starving = true;
+            }
+            continue;
+        }
+        starving = false;
+        watchdog.feed()?;
+
+        if let Some((level, offline)) = escalation.check(wifi.is_up() && broker::is_connected()) {
+            let message = format!("supervisor level {} ({:?}): offline {}s", level as u8, level, offline);
+            warn!("{}", message);
+            match level {
+                supervisor::Level::Reboot => {
+                    crash::record_message(&message);
+                    NETWORK.send(NetworkRequest::Reboot).await;
+                }
+                level => NETWORK.send(NetworkRequest::Recover(level)).await,
+            }
+        }
+    }
+}
+
+// the button waits on the gpio interrupt, a press counts once the level is stable for
+// BUTTON_DEBOUNCE, how long it was held decides the action.
+// the boot-held press for provisioning never gets here, provisioning does not return.
+pub async fn button_task(button: &mut PinDriver<'_, Gpio4, Input>) -> Result<()> {
+    let pause = Duration::from_secs(CONFIG.button_pause_minutes as u64 * 60);
+    loop {
+        button.wait_for_low().await?;
+        Timer::after(BUTTON_DEBOUNCE).await;
+        if button.is_high() {
+            continue;
+        }
+        let pressed = Instant::now();
+        loop {
+            button.wait_for_high().await?;
+            Timer::after(BUTTON_DEBOUNCE).await;
+            if button.is_high() {
+                break;
+            }
+        }
+
+        let event = ButtonEvent::from_press(pressed.elapsed());
+        info!("button {:?}, held {}ms", event, pressed.elapsed().as_millis());
+        match event {
+            ButtonEvent::Water => PUMP.send(PumpRequest::Water(None)).await,
+            ButtonEvent::Pause => PUMP.send(PumpRequest::Pause(pause)).await,
+            // network_task publishes the event first
+            ButtonEvent::FactoryReset => warn!("factory reset requested"),
+        }
+        NETWORK.send(NetworkRequest::Button(event)).await;
+    }
+}
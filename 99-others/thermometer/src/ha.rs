@@ -0,0 +1,90 @@
+// home assistant mqtt discovery
+// https://www.home-assistant.io/integrations/mqtt/#mqtt-discovery
+//
+// every entity gets a retained config message under
+// <prefix>/sensor/<node_id>/<object_id>/config
+// and reads its value from one shared json state topic
+
+use anyhow::Result;
+use esp_idf_svc::mqtt::client::{EspMqttClient, QoS};
+use log::info;
+use serde_json::{json, Value};
+
+pub struct HaTopics {
+    pub prefix: String,
+    pub node_id: String,
+    // empty if availability is disabled
+    pub availability: String,
+    // battery voltage & charge entities
+    pub battery: bool,
+}
+
+impl HaTopics {
+    pub fn new(prefix: &str, node_id: &str, availability: &str) -> Self {
+        Self {
+            prefix: prefix.to_string(),
+            node_id: node_id.to_string(),
+            availability: availability.to_string(),
+            battery: false,
+        }
+    }
+
+    // dht11 reading json goes here
+    pub fn state(&self) -> String {
+        format!("funny_games/{}/state", self.node_id)
+    }
+
+    fn config(&self, object_id: &str) -> String {
+        format!("{}/sensor/{}/{}/config", self.prefix, self.node_id, object_id)
+    }
+
+    fn sensor(&self, object_id: &str, name: &str, device_class: &str, unit: &str) -> Value {
+        let mut sensor = json!({
+            "name": name,
+            "unique_id": format!("{}_{}", self.node_id, object_id),
+            "object_id": format!("{}_{}", self.node_id, object_id),
+            "state_topic": self.state(),
+            "device_class": device_class,
+            "unit_of_measurement": unit,
+            "state_class": "measurement",
+            "value_template": format!("{{{{ value_json.{} }}}}", object_id),
+            "device": {
+                "identifiers": [self.node_id],
+                "name": "thermometer",
+                "model": "esp32c3 dht11 thermometer",
+                "manufacturer": "funny_games",
+                "sw_version": env!("CARGO_PKG_VERSION"),
+            },
+        });
+        if !self.availability.is_empty() {
+            sensor["availability_topic"] = json!(self.availability);
+        }
+        sensor
+    }
+
+    // publish retained discovery configs, should be called once the client is connected
+    pub fn publish_discovery(&self, client: &mut EspMqttClient<'static>) -> Result<()> {
+        let mut entities = vec![
+            ("temperature", self.sensor("temperature", "Temperature", "temperature", "°C")),
+            ("humidity", self.sensor("humidity", "Humidity", "humidity", "%")),
+            ("wifi_rssi", diagnostic(self.sensor("wifi_rssi", "WiFi Signal", "signal_strength", "dBm"))),
+        ];
+        if self.battery {
+            entities.push(("battery_voltage", diagnostic(self.sensor("battery_voltage", "Battery Voltage", "voltage", "V"))));
+            entities.push(("battery_soc", self.sensor("battery_soc", "Battery", "battery", "%")));
+        }
+        for (object_id, config) in entities {
+            let topic = self.config(object_id);
+            let payload = serde_json::to_string(&config)?;
+            client.enqueue(&topic, QoS::AtLeastOnce, true, payload.as_bytes())?;
+            info!("ha discovery published:{}", topic);
+        }
+        Ok(())
+    }
+}
+
+// shown under the device's diagnostic section in HA
+fn diagnostic(mut sensor: Value) -> Value {
+    sensor["entity_category"] = json!("diagnostic");
+    sensor
+}
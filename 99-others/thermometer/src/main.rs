@@ -1,21 +1,39 @@
-use anyhow::Result;
+mod ha;
+mod metrics;
+mod sleep;
+mod tasks;
+
+use anyhow::{bail, Result};
 use std::result::Result::Ok;
+use std::sync::atomic::{AtomicBool, Ordering};
+use std::time::{Duration, Instant};
 use dht_sensor::{dht11::{self, Reading}, DhtReading};
+use embassy_futures::select::{select, Either};
 use esp_idf_svc::{
     eventloop::EspSystemEventLoop,
     hal::{
+        adc::{
+            attenuation::DB_11,
+            oneshot::{config::AdcChannelConfig, AdcChannelDriver, AdcDriver},
+            ADC1,
+        },
         delay::{self, FreeRtos},
-        gpio::PinDriver,
-        prelude::Peripherals,
-    },
-    mqtt::{
-        self,
-        client::{EspMqttClient, EventPayload, MqttProtocolVersion, QoS},
+        gpio::{Gpio1, Gpio3, InputOutput, PinDriver},
+        task::block_on,
     },
-    nvs::EspDefaultNvsPartition,
-    wifi::{BlockingWifi, ClientConfiguration, Configuration, EspWifi},
+    mqtt::client::{EspMqttClient, QoS},
+    wifi::EspWifi,
 };
-use log::{error, info, warn};
+use funny_core::battery;
+use funny_core::crash::{self, CrashReport};
+use funny_core::mdns;
+use funny_core::mqtt::{self, MqttClient, AVAILABILITY_OFFLINE, AVAILABILITY_ONLINE};
+use funny_core::wifi::{self, KnownNetwork, WifiManager};
+use funny_core::{embedded_certificates, Device, MqttConfig, MqttEvent, Protocol, Telemetry, WifiConfig};
+use ha::HaTopics;
+use log::{error, info};
+use sleep::{RtcState, Sample};
+use serde_json::json;
 use serde::ser::{Serialize, SerializeStruct, Serializer};
 
 #[toml_cfg::toml_config]
@@ -30,16 +48,86 @@ pub struct Config {
     wifi_ssid: &'static str,
     #[default("")]
     wifi_psk: &'static str,
+    // more known networks after wifi_ssid, "ssid|psk;ssid|psk", the strongest visible one is used
+    #[default("")]
+    wifi_networks: &'static str,
     #[default("")]
     mqtt_clientid: &'static str,
     #[default("")]
     mqtt_topic: &'static str,
     #[default("")]
     mqtt_push_topic: &'static str,
+    // home assistant mqtt discovery
+    #[default(false)]
+    ha_discovery: bool,
+    #[default("homeassistant")]
+    ha_discovery_prefix: &'static str,
+    // default to mqtt_clientid
+    #[default("")]
+    ha_node_id: &'static str,
+    // retained "online" birth & "offline" last will
+    // empty to disable
+    #[default("")]
+    mqtt_availability_topic: &'static str,
+    // "3.1.1" or "5", keep 3.1.1 for ThingsCloud
+    #[default("3.1.1")]
+    mqtt_protocol: &'static str,
+    // mqtt 5 only, readings expire on the broker after N seconds, 0 to disable
+    #[default(0)]
+    mqtt_message_expiry: u32,
+    // battery mode, deep sleep N seconds between samples, 0 to stay awake
+    #[default(0)]
+    deep_sleep_secs: u32,
+    // battery mode, connect & publish every N wake-ups
+    #[default(4)]
+    deep_sleep_batch: u32,
+    // "li-ion" or "lifepo4", empty to disable battery monitoring
+    #[default("")]
+    battery_chemistry: &'static str,
+    // (R1 + R2) / R2 of the divider in front of gpio1
+    #[default(2.0)]
+    battery_divider: f32,
+    // cells in series
+    #[default(1)]
+    battery_cells: u32,
+    // prometheus /metrics on port 80, awake mode only
+    #[default(true)]
+    metrics_http: bool,
+    // reset reason & last panic after every boot, empty to use mqtt_topic
+    #[default("")]
+    crash_topic: &'static str,
+    // answer as funny-thermometer-xxxx.local & advertise the services, awake mode only
+    #[default(true)]
+    mdns: bool,
+}
+
+// set by mqtt callback on every (re)connect, birth message is sent from main loop
+static MQTT_CONNECTED: AtomicBool = AtomicBool::new(false);
+
+// battery mode, how long a publishing wake-up waits for wifi & mqtt
+const WAKE_CONNECT_TIMEOUT: Duration = Duration::from_secs(20);
+// battery mode, give the mqtt task time to flush the outbox before sleeping
+const WAKE_LINGER_MS: u32 = 1000;
+
+// battery adc, only set up when `battery_chemistry` is configured
+struct BatteryAdc<'d> {
+    adc: &'d AdcDriver<'d, ADC1>,
+    channel: AdcChannelDriver<'d, Gpio1, &'d AdcDriver<'d, ADC1>>,
+}
+
+impl BatteryAdc<'_> {
+    fn measure(&mut self) {
+        if let Err(e) = battery::measure(self.adc, &mut self.channel) {
+            error!("battery measure error:{}", e);
+        }
+    }
 }
 
 struct MyReading {
     reading: Reading,
+    wifi_ssid: Option<String>,
+    wifi_rssi: Option<i8>,
+    battery: Option<battery::Reading>,
 }
 
 impl Serialize for MyReading {
@@ -47,114 +135,308 @@ impl Serialize for MyReading {
     where
         S: Serializer,
     {
-        let mut s = serializer.serialize_struct("temperature", 2)?;
+        let mut s = serializer.serialize_struct("temperature", 6)?;
         s.serialize_field("temperature", &self.reading.temperature)?;
         s.serialize_field("humidity", &self.reading.relative_humidity)?;
+        s.serialize_field("wifi_ssid", &self.wifi_ssid)?;
+        s.serialize_field("wifi_rssi", &self.wifi_rssi)?;
+        s.serialize_field("battery_voltage", &self.battery.map(|reading| reading.volts()))?;
+        s.serialize_field("battery_soc", &self.battery.map(|reading| reading.soc))?;
         s.end()
     }
 }
 
-//
-fn wifi_connect(wifi: &mut BlockingWifi<EspWifi>) -> Result<(),anyhow::Error> {
+// the shared keys of cfg.toml, for funny-core
+fn mqtt_config() -> MqttConfig<'static> {
     let app_config = CONFIG;
-    wifi.set_configuration(&Configuration::Client(ClientConfiguration {
-        ssid: app_config.wifi_ssid.try_into().unwrap(),
-        password: app_config.wifi_psk.try_into().unwrap(),
-        ..Default::default()
-    }))?;
+    MqttConfig {
+        url: app_config.mqtt_host,
+        user: app_config.mqtt_user,
+        pass: app_config.mqtt_pass,
+        client_id: app_config.mqtt_clientid,
+        protocol: Protocol::parse(app_config.mqtt_protocol),
+        availability_topic: app_config.mqtt_availability_topic,
+        network_timeout: None,
+    }
+}
 
-    while wifi.is_connected()? == false {
-        wifi.start()?;
-        info!("Wifi started");
+fn telemetry() -> Telemetry<'static> {
+    let app_config = CONFIG;
+    Telemetry {
+        topic: app_config.mqtt_topic,
+        protocol: Protocol::parse(app_config.mqtt_protocol),
+        message_expiry: app_config.mqtt_message_expiry,
+        device: "thermometer",
+        version: env!("CARGO_PKG_VERSION"),
+    }
+}
+
+fn mqtt_client_init() -> Result<MqttClient> {
+    // the thermometer only publishes, a connect is all it listens for
+    mqtt::connect(&mqtt_config(), &embedded_certificates!(), |event| {
+        if let MqttEvent::Connected = event {
+            MQTT_CONNECTED.store(true, Ordering::Relaxed);
+        }
+    })
+}
 
-        wifi.connect()?;
-        info!("Wifi connected");
+// retained availability state, if enabled
+fn mqtt_publish_availability(client: &mut EspMqttClient<'static>, payload: &str) {
+    mqtt::publish_availability(client, CONFIG.mqtt_availability_topic, payload);
+}
 
-        wifi.wait_netif_up()?;
-        info!("Wifi netif up");
-    }
+// reset reason, boot count & last panic, to crash_topic or mqtt_topic
+fn mqtt_publish_crash_report(client: &mut EspMqttClient<'static>, report: &CrashReport) {
+    let app_config = CONFIG;
+    let topic = match app_config.crash_topic {
+        "" => app_config.mqtt_topic,
+        topic => topic,
+    };
+    crash::publish(client, topic, report);
+}
 
+// reading to mqtt_topic, and the ha state topic if enabled
+fn mqtt_publish_reading(client: &mut EspMqttClient<'static>, ha: Option<&HaTopics>, myres: &MyReading) -> Result<()> {
+    let ha_state = ha.map(HaTopics::state);
+    telemetry().publish(client, ha_state.as_deref(), myres)?;
     Ok(())
 }
 
-fn mqtt_client_init() -> Result<EspMqttClient<'static>> {
-    // mqtt client
+// battery mode, one wake-up: sample, maybe publish, deep sleep again
+fn low_power_cycle(
+    wifi_driver: EspWifi<'static>,
+    sysloop: &EspSystemEventLoop,
+    networks: Vec<KnownNetwork>,
+    dht11_pin: &mut PinDriver<'_, Gpio3, InputOutput>,
+    battery_adc: Option<&mut BatteryAdc<'_>>,
+    crash_report: Option<CrashReport>,
+) -> Result<()> {
     let app_config = CONFIG;
-    // info!("connect args:{}",app_config.mqtt_host);
-    // let mqtt_config = mqtt::client::MqttClientConfiguration::default();
-    let client: EspMqttClient<'static> = EspMqttClient::new_cb(
-        app_config.mqtt_host,
-        &mqtt::client::MqttClientConfiguration {
-            client_id: Some(app_config.mqtt_clientid),
-            username: Some(app_config.mqtt_user),
-            password: Some(&app_config.mqtt_pass),
-            protocol_version: Some(MqttProtocolVersion::V3_1_1),
-            ..Default::default()
-        },
-        move |message_event| match message_event.payload() {
-            EventPayload::Error(e) => error!("MQTT error {:?}", e),
-            e => warn!("MQTT event {:?}", e),
-        },
-    )?;
+    let mut rtc = sleep::load();
+    rtc.wakeups += 1;
+    info!("wake-up {}, by timer:{}", rtc.wakeups, sleep::woke_from_timer());
+    if let Some(battery_adc) = battery_adc {
+        battery_adc.measure();
+    }
+
+    match dht11::Reading::read(&mut delay::Ets, dht11_pin) {
+        Ok(res) => {
+            let filtered = rtc.board.filter_temperature(rtc.wakeups, res.temperature);
+            info!("temperature:{} filtered:{} humidity:{}", res.temperature, filtered, res.relative_humidity);
+            rtc.push_sample(Sample {
+                temperature: res.temperature,
+                humidity: res.relative_humidity,
+                wakeup: rtc.wakeups,
+            });
+        }
+        Err(e) => {
+            error!("Reading DHT11 Data ERROR:{:?}", e);
+            rtc.board.read_errors += 1;
+        }
+    }
+
+    // first wake-up after power on, then every `deep_sleep_batch` wake-ups
+    let batch = app_config.deep_sleep_batch.max(1);
+    if rtc.wakeups == 1 || rtc.wakeups % batch == 0 {
+        let wifi = WifiManager::start(wifi_driver, sysloop, networks, rtc.ap_hint())?;
+        match low_power_publish(&wifi, &mut rtc, crash_report.as_ref()) {
+            Ok(_) => rtc.clear_samples(),
+            Err(e) => error!("publish error:{}, keep {} samples", e, rtc.samples().len()),
+        }
+    }
 
-    Ok(client)
+    sleep::store(&rtc);
+    sleep::deep_sleep(Duration::from_secs(app_config.deep_sleep_secs as u64))
 }
 
-fn main() -> Result<()> {
-    // It is necessary to call this function once. Otherwise some patches to the runtime
-    // implemented by esp-idf-sys might not link properly. See https://github.com/esp-rs/esp-idf-template/issues/71
-    esp_idf_svc::sys::link_patches();
+// battery mode, bring up mqtt & publish the samples
+fn low_power_publish(wifi: &WifiManager, rtc: &mut RtcState, crash_report: Option<&CrashReport>) -> Result<()> {
+    let app_config = CONFIG;
+    let started = Instant::now();
+    while !wifi.state.is_up() && started.elapsed() < WAKE_CONNECT_TIMEOUT {
+        FreeRtos::delay_ms(100);
+    }
+    if !wifi.state.is_up() {
+        // cached ap is gone?, scan next time
+        rtc.set_ap_hint(None);
+        bail!("wifi not up in {:?}", WAKE_CONNECT_TIMEOUT);
+    }
+    rtc.set_ap_hint(wifi.state.ap_hint());
+
+    let mut client = mqtt_client_init()?;
+    while !MQTT_CONNECTED.load(Ordering::Relaxed) && started.elapsed() < WAKE_CONNECT_TIMEOUT {
+        FreeRtos::delay_ms(100);
+    }
+    if !MQTT_CONNECTED.swap(false, Ordering::Relaxed) {
+        bail!("mqtt not connected in {:?}", WAKE_CONNECT_TIMEOUT);
+    }
+    mqtt_publish_availability(&mut client, AVAILABILITY_ONLINE);
+    if let Some(report) = crash_report {
+        mqtt_publish_crash_report(&mut client, report);
+    }
+
+    // latest sample as the usual reading, all of them in `samples`
+    let latest = rtc.samples().last().copied();
+    let batch = json!({
+        "temperature": latest.map(|sample| sample.temperature),
+        "humidity": latest.map(|sample| sample.humidity),
+        "temperature_filtered": rtc.board.filtered_temperature as f32 / 100.0,
+        "wifi_ssid": wifi.state.network(),
+        "wifi_rssi": wifi.state.rssi(),
+        "read_errors": rtc.board.read_errors,
+        "battery_voltage": battery::last().map(|reading| reading.volts()),
+        "battery_soc": battery::last().map(|reading| reading.soc),
+        "samples": rtc.samples().iter().map(|sample| json!({
+            "temperature": sample.temperature,
+            "humidity": sample.humidity,
+            // seconds before the publish
+            "age": (rtc.wakeups - sample.wakeup) * app_config.deep_sleep_secs,
+        })).collect::<Vec<_>>(),
+    });
+    let payload = telemetry().publish(&mut client, None, &batch)?;
+
+    if app_config.ha_discovery {
+        let node_id = match app_config.ha_node_id {
+            "" => app_config.mqtt_clientid,
+            id => id,
+        };
+        let mut ha = HaTopics::new(app_config.ha_discovery_prefix, node_id, app_config.mqtt_availability_topic);
+        ha.battery = battery::last().is_some();
+        if let Err(e) = ha.publish_discovery(&mut client) {
+            error!("ha discovery error:{}", e);
+        }
+        client.publish(&ha.state(), QoS::AtMostOnce, false, payload.as_bytes())?;
+    }
+    FreeRtos::delay_ms(WAKE_LINGER_MS);
+    // planned sleep, gone until the next publishing wake-up. the last will only fires once
+    // the broker times out the session
+    mqtt_publish_availability(&mut client, AVAILABILITY_OFFLINE);
+    // give the mqtt task some time to flush
+    FreeRtos::delay_ms(500);
+    Ok(())
+}
+
+// txt `caps` for mdns, comma separated there
+fn mdns_capabilities(http: bool) -> Vec<&'static str> {
+    let mut caps = vec!["mqtt", "temperature", "humidity"];
+    if http {
+        caps.extend(["http", "metrics"]);
+    }
+    if CONFIG.ha_discovery {
+        caps.push("ha");
+    }
+    if !CONFIG.battery_chemistry.is_empty() {
+        caps.push("battery");
+    }
+    caps
+}
 
-    // Bind the log crate to the ESP Logging facilities
-    esp_idf_svc::log::EspLogger::initialize_default();
+fn main() -> Result<()> {
+    // logger, panic hook, sysloop, nvs & peripherals, see funny-core
+    let Device {
+        sysloop,
+        nvs,
+        peripherals: peripheral,
+    } = Device::take()?;
 
-    let sysloop = EspSystemEventLoop::take()?;
-    let nvs = EspDefaultNvsPartition::take()?;
-    let peripheral = Peripherals::take()?;
+    // reset reason & last panic, published once mqtt is up
+    let crash_report = match crash::boot(&nvs, env!("CARGO_PKG_VERSION")) {
+        Ok(report) => report,
+        Err(e) => {
+            error!("crash report error:{}", e);
+            None
+        }
+    };
 
     // Hardware Setup
     // wifi
-    let mut wifi = BlockingWifi::wrap(
-        EspWifi::new(peripheral.modem, sysloop.clone(), Some(nvs))?,
-        sysloop.clone(),
-    )?;
+    let wifi_driver = wifi::driver(peripheral.modem, &sysloop, &nvs)?;
     // dht11
     let mut dht11_pin = PinDriver::input_output(peripheral.pins.gpio3)?;
     dht11_pin.set_high()?;
+    // battery voltage, optional
+    // use pin: gpio1, through a resistor divider, the curves & averaging are funny_core::battery
+    //   pack+ --- R1 --- gpio1 --- R2 --- gnd,   battery_divider = (R1 + R2) / R2
+    let adc1 = AdcDriver::new(peripheral.adc1)?;
+    let mut battery_adc = match battery::init(CONFIG.battery_chemistry, CONFIG.battery_cells, CONFIG.battery_divider) {
+        Some(_) => Some(BatteryAdc {
+            adc: &adc1,
+            channel: AdcChannelDriver::new(
+                &adc1,
+                peripheral.pins.gpio1,
+                &AdcChannelConfig {
+                    attenuation: DB_11,
+                    calibration: true,
+                    ..Default::default()
+                },
+            )?,
+        }),
+        None => None,
+    };
 
     // Start Process
     // connect wifi
-    while  let Err(_) = wifi_connect(&mut wifi) {
-        wifi_connect(&mut wifi)?;
-    }
-   
-    // init mqtt client
+    // reconnects by itself in background, never blocks the loop
     let app_config = CONFIG;
-    let mut client = mqtt_client_init()?;
+    let networks = WifiConfig {
+        ssid: app_config.wifi_ssid,
+        psk: app_config.wifi_psk,
+        networks: app_config.wifi_networks,
+    }
+    .known_networks();
 
-    info!("start loop!!");
-    
-    loop {
-        // fetch dht11 data & send to MQTT server
-        match dht11::Reading::read(&mut delay::Ets, &mut dht11_pin){
-            Ok(res) => {
-                let myres = MyReading { reading: res };
-                let payload = serde_json::to_string(&myres)?;
-                client.publish(
-                    app_config.mqtt_topic,
-                    QoS::AtMostOnce,
-                    false,
-                    payload.as_bytes(),
-                )?;
-            },
-            Err(e)=> {
-                error!("Reading DHT11 Data ERROR:{:?}",e);
-            },
-        };
+    // battery mode, one sample per wake-up, never returns
+    if app_config.deep_sleep_secs > 0 {
+        return low_power_cycle(wifi_driver, &sysloop, networks, &mut dht11_pin, battery_adc.as_mut(), crash_report);
+    }
 
-        
+    let wifi = WifiManager::start(wifi_driver, &sysloop, networks, None)?;
 
+    let metrics_server = match app_config.metrics_http {
+        true => Some(metrics::start(wifi.state.clone())?),
+        false => None,
+    };
+    // the http services only while /metrics is served
+    let _mdns = match app_config.mdns {
+        true => {
+            let http_port = metrics_server.as_ref().map(|_| metrics::HTTP_PORT);
+            let caps = mdns_capabilities(http_port.is_some());
+            mdns::start("thermometer", env!("CARGO_PKG_VERSION"), &caps, http_port)
+                .map_err(|e| error!("mdns error:{}", e))
+                .ok()
+        }
+        false => None,
+    };
 
-        FreeRtos::delay_ms(1000 *10);
-    }
+    // init mqtt client
+    let client = mqtt_client_init()?;
+
+    // home assistant
+    let ha = if app_config.ha_discovery {
+        let node_id = match app_config.ha_node_id {
+            "" => app_config.mqtt_clientid,
+            id => id,
+        };
+        let mut ha = HaTopics::new(app_config.ha_discovery_prefix, node_id, app_config.mqtt_availability_topic);
+        ha.battery = battery_adc.is_some();
+        // discovery goes out on every connect, see tasks::network_task
+        Some(ha)
+    } else {
+        None
+    };
+
+    // sampling & publishing side by side, see tasks.rs
+    // they run forever, only an error ends them
+    info!("start tasks");
+    block_on(async {
+        match select(
+            tasks::sensor_task(&mut dht11_pin, battery_adc.as_mut()),
+            tasks::network_task(wifi, client, ha.as_ref(), crash_report),
+        )
+        .await
+        {
+            Either::First(result) | Either::Second(result) => result,
+        }
+    })
+    .inspect_err(crash::record_error)
 }
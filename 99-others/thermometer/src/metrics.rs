@@ -0,0 +1,72 @@
+// prometheus metrics, GET /metrics on port 80 in text exposition format, awake mode only
+//
+//   scrape_configs:
+//     - job_name: thermometer
+//       static_configs:
+//         - targets: ["192.168.1.51:80"]
+//
+// the sensor task records into here, the http handler renders a snapshot on every scrape, the
+// text format itself is funny_core::prometheus.
+
+use std::sync::{Arc, Mutex};
+
+use anyhow::Result;
+use esp_idf_svc::http::server::{Configuration as HttpConfiguration, EspHttpServer};
+use esp_idf_svc::http::Method;
+use esp_idf_svc::io::Write;
+use funny_core::battery;
+use funny_core::prometheus::{self, Exposition};
+use funny_core::wifi::WifiState;
+use log::info;
+
+// also advertised over mdns
+pub const HTTP_PORT: u16 = 80;
+
+struct Metrics {
+    temperature: Option<i8>,
+    humidity: Option<u32>,
+    sensor_errors: u32,
+}
+
+static METRICS: Mutex<Metrics> = Mutex::new(Metrics {
+    temperature: None,
+    humidity: None,
+    sensor_errors: 0,
+});
+
+pub fn set_environment(temperature: i8, humidity: u32) {
+    let mut metrics = METRICS.lock().unwrap();
+    metrics.temperature = Some(temperature);
+    metrics.humidity = Some(humidity);
+}
+
+pub fn sensor_error() {
+    METRICS.lock().unwrap().sensor_errors += 1;
+}
+
+// keep the server alive as long as /metrics should be reachable
+pub fn start(wifi: Arc<WifiState>) -> Result<EspHttpServer<'static>> {
+    let mut server = EspHttpServer::new(&HttpConfiguration {
+        http_port: HTTP_PORT,
+        ..Default::default()
+    })?;
+    server.fn_handler::<anyhow::Error, _>("/metrics", Method::Get, move |req| {
+        req.into_response(200, None, &[("Content-Type", prometheus::CONTENT_TYPE)])?
+            .write_all(render(&wifi).as_bytes())?;
+        Ok(())
+    })?;
+    info!("metrics http server started");
+    Ok(server)
+}
+
+fn render(wifi: &WifiState) -> String {
+    let metrics = METRICS.lock().unwrap();
+    let mut out = Exposition::new("thermometer", env!("CARGO_PKG_VERSION"));
+    out.system();
+    out.metric("temperature_celsius", "gauge", "DHT11 temperature.", metrics.temperature);
+    out.metric("humidity_percent", "gauge", "DHT11 relative humidity.", metrics.humidity);
+    out.metric("sensor_errors_total", "counter", "Failed DHT11 readings.", Some(metrics.sensor_errors));
+    out.wifi(wifi);
+    out.battery(battery::last());
+    out.finish()
+}
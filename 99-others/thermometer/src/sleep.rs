@@ -0,0 +1,70 @@
+// deep sleep battery mode, `deep_sleep_secs` > 0
+//
+// every wake-up: sample, back to sleep. wifi & mqtt only come up every `deep_sleep_batch`
+// wake-ups to publish the collected samples at once.
+// wake-ups, samples & the last good ap are funny_core::sleep::RtcState, the thermometer adds
+// its counters & the temperature filter.
+
+use funny_core::sleep::RtcSlot;
+use serde::{Deserialize, Serialize};
+
+pub use funny_core::sleep::{deep_sleep, woke_from_timer};
+
+// samples kept while offline, the oldest is dropped when full
+pub const MAX_SAMPLES: usize = 32;
+// wrong magic = cold boot or the layout changed with a firmware update
+const MAGIC: u32 = 0x5468_726f;
+
+#[derive(Clone, Copy, Debug, Default, Serialize, Deserialize)]
+pub struct Sample {
+    pub temperature: i8,
+    pub humidity: u8,
+    // wake-up the sample was taken on
+    #[serde(skip)]
+    pub wakeup: u32,
+}
+
+#[derive(Clone, Copy, Default)]
+pub struct Counters {
+    // failed dht11 reads since power on
+    pub read_errors: u32,
+    // exponential moving average of the temperature, x100
+    pub filtered_temperature: i32,
+}
+
+impl Counters {
+    // smooths out dht11 jitter between wake-ups, returns the filtered value
+    pub fn filter_temperature(&mut self, wakeups: u32, temperature: i8) -> i8 {
+        if wakeups <= 1 {
+            self.filtered_temperature = temperature as i32 * 100;
+        } else {
+            // 1/4 new, 3/4 history
+            self.filtered_temperature = (self.filtered_temperature * 3 + temperature as i32 * 100) / 4;
+        }
+        (self.filtered_temperature / 100) as i8
+    }
+}
+
+pub type RtcState = funny_core::sleep::RtcState<Counters, Sample, MAX_SAMPLES>;
+
+// rtc memory of the previous wake-up, empty after a cold boot
+pub fn load() -> RtcState {
+    RTC_STATE.load(MAGIC)
+}
+
+pub fn store(state: &RtcState) {
+    RTC_STATE.store(state);
+}
+
+#[link_section = ".rtc.data"]
+static RTC_STATE: RtcSlot<RtcState> = RtcSlot::new(RtcState::new(
+    Counters {
+        read_errors: 0,
+        filtered_temperature: 0,
+    },
+    Sample {
+        temperature: 0,
+        humidity: 0,
+        wakeup: 0,
+    },
+));
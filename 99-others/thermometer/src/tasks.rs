@@ -0,0 +1,105 @@
+// awake mode tasks, run concurrently on one executor (see main)
+//
+//   sensor_task --READINGS--> network_task (mqtt client)
+//
+// sampling keeps its pace whatever the network does, a slow publish or reconnect does not
+// shift the next reading. the dht11 read is a few ms of busy waiting, everything else awaits.
+
+use std::sync::atomic::Ordering;
+
+use anyhow::Result;
+use dht_sensor::{dht11::{self, Reading}, DhtReading};
+use embassy_futures::select::{select, Either};
+use embassy_sync::blocking_mutex::raw::CriticalSectionRawMutex;
+use embassy_sync::channel::Channel;
+use embassy_time::{Duration, Ticker, Timer};
+use esp_idf_svc::hal::delay;
+use esp_idf_svc::hal::gpio::{Gpio3, InputOutput, PinDriver};
+use funny_core::battery;
+use funny_core::crash::CrashReport;
+use funny_core::mqtt::{MqttClient, AVAILABILITY_ONLINE};
+use funny_core::wifi::WifiManager;
+use log::{error, warn};
+
+use crate::ha::HaTopics;
+use crate::metrics;
+use crate::{
+    mqtt_publish_availability, mqtt_publish_crash_report, mqtt_publish_reading, BatteryAdc, MyReading,
+    MQTT_CONNECTED,
+};
+
+// dht11 sample interval
+const SAMPLE_INTERVAL_MS: u64 = 10 * 1000;
+// birth message check
+const NETWORK_CHECK_INTERVAL: Duration = Duration::from_secs(1);
+
+static READINGS: Channel<CriticalSectionRawMutex, Reading, 4> = Channel::new();
+
+pub async fn sensor_task(
+    dht11_pin: &mut PinDriver<'_, Gpio3, InputOutput>,
+    mut battery_adc: Option<&mut BatteryAdc<'_>>,
+) -> Result<()> {
+    loop {
+        if let Some(battery_adc) = battery_adc.as_deref_mut() {
+            battery_adc.measure();
+        }
+
+        match dht11::Reading::read(&mut delay::Ets, dht11_pin) {
+            Ok(res) => {
+                metrics::set_environment(res.temperature, res.relative_humidity as u32);
+                READINGS.send(res).await
+            }
+            Err(e) => {
+                error!("Reading DHT11 Data ERROR:{:?}", e);
+                metrics::sensor_error();
+            }
+        }
+
+        Timer::after_millis(SAMPLE_INTERVAL_MS).await;
+    }
+}
+
+pub async fn network_task(
+    wifi: WifiManager,
+    mut client: MqttClient,
+    ha: Option<&HaTopics>,
+    mut crash_report: Option<CrashReport>,
+) -> Result<()> {
+    let mut ticker = Ticker::every(NETWORK_CHECK_INTERVAL);
+    loop {
+        let reading = match select(READINGS.receive(), ticker.next()).await {
+            Either::First(reading) => reading,
+            Either::Second(_) => {
+                // birth message & ha discovery after every (re)connect, a broker restart may
+                // have lost the retained configs
+                if MQTT_CONNECTED.swap(false, Ordering::Relaxed) {
+                    mqtt_publish_availability(&mut client, AVAILABILITY_ONLINE);
+                    if let Some(ha) = ha {
+                        if let Err(e) = ha.publish_discovery(&mut client) {
+                            error!("ha discovery error:{}", e);
+                        }
+                    }
+                    // once, on the first connect after boot
+                    if let Some(report) = crash_report.take() {
+                        mqtt_publish_crash_report(&mut client, &report);
+                    }
+                }
+                continue;
+            }
+        };
+
+        // nothing to send to while offline, wifi reconnects in background
+        if !wifi.state.is_up() {
+            warn!("wifi down, skip publish (reconnects:{})", wifi.state.reconnects());
+            continue;
+        }
+
+        let myres = MyReading {
+            reading,
+            wifi_ssid: wifi.state.network(),
+            wifi_rssi: wifi.state.rssi(),
+            battery: battery::last(),
+        };
+        mqtt_publish_reading(&mut client, ha, &myres)?;
+    }
+}